@@ -0,0 +1,343 @@
+// What the player wants to do, read from the keyboard and any connected gamepads. Systems check
+// these actions instead of particular keys, so the game plays the same with either. The keyboard
+// follows the key bindings in the settings, gamepads use a fixed layout.
+//
+// Menus can be walked through with the arrow keys or d-pad as well as the mouse: the focused
+// button is marked with SelectedOption, and Confirm clicks it.
+use bevy::{
+    input::{
+        gamepad::{
+            Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType, GamepadEvent,
+            GamepadEventType,
+        },
+        InputSystem,
+    },
+    prelude::*,
+    ui::{CalculatedClip, UiSystem},
+    utils::HashSet,
+};
+
+use crate::{button_color, settings, DisabledButton, SelectedOption};
+
+// How far a stick has to be pushed before it counts as a direction.
+const STICK_THRESHOLD: f32 = 0.5;
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Input<Action>>()
+            .init_resource::<ConnectedGamepads>()
+            .init_resource::<Focus>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_actions
+                    .label(ActionSystem::Update)
+                    .after(InputSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                navigate_ui
+                    .label(ActionSystem::Navigate)
+                    .after(ActionSystem::Update)
+                    .after(UiSystem::Focus),
+            );
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub(crate) enum ActionSystem {
+    Update,
+    Navigate, // clicks buttons, so anything watching clicks runs after it
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    // UI and world coordinates both point right and up.
+    fn vec2(self) -> Vec2 {
+        match self {
+            Direction::Up => Vec2::Y,
+            Direction::Down => -Vec2::Y,
+            Direction::Left => -Vec2::X,
+            Direction::Right => Vec2::X,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Action {
+    Move(Direction), // also moves the focus between buttons
+    Sprint,
+    Confirm, // interacts in the overworld, clicks the focused button in menus
+    Cancel,
+    OpenMenu,
+}
+
+impl Action {
+    const ALL: [Action; 8] = [
+        Action::Move(Direction::Up),
+        Action::Move(Direction::Down),
+        Action::Move(Direction::Left),
+        Action::Move(Direction::Right),
+        Action::Sprint,
+        Action::Confirm,
+        Action::Cancel,
+        Action::OpenMenu,
+    ];
+}
+
+// Gamepads are only listed while they're connected, so their buttons and sticks are looked up here.
+#[derive(Default, Deref)]
+struct ConnectedGamepads(HashSet<Gamepad>);
+
+// The button the keyboard or gamepad is on.
+#[derive(Default)]
+struct Focus {
+    entity: Option<Entity>,
+    clicked: Option<Entity>, // let go of on the next frame, like a mouse click
+}
+
+// Everything navigate_ui needs to know about a button. Disabled buttons are included so the focus
+// can be taken off one that gets disabled. It's queried With<Button>, since other nodes can have an
+// Interaction too and on an image the UiColor is a tint that the button colours would grey out.
+type NavigableButton = (
+    Entity,
+    &'static GlobalTransform,
+    &'static Visibility,
+    Option<&'static CalculatedClip>,
+    &'static mut Interaction,
+    &'static mut UiColor,
+    Option<&'static DisabledButton>,
+);
+
+fn update_actions(
+    keyboard_input: Res<Input<KeyCode>>,
+    gamepad_input: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut gamepads: ResMut<ConnectedGamepads>,
+    settings: Res<settings::Settings>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    for GamepadEvent(gamepad, event) in gamepad_events.iter() {
+        match event {
+            GamepadEventType::Connected => {
+                gamepads.0.insert(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                gamepads.0.remove(gamepad);
+            }
+            _ => (),
+        }
+    }
+
+    let button = |button_type| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_input.pressed(GamepadButton(*gamepad, button_type)))
+    };
+    let stick = |axis_type, sign: f32| {
+        gamepads.iter().any(|gamepad| {
+            let value = gamepad_axes.get(GamepadAxis(*gamepad, axis_type));
+            value.unwrap_or(0.) * sign > STICK_THRESHOLD
+        })
+    };
+
+    let keys = &settings.key_bindings;
+    actions.clear();
+    for action in Action::ALL {
+        let held = match action {
+            Action::Move(direction) => {
+                use GamepadAxisType::*;
+                use GamepadButtonType::*;
+                let (key, dpad, axis, sign) = match direction {
+                    Direction::Up => (keys.up, DPadUp, LeftStickY, 1.),
+                    Direction::Down => (keys.down, DPadDown, LeftStickY, -1.),
+                    Direction::Left => (keys.left, DPadLeft, LeftStickX, -1.),
+                    Direction::Right => (keys.right, DPadRight, LeftStickX, 1.),
+                };
+                keyboard_input.pressed(key) || button(dpad) || stick(axis, sign)
+            }
+            Action::Sprint => {
+                keyboard_input.pressed(keys.sprint) || button(GamepadButtonType::West)
+            }
+            Action::Confirm => {
+                keyboard_input.pressed(keys.interact) || button(GamepadButtonType::South)
+            }
+            Action::Cancel => {
+                keyboard_input.pressed(keys.cancel) || button(GamepadButtonType::East)
+            }
+            Action::OpenMenu => {
+                keyboard_input.pressed(keys.menu) || button(GamepadButtonType::Start)
+            }
+        };
+        if held {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+}
+
+// Moves the focus to the next button in the direction pressed, or onto the top left one if nothing
+// has focus yet, and clicks it on Confirm. Only buttons that can be seen and clicked get focus.
+fn navigate_ui(
+    mut commands: Commands,
+    actions: Res<Input<Action>>,
+    rebinding: Res<settings::Rebinding>,
+    mut focus: ResMut<Focus>,
+    mut buttons: Query<NavigableButton, With<Button>>,
+) {
+    if let Some(entity) = focus.clicked.take() {
+        if let Ok((.., mut interaction, _, _)) = buttons.get_mut(entity) {
+            if *interaction == Interaction::Clicked {
+                *interaction = Interaction::None;
+            }
+        }
+    }
+
+    let reachable: Vec<_> = buttons
+        .iter()
+        .filter(|(_, transform, visibility, clip, .., disabled)| {
+            disabled.is_none()
+                && visibility.is_visible
+                && unclipped(transform.translation.truncate(), *clip)
+        })
+        .map(|(entity, transform, ..)| (entity, transform.translation.truncate()))
+        .collect();
+
+    // The focused button went away or was disabled, e.g. because the screen changed.
+    if let Some(entity) = focus.entity {
+        if !reachable.iter().any(|(e, _)| *e == entity) {
+            unfocus(&mut commands, &mut buttons, entity);
+            focus.entity = None;
+        }
+    }
+
+    // The key being bound shouldn't move the focus too.
+    if rebinding.is_some() {
+        return;
+    }
+
+    let pressed = Direction::ALL
+        .into_iter()
+        .find(|direction| actions.just_pressed(Action::Move(*direction)));
+    if let Some(direction) = pressed {
+        let from = focus
+            .entity
+            .and_then(|entity| reachable.iter().find(|(e, _)| *e == entity));
+        let next = match from {
+            Some((_, from)) => next_in_direction(*from, direction, &reachable),
+            None => top_left(&reachable),
+        };
+        if let Some(next) = next {
+            if let Some(entity) = focus.entity {
+                unfocus(&mut commands, &mut buttons, entity);
+            }
+            let (.., interaction, mut color, _) = buttons.get_mut(next).unwrap();
+            *color = button_color(*interaction, true);
+            commands.entity(next).insert(SelectedOption);
+            focus.entity = Some(next);
+        }
+    }
+
+    if actions.just_pressed(Action::Confirm) {
+        if let Some(entity) = focus.entity {
+            let (.., mut interaction, _, _) = buttons.get_mut(entity).unwrap();
+            *interaction = Interaction::Clicked;
+            focus.clicked = Some(entity);
+        }
+    }
+}
+
+// Takes the focus off a button that's still around. Disabled buttons keep their own colour.
+fn unfocus(
+    commands: &mut Commands,
+    buttons: &mut Query<NavigableButton, With<Button>>,
+    entity: Entity,
+) {
+    if let Ok((.., interaction, mut color, disabled)) = buttons.get_mut(entity) {
+        if disabled.is_none() {
+            *color = button_color(*interaction, false);
+        }
+        commands.entity(entity).remove::<SelectedOption>();
+    }
+}
+
+// Whether a point is inside the clipping rectangle of a scrolling list, if there is one.
+fn unclipped(position: Vec2, clip: Option<&CalculatedClip>) -> bool {
+    match clip {
+        Some(clip) => position.cmpge(clip.clip.min).all() && position.cmple(clip.clip.max).all(),
+        None => true,
+    }
+}
+
+fn top_left(buttons: &[(Entity, Vec2)]) -> Option<Entity> {
+    buttons
+        .iter()
+        .max_by(|(_, a), (_, b)| (a.y, -a.x).partial_cmp(&(b.y, -b.x)).unwrap())
+        .map(|(entity, _)| *entity)
+}
+
+// The closest button in the given direction. Ones off to the side count as further away, so the
+// focus sticks to rows and columns when it can.
+fn next_in_direction(
+    from: Vec2,
+    direction: Direction,
+    buttons: &[(Entity, Vec2)],
+) -> Option<Entity> {
+    let forward = direction.vec2();
+    buttons
+        .iter()
+        .filter_map(|(entity, position)| {
+            let offset = *position - from;
+            let ahead = offset.dot(forward);
+            let aside = offset.perp_dot(forward).abs();
+            (ahead > 1.).then_some((*entity, ahead + 2. * aside))
+        })
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(entity, _)| entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //   a b
+    //   c d   e
+    #[test]
+    fn focus_moves_to_the_closest_button_in_line() {
+        let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(Entity::from_raw);
+        let buttons = [
+            (a, Vec2::new(0., 100.)),
+            (b, Vec2::new(100., 100.)),
+            (c, Vec2::new(0., 0.)),
+            (d, Vec2::new(100., 0.)),
+            (e, Vec2::new(300., 40.)),
+        ];
+        let position = |entity| buttons.iter().find(|(b, _)| *b == entity).unwrap().1;
+        let next = |from, direction| next_in_direction(position(from), direction, &buttons);
+
+        assert_eq!(Some(a), top_left(&buttons));
+        assert_eq!(Some(a), next(c, Direction::Up));
+        assert_eq!(Some(d), next(c, Direction::Right));
+        assert_eq!(Some(e), next(d, Direction::Right));
+        assert_eq!(Some(d), next(e, Direction::Left));
+        assert_eq!(Some(c), next(a, Direction::Down));
+        assert_eq!(None, next(a, Direction::Left));
+    }
+}
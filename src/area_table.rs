@@ -0,0 +1,122 @@
+use bevy::{prelude::*, reflect::TypeUuid, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    content::ContentError,
+    global::{Area, Areas, Exit, Formation, Interactable, InteractableKind, MAX_FORMATION_SIZE},
+    tile_map::TileMap,
+    ImageAssets,
+};
+
+// Contents of an `.areas.ron` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "7ec6822d-244a-4853-b0dc-bcad99a64227"]
+pub(crate) struct AreaTableAsset {
+    areas: Vec<AreaEntry>,
+}
+
+#[derive(Deserialize)]
+struct AreaEntry {
+    id: usize,
+    #[serde(default)]
+    formations: Vec<Formation>,
+    background: String, // name of an image in ImageAssets
+    map: Vec<String>,   // rows of tiles, see tile_map
+    #[serde(default)]
+    exits: Vec<Exit>,
+    #[serde(default)]
+    interactables: Vec<InteractableEntry>,
+}
+
+#[derive(Deserialize)]
+struct InteractableEntry {
+    kind: InteractableKind,
+    position: (f32, f32),
+    sprite: String, // name of an image in ImageAssets
+}
+
+impl AreaTableAsset {
+    pub(crate) fn build(&self, image_assets: &ImageAssets) -> Result<Areas, Vec<ContentError>> {
+        let mut areas = HashMap::new();
+        let mut errors = vec![];
+
+        for area in &self.areas {
+            let entry = format!("area {}", area.id);
+            let mut error =
+                |message: String| errors.push(ContentError::new("areas", entry.clone(), message));
+
+            if areas.contains_key(&area.id) {
+                error("duplicate id".to_string());
+            }
+            let background = image_assets.get(&area.background);
+            if background.is_none() {
+                error(format!("unknown background \"{}\"", area.background));
+            }
+
+            for (i, formation) in area.formations.iter().enumerate() {
+                if !(1..=MAX_FORMATION_SIZE).contains(&formation.enemies.len()) {
+                    error(format!(
+                        "formation {} must have 1 to {} enemies",
+                        i, MAX_FORMATION_SIZE
+                    ));
+                }
+                if formation.weight == 0 {
+                    error(format!("formation {} has a weight of 0", i));
+                }
+            }
+
+            let map = match TileMap::parse(&area.map) {
+                Ok(map) => map,
+                Err(message) => {
+                    error(message);
+                    TileMap::default()
+                }
+            };
+            for (i, exit) in area.exits.iter().enumerate() {
+                let tiles = exit.tiles(&map);
+                if tiles.width == 0
+                    || tiles.height == 0
+                    || tiles.x + tiles.width > map.width()
+                    || tiles.y + tiles.height > map.height()
+                {
+                    error(format!("exit {} isn't on the map", i));
+                }
+            }
+
+            let mut interactables = vec![];
+            for interactable in &area.interactables {
+                match image_assets.get(&interactable.sprite) {
+                    Some(sprite) => interactables.push(Interactable {
+                        kind: interactable.kind.clone(),
+                        position: Vec2::new(interactable.position.0, interactable.position.1),
+                        sprite,
+                    }),
+                    None => error(format!(
+                        "{:?} has unknown sprite \"{}\"",
+                        interactable.kind, interactable.sprite
+                    )),
+                }
+            }
+
+            if let Some(background) = background {
+                areas.insert(
+                    area.id,
+                    Area {
+                        id: area.id,
+                        formations: area.formations.clone(),
+                        background,
+                        map,
+                        exits: area.exits.clone(),
+                        interactables,
+                    },
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Areas(areas))
+        } else {
+            Err(errors)
+        }
+    }
+}
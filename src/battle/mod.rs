@@ -1,4 +1,7 @@
-use crate::{button_system, despawn_children, despawn_screen, global, FontAssets};
+use crate::{
+    actions::Action, button_system, combat, despawn_children, despawn_screen, global,
+    replay::RecordClicks, set_visible_recursive, settings, FontAssets,
+};
 
 mod styles;
 use queues::*;
@@ -8,31 +11,46 @@ use bevy::{
     input::mouse::{MouseScrollUnit, MouseWheel},
     prelude::*,
 };
-use rand::prelude::*;
 
 pub struct BattlePlugin;
 
 impl Plugin for BattlePlugin {
     fn build(&self, app: &mut App) {
-        app.add_state(BattleState::Initialization)
+        app.record_clicks::<PlayerButtonAction>()
+            .record_clicks::<global::PlayerAttack>()
+            .record_clicks::<ItemButton>()
+            .record_clicks::<TargetButton>()
+            .add_state(BattleState::Initialization)
             .add_state(ActionMenuState::Inactive)
             .add_state(MagicMenuState::Inactive)
             .add_state(ItemMenuState::Inactive)
             .init_resource::<Announcement>()
             .init_resource::<PlayerBattleAction>()
+            .init_resource::<combat::Timeline>()
+            .add_event::<DamageDealt>()
             .add_system_set(
                 SystemSet::on_enter(global::GameState::Battle).with_system(battle_setup),
             )
             .add_system_set(
-                SystemSet::on_update(global::GameState::Battle).with_system(battle_init),
+                SystemSet::on_update(global::GameState::Battle)
+                    .with_system(battle_init.label(BattleSystem::Init))
+                    .with_system(update_stat_bars)
+                    .with_system(hide_defeated_enemies)
+                    .with_system(update_status_icons)
+                    .with_system(update_turn_order)
+                    .with_system(spawn_damage_numbers)
+                    .with_system(float_damage_numbers),
             )
             .add_system_set(SystemSet::on_enter(BattleState::Idle).with_system(idle_init))
             .add_system_set(
                 SystemSet::on_update(BattleState::Idle)
                     .with_system(button_system)
-                    .with_system(action_menu_button_action)
-                    .with_system(magic_menu_button_action)
+                    .with_system(action_menu_button_action.label(BattleSystem::ChooseAttack))
+                    .with_system(magic_menu_button_action.label(BattleSystem::ChooseAttack))
                     .with_system(item_button_action)
+                    .with_system(aim_attack.after(BattleSystem::ChooseAttack))
+                    .with_system(target_button_action)
+                    .with_system(cancel_player_choice)
                     .with_system(item_list_scroll),
             )
             .add_system_set(
@@ -66,18 +84,34 @@ impl Plugin for BattlePlugin {
                 SystemSet::on_enter(BattleState::PlayerAction).with_system(player_attack_setup),
             )
             .add_system_set(
-                SystemSet::on_update(BattleState::PlayerAction).with_system(battle_update),
+                SystemSet::on_update(BattleState::PlayerAction)
+                    .with_system(battle_update.after(BattleSystem::Init)),
             )
             .add_system_set(
                 SystemSet::on_enter(BattleState::EnemyAction).with_system(enemy_attack_setup),
             )
             .add_system_set(
-                SystemSet::on_update(BattleState::EnemyAction).with_system(battle_update),
+                SystemSet::on_update(BattleState::EnemyAction)
+                    .with_system(battle_update.after(BattleSystem::Init)),
+            )
+            .add_system_set(
+                SystemSet::on_enter(BattleState::PhaseTransition)
+                    .with_system(phase_transition_setup),
+            )
+            .add_system_set(
+                SystemSet::on_update(BattleState::PhaseTransition)
+                    .with_system(battle_update.after(BattleSystem::Init)),
             )
             .add_system_set(SystemSet::on_enter(BattleState::Win).with_system(win_setup))
-            .add_system_set(SystemSet::on_update(BattleState::Win).with_system(battle_update))
+            .add_system_set(
+                SystemSet::on_update(BattleState::Win)
+                    .with_system(battle_update.after(BattleSystem::Init)),
+            )
             .add_system_set(SystemSet::on_enter(BattleState::Lose).with_system(lose_setup))
-            .add_system_set(SystemSet::on_update(BattleState::Lose).with_system(battle_update))
+            .add_system_set(
+                SystemSet::on_update(BattleState::Lose)
+                    .with_system(battle_update.after(BattleSystem::Init)),
+            )
             // When exiting the state, despawn everything that was spawned for this screen.
             .add_system_set(
                 SystemSet::on_exit(global::GameState::Battle)
@@ -89,12 +123,22 @@ impl Plugin for BattlePlugin {
 #[derive(Component)]
 struct BattleScreen;
 
+// Systems that would otherwise run in either order, which a replay can't tell apart from different
+// inputs: battle_init and battle_update both tick the Timer, and an attack is aimed on the frame
+// it's picked from the menus.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+enum BattleSystem {
+    Init,
+    ChooseAttack,
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum BattleState {
     Initialization,
     Idle,
     PlayerAction,
     EnemyAction,
+    PhaseTransition,
     Win,
     Lose,
     Deinitialize,
@@ -103,8 +147,11 @@ enum BattleState {
 #[derive(Default)]
 struct PlayerBattleAction {
     attack: Option<global::PlayerAttack>,
+    target: usize, // slot of the enemy the attack is aimed at
     block: bool,
     item: Option<global::Item>,
+    run: bool,
+    escaped: bool,
 }
 
 #[derive(Component)]
@@ -119,13 +166,14 @@ enum ActionMenuState {
     Inactive,
 }
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Debug, Clone, PartialEq)]
 pub enum PlayerButtonAction {
     Attack,
     LimitBreak,
     Magic,
     Block,
     Item,
+    Run,
 }
 
 #[derive(Component)]
@@ -149,7 +197,7 @@ enum ItemMenuState {
     Inactive,
 }
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct ItemButton(usize); // holds item id
 
 #[derive(Component)]
@@ -160,8 +208,30 @@ struct ItemList {
     position: f32,
 }
 
+// Which enemy of the formation an enemy entity is, counting from the left.
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub(crate) struct EnemySlot(pub(crate) usize);
+
+#[derive(Component, Debug)]
+pub(crate) struct TargetButton(pub(crate) usize); // holds enemy slot
+
+#[derive(Component)]
+struct EnemyContainer(usize); // holds enemy slot
+
 #[derive(Component)]
-struct EnemyHPBar;
+struct EnemyHPBar(usize); // holds enemy slot
+
+#[derive(Component)]
+struct EnemyPortrait(usize); // holds enemy slot
+
+#[derive(Component)]
+struct PlayerPortrait;
+
+#[derive(Component)]
+struct PlayerStatusIcons;
+
+#[derive(Component)]
+struct EnemyStatusIcons(usize); // holds enemy slot
 
 #[derive(Component)]
 struct HealthText;
@@ -178,30 +248,60 @@ struct ManaBar;
 #[derive(Component)]
 struct LimitBar;
 
+#[derive(Component)]
+struct TurnOrderStrip;
+
+// Turns shown in the turn order strip.
+const TURN_ORDER_LENGTH: usize = 6;
+
+// A hit to pop up over the portrait of whoever took it.
+struct DamageDealt {
+    target: combat::Side,
+    damage: i32,
+    hit: combat::Hit,
+}
+
+#[derive(Component)]
+struct DamageNumber(Timer); // despawns the number once it's done floating up
+
 #[derive(Default)]
 struct Announcement {
     entity: Option<Entity>,
     texts: Queue<String>,
 }
 
-const TEXT_DURATION: f32 = 2.;
-
+#[allow(clippy::too_many_arguments)]
 fn battle_setup(
     mut commands: Commands,
     font_assets: Res<FontAssets>,
-    player: Res<global::Player>,
-    enemy: Res<global::Enemy>,
+    mut player: ResMut<global::Player>,
+    encounter: Res<global::Encounter>,
+    enemy_table: Res<global::EnemyTable>,
     mut battle_state: ResMut<State<BattleState>>,
     mut announcement: ResMut<Announcement>,
+    mut timeline: ResMut<combat::Timeline>,
+    settings: Res<settings::Settings>,
 ) {
+    player.status_effects.clear();
+
+    let enemies: Vec<_> = encounter
+        .enemies
+        .iter()
+        .map(|id| global::Enemy::from_table(&enemy_table, *id))
+        .collect();
+    let enemy_speeds: Vec<_> = enemies.iter().map(|enemy| enemy.stats.speed).collect();
+    *timeline = combat::Timeline::new(player.stats.speed, &enemy_speeds);
+
     let hp_perc = player.stats.hp as f32 / player.stats.hp_max as f32 * 100.;
     let mp_perc = player.stats.mp as f32 / player.stats.mp_max as f32 * 100.;
 
-    let enemy_sprite = enemy.stats.battle_sprite.clone();
-    let enemy_name = enemy.enemy_stats.name.clone();
+    let enemy_names: Vec<_> = enemies
+        .iter()
+        .map(|enemy| enemy.enemy_stats.name.clone())
+        .collect();
 
     // This will set BattleState::Initialization to BattleState::Idle in 1 second.
-    commands.insert_resource(Timer::from_seconds(TEXT_DURATION, false));
+    commands.insert_resource(Timer::from_seconds(settings.text_duration(), false));
     // Repeating battles will start on BattleState::Deinitialize... Reset it.
     if *battle_state.current() == BattleState::Deinitialize {
         battle_state.set(BattleState::Initialization).unwrap();
@@ -228,6 +328,8 @@ fn battle_setup(
                                             p.spawn_bundle(styled_player_hp_bar(hp_perc))
                                                 .insert(HealthBar);
                                         });
+                                    p.spawn_bundle(styled_status_icons_container())
+                                        .insert(PlayerStatusIcons);
                                 });
 
                             p.spawn_bundle(styled_player_stats_child_container())
@@ -277,21 +379,52 @@ fn battle_setup(
             p.spawn_bundle(styled_announcement_container())
                 .with_children(|p| {
                     announcement.entity = Some(
-                        p.spawn_bundle(styled_announcement_text(&font_assets, enemy_name))
+                        p.spawn_bundle(styled_announcement_text(&font_assets, &enemy_names))
                             .id(),
                     );
                 });
 
             p.spawn_bundle(styled_battle_images_container())
                 .with_children(|p| {
-                    p.spawn_bundle(styled_battle_portrait(player.stats.battle_sprite.clone()));
-                    p.spawn_bundle(styled_enemy_portrait_container())
+                    p.spawn_bundle(styled_turn_order_strip())
+                        .insert(TurnOrderStrip);
+                    p.spawn_bundle(styled_battle_portrait(player.stats.battle_sprite.clone()))
+                        .insert(PlayerPortrait);
+                    p.spawn_bundle(styled_enemy_formation_container())
                         .with_children(|p| {
-                            p.spawn_bundle(styled_battle_portrait(enemy_sprite));
-                            p.spawn_bundle(styled_enemy_hp_bar()).insert(EnemyHPBar);
+                            let count = enemies.len();
+                            for (slot, enemy) in enemies.iter().enumerate() {
+                                p.spawn_bundle(styled_enemy_portrait_container(count))
+                                    .insert(EnemyContainer(slot))
+                                    .with_children(|p| {
+                                        p.spawn_bundle(styled_target_button())
+                                            .insert(TargetButton(slot))
+                                            .with_children(|p| {
+                                                p.spawn_bundle(styled_enemy_portrait(
+                                                    enemy.stats.battle_sprite.clone(),
+                                                    count,
+                                                ))
+                                                .insert(EnemyPortrait(slot));
+                                            });
+                                        p.spawn_bundle(styled_enemy_hp_bar_container(count))
+                                            .with_children(|p| {
+                                                p.spawn_bundle(styled_enemy_hp_bar())
+                                                    .insert(EnemyHPBar(slot));
+                                            });
+                                        p.spawn_bundle(styled_status_icons_container())
+                                            .insert(EnemyStatusIcons(slot));
+                                    });
+                            }
                         });
                 });
         });
+
+    for (slot, enemy) in enemies.into_iter().enumerate() {
+        commands
+            .spawn_bundle(enemy)
+            .insert(EnemySlot(slot))
+            .insert(BattleScreen);
+    }
 }
 
 fn spawn_action_menu(
@@ -310,6 +443,7 @@ fn spawn_action_menu(
             PlayerButtonAction::Magic,
             PlayerButtonAction::Block,
             PlayerButtonAction::Item,
+            PlayerButtonAction::Run,
         ] {
             p.spawn_bundle(styled_player_action_button())
                 .insert(player_button_action.clone())
@@ -396,17 +530,31 @@ fn deactivate_player_menus(
 }
 
 fn battle_init(
-    time: Res<Time>,
+    time: Res<global::FrameTime>,
     mut timer: ResMut<Timer>,
     mut battle_state: ResMut<State<BattleState>>,
+    timeline: Res<combat::Timeline>,
 ) {
     if timer.tick(time.delta()).finished() {
         if let BattleState::Initialization = battle_state.as_ref().current() {
-            battle_state.set(BattleState::Idle).unwrap()
+            start_next_turn(&mut battle_state, &timeline);
         }
     }
 }
 
+// Moves on to whoever's turn it is on the timeline. Anyone can get several turns in a row.
+fn start_next_turn(battle_state: &mut State<BattleState>, timeline: &combat::Timeline) {
+    let next = match timeline.current() {
+        combat::Side::Player => BattleState::Idle,
+        combat::Side::Enemy(_) => BattleState::EnemyAction,
+    };
+    if *battle_state.current() == next {
+        battle_state.restart().unwrap();
+    } else {
+        battle_state.set(next).unwrap();
+    }
+}
+
 fn idle_init(
     font_assets: Res<FontAssets>,
     announcement: Res<Announcement>,
@@ -425,35 +573,43 @@ fn idle_init(
     player_battle_action.attack = None;
     player_battle_action.block = false;
     player_battle_action.item = None;
+    player_battle_action.run = false;
+    player_battle_action.escaped = false;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn action_menu_button_action(
+    mut commands: Commands,
+    children_query: Query<&Children>,
     interaction_query: Query<
         (&Interaction, &PlayerButtonAction),
         (Changed<Interaction>, With<Button>),
     >,
+    desc_container: Query<Entity, With<SubSubActionMenuDescContainer>>,
     mut battle_state: ResMut<State<BattleState>>,
     mut magic_menu_state: ResMut<State<MagicMenuState>>,
     mut item_menu_state: ResMut<State<ItemMenuState>>,
     mut player_battle_action: ResMut<PlayerBattleAction>,
     player_limit: Res<global::PlayerLimitEquipped>,
     player_attack_table: Res<global::PlayerAttackTable>,
+    enemies: Query<&global::EnemyStats>,
+    font_assets: Res<FontAssets>,
 ) {
     for (interaction, menu_button_action) in interaction_query.iter() {
         if *interaction == Interaction::Clicked {
             match menu_button_action {
+                // Attacks wait for aim_attack to pick their target.
                 PlayerButtonAction::Attack => {
-                    battle_state.set(BattleState::PlayerAction).unwrap();
                     player_battle_action.attack =
                         Some(player_attack_table.table.get(&0).unwrap().clone())
                 }
                 PlayerButtonAction::LimitBreak => {
-                    battle_state.set(BattleState::PlayerAction).unwrap();
                     player_battle_action.attack = Some(player_limit.clone());
                 }
                 PlayerButtonAction::Block => {
                     // TODO: block & use item
                     battle_state.set(BattleState::PlayerAction).unwrap();
+                    player_battle_action.attack = None;
                     player_battle_action.block = true;
                 }
                 PlayerButtonAction::Magic => {
@@ -478,6 +634,27 @@ fn action_menu_button_action(
                         magic_menu_state.set(MagicMenuState::Inactive).unwrap();
                     }
                 }
+                PlayerButtonAction::Run => {
+                    if enemies.iter().any(|enemy_stats| enemy_stats.unescapable) {
+                        // Trying doesn't use up the turn.
+                        let desc_container = desc_container.single();
+                        if let Ok(children) = children_query.get(desc_container) {
+                            for child in children.iter() {
+                                commands.entity(*child).despawn_recursive();
+                            }
+                        }
+                        commands.entity(desc_container).with_children(|p| {
+                            p.spawn_bundle(styled_text_bundle(
+                                "You can't run from this battle!",
+                                &font_assets,
+                            ));
+                        });
+                    } else {
+                        battle_state.set(BattleState::PlayerAction).unwrap();
+                        player_battle_action.attack = None;
+                        player_battle_action.run = true;
+                    }
+                }
             }
         }
     }
@@ -491,7 +668,6 @@ fn magic_menu_button_action(
         (Changed<Interaction>, With<Button>),
     >,
     mut desc_container: Query<Entity, With<SubSubActionMenuDescContainer>>,
-    mut battle_state: ResMut<State<BattleState>>,
     mut player_battle_action: ResMut<PlayerBattleAction>,
     player: Res<global::Player>,
     font_assets: Res<FontAssets>,
@@ -504,8 +680,13 @@ fn magic_menu_button_action(
             }
         }
         if *interaction == Interaction::Clicked {
-            if player.stats.mp >= menu_button_action.mp_use {
-                battle_state.set(BattleState::PlayerAction).unwrap();
+            if player.status_effects.has(global::StatusEffectKind::Silence) {
+                commands
+                    .entity(desc_container.single_mut())
+                    .with_children(|p| {
+                        p.spawn_bundle(styled_text_bundle("You are silenced!", &font_assets));
+                    });
+            } else if player.stats.mp >= menu_button_action.mp_use {
                 player_battle_action.attack = Some(menu_button_action.clone());
             } else {
                 commands
@@ -546,6 +727,7 @@ fn item_button_action(
     for (interaction, menu_button_action) in interaction_query.iter() {
         let this_item = item_table.get(&menu_button_action.0).unwrap().clone();
         if *interaction == Interaction::Clicked {
+            player_battle_action.attack = None;
             player_battle_action.item = Some(this_item);
             battle_state.set(BattleState::PlayerAction).unwrap();
         } else if *interaction == Interaction::Hovered {
@@ -574,199 +756,292 @@ fn item_button_action(
     }
 }
 
-fn player_attack_setup(
-    mut set: ParamSet<(
-        Query<&mut Text, With<ManaText>>,
-        Query<&mut Style, With<ManaBar>>,
-        Query<(&mut Style, &mut UiColor), With<LimitBar>>,
-        Query<&mut Style, With<EnemyHPBar>>,
-        Query<&mut Text, With<HealthText>>,
-        Query<&mut Style, With<HealthBar>>,
-    )>,
-    mut announcement: ResMut<Announcement>,
-    mut player: ResMut<global::Player>,
-    mut enemy: ResMut<global::Enemy>,
-    player_action: Res<PlayerBattleAction>,
-    mut item_inventory: ResMut<global::PlayerItemInventory>,
+// Aims a chosen attack at the only enemy left standing, or asks the player which one to hit.
+fn aim_attack(
+    font_assets: Res<FontAssets>,
+    announcement: Res<Announcement>,
+    mut announcement_text: Query<&mut Text>,
+    enemies: Query<(&EnemySlot, &global::Stats)>,
+    mut battle_state: ResMut<State<BattleState>>,
+    mut player_battle_action: ResMut<PlayerBattleAction>,
 ) {
-    if let Some(attack) = &player_action.attack {
-        let damage = calculate_player_attack_damage(&attack, &player, &enemy);
+    if !player_battle_action.is_changed() || player_battle_action.attack.is_none() {
+        return;
+    }
 
-        enemy.stats.hp = std::cmp::min(
-            std::cmp::max(0, enemy.stats.hp - damage),
-            enemy.stats.hp_max,
+    let living: Vec<_> = enemies
+        .iter()
+        .filter(|(_, stats)| stats.hp > 0)
+        .map(|(slot, _)| slot.0)
+        .collect();
+    if let [target] = living[..] {
+        player_battle_action.target = target;
+        battle_state.set(BattleState::PlayerAction).unwrap();
+    } else {
+        *announcement_text
+            .get_mut(announcement.entity.unwrap())
+            .unwrap() = Text::with_section(
+            "Choose a target.",
+            common_text_style(&font_assets),
+            Default::default(),
         );
+    }
+}
 
-        if let Some(global::PlayerAttackType::Limit) = &attack.attack_type {
-            player.limit = 0;
-            for (mut limit_bar, mut color) in set.p2().iter_mut() {
-                limit_bar.size.width = Val::Percent(0.);
-                *color = Color::ORANGE.into();
-            }
-        }
-
-        player.stats.mp -= attack.mp_use;
-
-        let announcement_text = if damage >= 0 {
-            format!("You used {}, dealing {} damage.", attack.name, damage)
-        } else {
-            format!(
-                "You used {}, healing {} to the enemy!",
-                attack.name, -damage
-            )
-        };
+// Backs out of choosing a target, or closes the magic or item list.
+fn cancel_player_choice(
+    actions: Res<Input<Action>>,
+    font_assets: Res<FontAssets>,
+    announcement: Res<Announcement>,
+    mut announcement_text: Query<&mut Text>,
+    mut magic_menu_state: ResMut<State<MagicMenuState>>,
+    mut item_menu_state: ResMut<State<ItemMenuState>>,
+    mut player_battle_action: ResMut<PlayerBattleAction>,
+) {
+    if !actions.just_pressed(Action::Cancel) {
+        return;
+    }
 
-        let _ = announcement.texts.add(announcement_text);
+    if player_battle_action.attack.is_some() {
+        player_battle_action.attack = None;
+        *announcement_text
+            .get_mut(announcement.entity.unwrap())
+            .unwrap() = Text::with_section("", common_text_style(&font_assets), Default::default());
+    } else if *magic_menu_state.current() == MagicMenuState::Active {
+        magic_menu_state.set(MagicMenuState::Inactive).unwrap();
+    } else if *item_menu_state.current() == ItemMenuState::Active {
+        item_menu_state.set(ItemMenuState::Inactive).unwrap();
+    }
+}
 
-        let enemy_hp_perc = enemy.stats.hp as f32 / enemy.stats.hp_max as f32 * 256.;
-        for mut enemy_hp_bar in set.p3().iter_mut() {
-            enemy_hp_bar.size.width = Val::Px(enemy_hp_perc);
+fn target_button_action(
+    interaction_query: Query<(&Interaction, &TargetButton), Changed<Interaction>>,
+    enemies: Query<(&EnemySlot, &global::Stats)>,
+    mut battle_state: ResMut<State<BattleState>>,
+    mut player_battle_action: ResMut<PlayerBattleAction>,
+) {
+    for (interaction, target) in interaction_query.iter() {
+        let living = enemies
+            .iter()
+            .any(|(slot, stats)| slot.0 == target.0 && stats.hp > 0);
+        if *interaction == Interaction::Clicked && living && player_battle_action.attack.is_some() {
+            player_battle_action.target = target.0;
+            battle_state.set(BattleState::PlayerAction).unwrap();
         }
     }
+}
 
-    if player_action.block {
-        let _ = announcement
-            .texts
-            .add("You blocked their next attack.".to_string());
+#[allow(clippy::too_many_arguments)]
+fn player_attack_setup(
+    mut announcement: ResMut<Announcement>,
+    mut player: ResMut<global::Player>,
+    mut enemies: Query<(&EnemySlot, global::EnemyComponents)>,
+    mut player_action: ResMut<PlayerBattleAction>,
+    mut item_inventory: ResMut<global::PlayerItemInventory>,
+    mut rng: ResMut<global::GameRng>,
+    mut timeline: ResMut<combat::Timeline>,
+    mut damage_dealt: EventWriter<DamageDealt>,
+) {
+    timeline.advance();
+    let action = match (&player_action.attack, &player_action.item) {
+        (Some(attack), _) => combat::PlayerAction::Attack(attack.clone()),
+        (None, Some(item)) => combat::PlayerAction::UseItem(item.clone()),
+        (None, None) if player_action.run => combat::PlayerAction::Run,
+        (None, None) => combat::PlayerAction::Block,
+    };
+
+    let mut living: Vec<_> = enemies
+        .iter_mut()
+        .filter(|(_, (stats, ..))| stats.hp > 0)
+        .collect();
+    let escapable = !living
+        .iter()
+        .any(|(_, (_, enemy_stats, ..))| enemy_stats.unescapable);
+    for (_, (.., memory)) in living.iter_mut() {
+        memory.weakness_hit = false;
     }
 
-    if let Some(item) = player_action.item.clone() {
-        let mut item_announce_text = format!("You used {}, healing", item.name);
-        if item.stats.hp > 0 {
-            player.stats.hp = std::cmp::min(player.stats.hp + item.stats.hp, player.stats.hp_max);
-            item_announce_text.push_str(&format!(" {} HP", item.stats.hp)[..]);
-        }
-        if item.stats.mp > 0 {
-            player.stats.mp = std::cmp::min(player.stats.mp + item.stats.mp, player.stats.mp_max);
-            item_announce_text.push_str(&format!(" {} MP", item.stats.mp)[..]);
+    // Attacks hit their target and running is up against the strongest enemy. Anything else just
+    // needs an enemy to be up against.
+    let acted_against = match &action {
+        combat::PlayerAction::Attack(_) => living
+            .iter()
+            .position(|(slot, _)| slot.0 == player_action.target),
+        combat::PlayerAction::Run => living
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (_, (stats, ..)))| stats.level)
+            .map(|(i, _)| i),
+        _ => None,
+    };
+    let (slot, enemy) = living.swap_remove(acted_against.unwrap_or(0));
+
+    let events = global::Enemy::lend(enemy, |enemy| {
+        let mut state = combat::BattleState::new(&player, enemy, false);
+        state.escapable = escapable;
+        let events = combat::apply_player_action(&mut state, &action, &mut rng.ai);
+        state.write_back(&mut player, enemy);
+        player_action.block = state.blocking;
+        retire_if_defeated(&mut timeline, slot.0, enemy);
+        events
+    });
+    player_action.escaped = events.contains(&combat::BattleEvent::Escaped);
+
+    // Decrement item and remove if reach 0. Nothing is used up if the player couldn't move.
+    if let combat::PlayerAction::UseItem(item) = &action {
+        if events
+            .iter()
+            .any(|e| matches!(e, combat::BattleEvent::ItemUsed { .. }))
+        {
+            *item_inventory.get_mut(&item.id).unwrap() -= 1;
+            if *item_inventory.get_mut(&item.id).unwrap() == 0 {
+                item_inventory.remove(&item.id);
+            }
         }
+    }
 
-        // Decrement item and remove if reach 0.
-        *item_inventory.get_mut(&item.id).unwrap() -= 1;
-        if *item_inventory.get_mut(&item.id).unwrap() == 0 {
-            item_inventory.remove(&item.id);
-        }
+    show_damage(&mut damage_dealt, &events, combat::Side::Enemy(slot.0));
+    announce(&mut announcement, events);
+}
 
-        let _ = announcement.texts.add(item_announce_text);
-    }
+fn enemy_attack_setup(
+    mut announcement: ResMut<Announcement>,
+    mut player: ResMut<global::Player>,
+    mut enemies: Query<(&EnemySlot, global::EnemyComponents)>,
+    mut player_action: ResMut<PlayerBattleAction>,
+    mut rng: ResMut<global::GameRng>,
+    mut timeline: ResMut<combat::Timeline>,
+    mut damage_dealt: EventWriter<DamageDealt>,
+) {
+    let side = timeline.advance();
+    let (slot, enemy) = match enemies
+        .iter_mut()
+        .find(|(slot, _)| combat::Side::Enemy(slot.0) == side)
+    {
+        Some(enemy) => enemy,
+        None => return,
+    };
 
-    for mut health_text in set.p4().iter_mut() {
-        health_text.sections[1].value = format!("{} / {}", player.stats.hp, player.stats.hp_max);
-    }
+    let (events, blocking) = global::Enemy::lend(enemy, |enemy| {
+        let mut state = combat::BattleState::new(&player, enemy, player_action.block);
+        let events = combat::apply_enemy_turn(
+            &mut state,
+            &enemy.enemy_stats.ai,
+            &enemy.attacks,
+            &mut rng.ai,
+        );
+        state.write_back(&mut player, enemy);
+        // Burns and poison can finish an enemy off on its own turn.
+        retire_if_defeated(&mut timeline, slot.0, enemy);
+        (events, state.blocking)
+    });
+    // The block is used up, even if the enemy gets another turn straight away.
+    player_action.block = blocking;
 
-    let player_hp_perc = player.stats.hp as f32 / player.stats.hp_max as f32 * 100.;
-    for mut health_bar in set.p5().iter_mut() {
-        health_bar.size.width = Val::Percent(player_hp_perc);
-    }
+    show_damage(&mut damage_dealt, &events, combat::Side::Player);
+    announce(&mut announcement, events);
+}
 
-    for mut mp_text in set.p0().iter_mut() {
-        mp_text.sections[1].value = format!("{} / {}", player.stats.mp, player.stats.mp_max);
+// Takes an enemy out of the turn order once it's down for good.
+fn retire_if_defeated(timeline: &mut combat::Timeline, slot: usize, enemy: &global::Enemy) {
+    if enemy.stats.hp <= 0 && enemy.enemy_stats.next_phase.is_none() {
+        timeline.remove(combat::Side::Enemy(slot));
     }
+}
 
-    let player_mp_perc = player.stats.mp as f32 / player.stats.mp_max as f32 * 100.;
-    for mut mp_bar in set.p1().iter_mut() {
-        mp_bar.size.width = Val::Percent(player_mp_perc);
+// Pops up a number for each attack that was made against the target this turn.
+fn show_damage(
+    damage_dealt: &mut EventWriter<DamageDealt>,
+    events: &[combat::BattleEvent],
+    target: combat::Side,
+) {
+    for event in events {
+        if let combat::BattleEvent::PlayerAttacked { damage, hit, .. }
+        | combat::BattleEvent::EnemyAttacked { damage, hit, .. } = event
+        {
+            damage_dealt.send(DamageDealt {
+                target,
+                damage: *damage,
+                hit: *hit,
+            });
+        }
     }
 }
 
-fn calculate_player_attack_damage(
-    attack: &global::PlayerAttack,
-    player: &global::Player,
-    enemy: &global::Enemy,
-) -> i32 {
-    // Get attack power.
-    let mut power = if let Some(global::PlayerAttackType::Limit) = attack.attack_type {
-        match attack.tier {
-            3 => 120. + 2.5 * player.stats.strength as f32,
-            2 => 80. + 2.0 * player.stats.strength as f32,
-            1 | _ => 40. + 1.5 * player.stats.strength as f32,
-        }
-    } else if let Some(global::PlayerAttackType::Magic) = attack.attack_type {
-        match attack.tier {
-            3 => 45. + 1.2 * player.stats.wisdom as f32,
-            2 => 15. + 1.0 * player.stats.wisdom as f32,
-            1 | _ => 5. + 0.8 * player.stats.wisdom as f32,
+fn spawn_damage_numbers(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    settings: Res<settings::Settings>,
+    mut damage_dealt: EventReader<DamageDealt>,
+    player_portrait: Query<Entity, With<PlayerPortrait>>,
+    enemy_portraits: Query<(Entity, &EnemyPortrait)>,
+) {
+    for event in damage_dealt.iter() {
+        let portrait = match event.target {
+            combat::Side::Player => player_portrait.get_single().ok(),
+            combat::Side::Enemy(slot) => enemy_portraits
+                .iter()
+                .find(|(_, portrait)| portrait.0 == slot)
+                .map(|(entity, _)| entity),
+        };
+        if let Some(portrait) = portrait {
+            commands.entity(portrait).with_children(|p| {
+                p.spawn_bundle(styled_damage_number(&font_assets, event.damage, event.hit))
+                    .insert(DamageNumber(Timer::from_seconds(
+                        settings.text_duration(),
+                        false,
+                    )));
+            });
         }
-    } else {
-        // Normal attack
-        1.5 * player.stats.strength as f32
-    };
-
-    // Apply elemental modifier.
-    power *= element_modifier(&attack.element, &enemy.enemy_stats.element);
-
-    // Get damage reduction.
-    let mut damage_reduction =
-        enemy.stats.defense as f32 + enemy.stats.defense as f32 / 300. * power;
-    if let Some(global::PlayerAttackType::Magic) = attack.attack_type {
-        damage_reduction *= 0.2;
     }
-
-    (power - damage_reduction).round() as i32
 }
 
-fn element_modifier(attack: &Option<global::Element>, receiver: &Option<global::Element>) -> f32 {
-    if attack.is_none() || receiver.is_none() {
-        return 1.0;
+fn float_damage_numbers(
+    mut commands: Commands,
+    time: Res<global::FrameTime>,
+    mut numbers: Query<(Entity, &mut DamageNumber, &mut Style)>,
+) {
+    for (entity, mut number, mut style) in numbers.iter_mut() {
+        number.0.tick(time.delta());
+        style.position.top = damage_number_top(number.0.percent());
+        if number.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
     }
+}
 
-    let (attack, receiver) = (attack.clone().unwrap(), receiver.clone().unwrap());
-    use global::Element::*;
-    match (&attack, &receiver) {
-        (Water, Fire)
-        | (Fire, Earth)
-        | (Earth, Electric)
-        | (Electric, Water)
-        | (Dark, Light)
-        | (Light, Dark) => 2.0,
-        (Fire, Water) | (Earth, Fire) | (Electric, Earth) | (Water, Electric) => 0.5,
-        _ if &attack == &receiver => -1.0,
-        _ => 1.0,
+fn announce(announcement: &mut Announcement, events: Vec<combat::BattleEvent>) {
+    for event in events {
+        let _ = announcement.texts.add(event.to_string());
     }
 }
 
-fn enemy_attack_setup(
+// Keeps the HP, MP and limit bars in line with the player and enemies.
+fn update_stat_bars(
     mut set: ParamSet<(
         Query<&mut Text, With<HealthText>>,
         Query<&mut Style, With<HealthBar>>,
+        Query<&mut Text, With<ManaText>>,
+        Query<&mut Style, With<ManaBar>>,
         Query<(&mut Style, &mut UiColor), With<LimitBar>>,
+        Query<(&mut Style, &EnemyHPBar)>,
     )>,
-    mut announcement: ResMut<Announcement>,
-    mut player: ResMut<global::Player>,
-    mut enemy: ResMut<global::Enemy>,
-    player_action: Res<PlayerBattleAction>,
+    player: Res<global::Player>,
+    enemies: Query<(&EnemySlot, &global::Stats), Changed<global::Stats>>,
 ) {
-    // Pick attack as long as there is mp available.
-    let mut rng = thread_rng();
-    let mut attack_index = rng.gen_range(0..enemy.attacks.len());
-    while enemy.attacks[attack_index].mp_use > enemy.stats.mp {
-        attack_index = rng.gen_range(0..enemy.attacks.len());
+    for (slot, stats) in enemies.iter() {
+        let enemy_hp_perc = stats.hp.max(0) as f32 / stats.hp_max as f32 * 100.;
+        for (mut enemy_hp_bar, bar) in set.p5().iter_mut() {
+            if bar.0 == slot.0 {
+                enemy_hp_bar.size.width = Val::Percent(enemy_hp_perc);
+            }
+        }
     }
-    let attack = enemy.attacks[attack_index].clone();
-
-    let damage = calculate_enemy_attack_damage(&attack, &enemy, &player, player_action.block);
-
-    let limit_addition = (200. * (damage as f32) / (player.stats.hp_max as f32)) as f32;
-    let new_limit = player.limit as f32 + limit_addition;
-    player.limit = if new_limit < 100. {
-        new_limit as u8
-    } else {
-        100
-    };
 
-    player.stats.hp = std::cmp::min(
-        std::cmp::max(0, player.stats.hp - damage),
-        player.stats.hp_max,
-    );
-
-    enemy.stats.mp -= attack.mp_use;
-
-    let _ = announcement.texts.add(format!(
-        "{} used {}, dealing {} damage to you!",
-        enemy.enemy_stats.name, attack.name, damage
-    ));
+    if !player.is_changed() {
+        return;
+    }
 
-    // TODO: maybe put these into another system with Changed<> query filter...
     for mut health_text in set.p0().iter_mut() {
         health_text.sections[1].value = format!("{} / {}", player.stats.hp, player.stats.hp_max);
     }
@@ -776,43 +1051,128 @@ fn enemy_attack_setup(
         health_bar.size.width = Val::Percent(player_hp_perc);
     }
 
-    for (mut limit, mut color) in set.p2().iter_mut() {
+    for mut mp_text in set.p2().iter_mut() {
+        mp_text.sections[1].value = format!("{} / {}", player.stats.mp, player.stats.mp_max);
+    }
+
+    let player_mp_perc = player.stats.mp as f32 / player.stats.mp_max as f32 * 100.;
+    for mut mp_bar in set.p3().iter_mut() {
+        mp_bar.size.width = Val::Percent(player_mp_perc);
+    }
+
+    for (mut limit, mut color) in set.p4().iter_mut() {
         limit.size.width = Val::Percent(player.limit as f32);
-        if player.limit == 100 {
-            *color = Color::RED.into();
+        *color = if player.limit == 100 {
+            Color::RED.into()
+        } else {
+            Color::ORANGE.into()
+        };
+    }
+}
+
+// Hides an enemy's portrait, HP bar and status icons once it's down for good, leaving a gap in the
+// formation.
+fn hide_defeated_enemies(
+    enemies: Query<(&EnemySlot, &global::Stats, &global::EnemyStats), Changed<global::Stats>>,
+    containers: Query<(Entity, &EnemyContainer)>,
+    mut visibility_query: Query<&mut Visibility>,
+    children_query: Query<&Children>,
+) {
+    for (slot, stats, enemy_stats) in enemies.iter() {
+        let defeated = stats.hp <= 0 && enemy_stats.next_phase.is_none();
+        for (container, _) in containers.iter().filter(|(_, c)| c.0 == slot.0) {
+            set_visible_recursive(!defeated, container, &mut visibility_query, &children_query);
         }
     }
 }
 
-fn calculate_enemy_attack_damage(
-    attack: &global::EnemyAttack,
-    enemy: &global::Enemy,
-    player: &global::Player,
-    player_block: bool,
-) -> i32 {
-    // Get attack power.
-    let power = attack.damage_modifier
-        * if let Some(global::EnemyAttackType::Magic) = attack.attack_type {
-            enemy.stats.wisdom as f32
-        } else if let Some(global::EnemyAttackType::Percentile) = attack.attack_type {
-            player.stats.hp_max as f32
-        } else {
-            // Physical attack.
-            enemy.stats.strength as f32
-        };
+// Rebuilds the status effect icons next to the HP bars whenever anyone's effects may have changed.
+// Defeated enemies show none.
+fn update_status_icons(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    children_query: Query<&Children>,
+    player_icons: Query<Entity, With<PlayerStatusIcons>>,
+    enemy_icons: Query<(Entity, &EnemyStatusIcons)>,
+    player: Res<global::Player>,
+    enemies: Query<
+        (&EnemySlot, &global::Stats, &global::StatusEffects),
+        Changed<global::StatusEffects>,
+    >,
+) {
+    let no_effects = global::StatusEffects::default();
+    let mut containers = vec![];
+    if player.is_changed() {
+        containers.extend(player_icons.iter().map(|e| (e, &player.status_effects)));
+    }
+    for (slot, stats, effects) in enemies.iter() {
+        let effects = if stats.hp > 0 { effects } else { &no_effects };
+        containers.extend(
+            enemy_icons
+                .iter()
+                .filter(|(_, icons)| icons.0 == slot.0)
+                .map(|(e, _)| (e, effects)),
+        );
+    }
 
-    // Get damage reduction.
-    let mut damage_reduction =
-        player.stats.defense as f32 + player.stats.defense as f32 / 300. * power;
-    if let Some(global::EnemyAttackType::Magic) = attack.attack_type {
-        damage_reduction *= 0.2;
+    for (container, effects) in containers {
+        if let Ok(children) = children_query.get(container) {
+            for child in children.iter() {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        commands.entity(container).with_children(|p| {
+            for effect in effects.iter() {
+                p.spawn_bundle(styled_status_icon(effect.kind))
+                    .with_children(|p| {
+                        p.spawn_bundle(styled_status_icon_text(
+                            &font_assets,
+                            effect.kind,
+                            effect.turns_left,
+                        ));
+                    });
+            }
+        });
     }
-    if player_block {
-        damage_reduction *= 2.;
+}
+
+// Shows who acts next, rebuilt whenever the timeline moves on.
+fn update_turn_order(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    strip: Query<Entity, With<TurnOrderStrip>>,
+    new_strip: Query<(), Added<TurnOrderStrip>>,
+    children_query: Query<&Children>,
+    timeline: Res<combat::Timeline>,
+    enemies: Query<(&EnemySlot, &global::EnemyStats)>,
+) {
+    if !timeline.is_changed() && new_strip.is_empty() {
+        return;
     }
 
-    // enemy damage cannot go below 0
-    std::cmp::max(0, (power - damage_reduction).round() as i32)
+    for strip in strip.iter() {
+        if let Ok(children) = children_query.get(strip) {
+            for child in children.iter() {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        commands.entity(strip).with_children(|p| {
+            p.spawn_bundle(styled_turn_order_text(&font_assets, "Next:"));
+            for side in timeline.upcoming(TURN_ORDER_LENGTH) {
+                let label = match side {
+                    combat::Side::Player => "You",
+                    combat::Side::Enemy(slot) => enemies
+                        .iter()
+                        .find(|(s, _)| s.0 == slot)
+                        .map_or("", |(_, enemy_stats)| enemy_stats.name.as_str()),
+                };
+                p.spawn_bundle(styled_turn_order_icon(side))
+                    .with_children(|p| {
+                        p.spawn_bundle(styled_turn_order_text(&font_assets, label));
+                    });
+            }
+        });
+    }
 }
 
 fn win_setup(
@@ -820,97 +1180,129 @@ fn win_setup(
     mut player: ResMut<global::Player>,
     mut player_item_inv: ResMut<global::PlayerItemInventory>,
     mut player_attack_inv: ResMut<global::PlayerAttackInventory>,
+    mut run_stats: ResMut<global::RunStats>,
     item_table: Res<global::ItemTable>,
-    enemy: Res<global::Enemy>,
+    enemies: Query<(
+        &EnemySlot,
+        &global::Stats,
+        &global::EnemyStats,
+        &global::EnemyLoot,
+    )>,
     attack_table: Res<global::PlayerAttackTable>,
+    mut rng: ResMut<global::GameRng>,
 ) {
-    let mut player = &mut player.stats;
-    let enemy_name = enemy.enemy_stats.name.clone();
+    run_stats.battles_won += 1;
+
+    let mut enemies: Vec<_> = enemies.iter().collect();
+    enemies.sort_by_key(|(slot, ..)| slot.0);
+    let enemies: Vec<_> = enemies
+        .into_iter()
+        .map(|(_, stats, enemy_stats, loot_table)| global::Enemy {
+            stats: stats.clone(),
+            enemy_stats: enemy_stats.clone(),
+            loot_table: loot_table.clone(),
+            ..default()
+        })
+        .collect();
+    let victory = combat::resolve_victory(&mut player.stats, &enemies, &item_table, &mut rng.loot);
+
+    for attack_id in victory.unlocked_attacks {
+        player_attack_inv.insert(attack_table.table.get(&attack_id).unwrap().clone());
+    }
+    for item_id in victory.loot {
+        player_item_inv
+            .entry(item_id)
+            .and_modify(|e| *e += 1)
+            .or_insert(1);
+    }
 
-    let _ = announcement
-        .texts
-        .add(format!("You defeated {}!", enemy_name));
+    announce(&mut announcement, victory.events);
+}
 
-    // Level up
-    player.experience += enemy.stats.experience;
-    let _ = announcement
-        .texts
-        .add(format!("You gained {} experience!", enemy.stats.experience));
-    if player.level < 5 && player.experience >= global::XP_TABLE[player.level as usize - 1] {
-        player.experience %= global::XP_TABLE[player.level as usize - 1];
-        player.level += 1;
-
-        player.hp_max += player.level * 50;
-        player.hp = player.hp_max;
-        player.strength += player.level * 5;
-        player.wisdom += player.level * 5;
-        player.mp_max += 40 + player.wisdom * 5;
-        player.mp = player.mp_max;
-
-        let _ = announcement.texts.add("You leveled up!".to_string());
-
-        let attack_table = attack_table.table.clone();
-        if player.level == 2 {
-            let _ = announcement
-                .texts
-                .add("You've unlocked tier 2 magic!".to_string());
-            for i in 10..16 {
-                player_attack_inv.insert(attack_table.get(&i).unwrap().clone());
-            }
-        } else if player.level == 3 {
-            let _ = announcement
-                .texts
-                .add("You've unlocked tier 2 limit break!".to_string());
-            player_attack_inv.insert(attack_table.get(&2).unwrap().clone());
-        } else if player.level == 4 {
-            let _ = announcement
-                .texts
-                .add("You've unlocked tier 3 magic!".to_string());
-            for i in 16..22 {
-                player_attack_inv.insert(attack_table.get(&i).unwrap().clone());
-            }
-        } else if player.level == 5 {
-            let _ = announcement
-                .texts
-                .add("You've unlocked tier 3 limit break!".to_string());
-            player_attack_inv.insert(attack_table.get(&3).unwrap().clone());
-        }
-    } else if player.level == 5 {
-        player.experience = 1;
-    }
+fn phase_transition_setup(
+    mut enemy_portraits: Query<(&mut UiImage, &EnemyPortrait)>,
+    mut announcement: ResMut<Announcement>,
+    mut enemies: Query<(&EnemySlot, global::EnemyComponents)>,
+    mut timeline: ResMut<combat::Timeline>,
+    enemy_table: Res<global::EnemyTable>,
+) {
+    for (slot, enemy) in enemies.iter_mut() {
+        let next_phase = match enemy.1.next_phase {
+            Some(next_phase) if enemy.0.hp <= 0 => next_phase,
+            _ => continue,
+        };
 
-    for loot_table in &enemy.loot_table {
-        if let Some(drop_item_id) = loot_table.get_item_id() {
-            player_item_inv
-                .entry(drop_item_id)
-                .and_modify(|e| *e += 1)
-                .or_insert(1);
-            let item_name = item_table.get(&drop_item_id).unwrap().clone().name;
+        global::Enemy::lend(enemy, |enemy| {
             let _ = announcement
                 .texts
-                .add(format!("You've gained {}", item_name));
-        }
+                .add(format!("{} is not done yet...", enemy.enemy_stats.name));
+
+            // Swap in the next phase. The player keeps their HP, MP and limit.
+            enemy.set_from_table(&enemy_table, next_phase);
+            timeline.set_speed(combat::Side::Enemy(slot.0), enemy.stats.speed);
+
+            let element = if let Some(e) = &enemy.enemy_stats.element {
+                format!("{:?}", e)
+            } else {
+                "Normal".to_string()
+            };
+            let _ = announcement.texts.add(format!(
+                "{} shifted into its {} form!",
+                enemy.enemy_stats.name, element
+            ));
+
+            for (mut portrait, _) in enemy_portraits
+                .iter_mut()
+                .filter(|(_, portrait)| portrait.0 == slot.0)
+            {
+                portrait.0 = enemy.stats.battle_sprite.clone();
+            }
+        });
     }
 }
 
-fn lose_setup(mut announcement: ResMut<Announcement>, enemy: Res<global::Enemy>) {
-    let enemy_name = enemy.enemy_stats.name.clone();
+fn lose_setup(
+    mut announcement: ResMut<Announcement>,
+    enemies: Query<(&EnemySlot, &global::Stats, &global::EnemyStats)>,
+) {
+    let mut living: Vec<_> = enemies
+        .iter()
+        .filter(|(_, stats, _)| stats.hp > 0)
+        .collect();
+    living.sort_by_key(|(slot, ..)| slot.0);
+    let names: Vec<_> = living
+        .iter()
+        .map(|(_, _, enemy_stats)| enemy_stats.name.clone())
+        .collect();
     let _ = announcement
         .texts
-        .add(format!("{} defeated you!", enemy_name));
+        .add(format!("{} defeated you!", join_names(&names)));
 }
 
+// Lists names the way they'd be read out, e.g. "Slime, Rocky and Mushroom".
+fn join_names(names: &[String]) -> String {
+    match names {
+        [] => String::new(),
+        [name] => name.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn battle_update(
     mut commands: Commands,
-    time: Res<Time>,
+    time: Res<global::FrameTime>,
     mut timer: ResMut<Timer>,
     mut battle_state: ResMut<State<BattleState>>,
     mut game_state: ResMut<State<global::GameState>>,
     mut announcement: ResMut<Announcement>,
     mut announcement_text: Query<&mut Text>,
     font_assets: Res<FontAssets>,
-    enemy: Res<global::Enemy>,
+    enemies: Query<(&global::Stats, &global::EnemyStats), With<EnemySlot>>,
     player: Res<global::Player>,
+    player_action: Res<PlayerBattleAction>,
+    timeline: Res<combat::Timeline>,
+    settings: Res<settings::Settings>,
 ) {
     if timer.tick(time.delta()).finished() {
         if let Ok(text) = announcement.texts.remove() {
@@ -919,37 +1311,63 @@ fn battle_update(
                 .unwrap() =
                 Text::with_section(text, common_text_style(&font_assets), Default::default());
 
-            commands.insert_resource(Timer::from_seconds(TEXT_DURATION, false));
+            commands.insert_resource(Timer::from_seconds(settings.text_duration(), false));
         } else {
+            // Enemies that are down but have another phase to go take the battle into a phase
+            // transition. The battle is won once every enemy is down for good.
+            let changing_form = enemies
+                .iter()
+                .any(|(stats, enemy_stats)| stats.hp <= 0 && enemy_stats.next_phase.is_some());
+            let all_defeated = enemies
+                .iter()
+                .all(|(stats, enemy_stats)| stats.hp <= 0 && enemy_stats.next_phase.is_none());
+
             match battle_state.as_ref().current() {
                 BattleState::PlayerAction => {
-                    if enemy.stats.hp <= 0 {
+                    if changing_form {
+                        battle_state.set(BattleState::PhaseTransition).unwrap();
+                    } else if all_defeated {
                         battle_state.set(BattleState::Win).unwrap();
+                    } else if player.stats.hp <= 0 {
+                        // Status effects can take the player down on their own turn.
+                        battle_state.set(BattleState::Lose).unwrap();
+                    } else if player_action.escaped {
+                        // Nothing is won, and the enemies are gone from the overworld too.
+                        battle_state.set(BattleState::Deinitialize).unwrap();
+                        game_state.set(global::GameState::Overworld).unwrap();
                     } else {
-                        battle_state.set(BattleState::EnemyAction).unwrap();
+                        start_next_turn(&mut battle_state, &timeline);
                     }
                 }
                 BattleState::EnemyAction => {
                     if player.stats.hp <= 0 {
                         battle_state.set(BattleState::Lose).unwrap();
+                    } else if changing_form {
+                        battle_state.set(BattleState::PhaseTransition).unwrap();
+                    } else if all_defeated {
+                        battle_state.set(BattleState::Win).unwrap();
                     } else {
-                        battle_state.set(BattleState::Idle).unwrap();
+                        start_next_turn(&mut battle_state, &timeline);
                     }
                 }
+                BattleState::PhaseTransition => {
+                    start_next_turn(&mut battle_state, &timeline);
+                }
                 BattleState::Win => {
                     battle_state.set(BattleState::Deinitialize).unwrap();
-                    game_state.set(global::GameState::Overworld).unwrap();
-                    // TODO: transition to final boss victory screen
+                    if enemies
+                        .iter()
+                        .any(|(_, enemy_stats)| enemy_stats.id == global::FINAL_BOSS_LAST_PHASE_ID)
+                    {
+                        game_state.set(global::GameState::FinalVictory).unwrap();
+                    } else {
+                        game_state.set(global::GameState::Overworld).unwrap();
+                    }
                 }
                 BattleState::Lose => {
                     battle_state.set(BattleState::Deinitialize).unwrap();
                     game_state.set(global::GameState::Lose).unwrap();
                 }
-                BattleState::Deinitialize => {
-                    if let Some(e) = enemy.entity {
-                        commands.entity(e).despawn_recursive();
-                    }
-                }
                 _ => (),
             }
         }
@@ -979,3 +1397,205 @@ fn item_list_scroll(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    // A new game, straight into a battle with the given enemies.
+    fn battle(enemy_ids: &[usize]) -> Harness {
+        let mut harness = Harness::new(0);
+        harness.new_game();
+        // Strong enough to finish any enemy in one tackle, and quick enough to always go first.
+        let mut player = harness.resource_mut::<global::Player>();
+        player.stats.strength = 10000;
+        player.stats.speed = 10000;
+        harness.start_battle(enemy_ids);
+        run_until(&mut harness, BattleState::Idle);
+        harness
+    }
+
+    fn battle_state(world: &World) -> BattleState {
+        world
+            .get_resource::<State<BattleState>>()
+            .unwrap()
+            .current()
+            .clone()
+    }
+
+    fn run_until(harness: &mut Harness, state: BattleState) {
+        harness.run_until(&format!("{:?}", state), |world| {
+            battle_state(world) == state
+        });
+    }
+
+    // The enemy in the given slot. Enemies are spawned once the battle has started.
+    fn enemy(harness: &mut Harness, slot: usize) -> global::Enemy {
+        let world = &mut harness.app.world;
+        world
+            .query::<(&EnemySlot, &global::Stats, &global::EnemyStats)>()
+            .iter(world)
+            .find(|(s, ..)| s.0 == slot)
+            .map(|(_, stats, enemy_stats)| global::Enemy {
+                stats: stats.clone(),
+                enemy_stats: enemy_stats.clone(),
+                ..default()
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn boss_phases_transition_until_final_phase() {
+        let mut harness = battle(&[11]);
+
+        let player = harness.resource::<global::Player>();
+        let (hp, mp, limit) = (player.stats.hp, player.stats.mp, player.limit);
+
+        for next_phase in [12, 13] {
+            harness.click(PlayerButtonAction::Attack);
+            run_until(&mut harness, BattleState::PhaseTransition);
+
+            let enemy = enemy(&mut harness, 0);
+            assert_eq!(next_phase, enemy.enemy_stats.id);
+            assert_eq!(enemy.stats.hp_max, enemy.stats.hp);
+
+            let player = harness.resource::<global::Player>();
+            assert_eq!(
+                (hp, mp, limit),
+                (player.stats.hp, player.stats.mp, player.limit)
+            );
+
+            run_until(&mut harness, BattleState::Idle);
+        }
+
+        harness.click(PlayerButtonAction::Attack);
+        run_until(&mut harness, BattleState::Win);
+        assert_eq!(13, enemy(&mut harness, 0).enemy_stats.id);
+
+        harness.run_until_state(global::GameState::FinalVictory);
+    }
+
+    #[test]
+    fn enemy_without_next_phase_is_won() {
+        let mut harness = battle(&[0]);
+
+        harness.click(PlayerButtonAction::Attack);
+        run_until(&mut harness, BattleState::Win);
+        assert_eq!(0, enemy(&mut harness, 0).enemy_stats.id);
+
+        harness.run_until_state(global::GameState::Overworld);
+        assert_eq!(1, harness.resource::<global::RunStats>().battles_won);
+    }
+
+    #[test]
+    fn running_away_returns_to_the_overworld_empty_handed() {
+        let mut harness = battle(&[0]);
+        // Four levels above the enemy, so 90% to get away each try.
+        harness.resource_mut::<global::Player>().stats.level = 5;
+
+        for _ in 0..5 {
+            harness.click(PlayerButtonAction::Run);
+            harness.run_until("the run to play out", |world| {
+                matches!(
+                    battle_state(world),
+                    BattleState::Idle | BattleState::Deinitialize
+                )
+            });
+            if battle_state(&harness.app.world) == BattleState::Deinitialize {
+                break;
+            }
+        }
+
+        harness.run_until_state(global::GameState::Overworld);
+        let player = harness.resource::<global::Player>();
+        assert_eq!((0, 0), (player.stats.experience, player.stats.gold));
+        assert_eq!(0, harness.resource::<global::RunStats>().battles_won);
+    }
+
+    #[test]
+    fn bosses_cant_be_run_from() {
+        let mut harness = battle(&[11]);
+
+        harness.click(PlayerButtonAction::Run);
+        harness.update();
+        assert_eq!(BattleState::Idle, battle_state(&harness.app.world));
+    }
+
+    #[test]
+    fn gold_is_paid_out_on_win() {
+        let mut harness = battle(&[0]);
+        let gold = harness.resource::<global::Player>().stats.gold;
+        let enemy_gold = enemy(&mut harness, 0).stats.gold;
+
+        harness.click(PlayerButtonAction::Attack);
+        harness.run_until_state(global::GameState::Overworld);
+        assert_eq!(
+            gold + enemy_gold,
+            harness.resource::<global::Player>().stats.gold
+        );
+    }
+
+    #[test]
+    fn the_dpad_can_pick_a_target_without_tinting_the_enemies() {
+        use bevy::input::gamepad::{GamepadButtonType, GamepadEventType};
+
+        let mut harness = battle(&[0, 1]);
+        harness.click(PlayerButtonAction::Attack);
+        harness.update();
+        // Nothing is laid out without a window, so put the targets side by side above the menus.
+        let world = &mut harness.app.world;
+        for (target, mut transform) in world
+            .query::<(&TargetButton, &mut GlobalTransform)>()
+            .iter_mut(world)
+        {
+            transform.translation = Vec3::new(target.0 as f32 * 200., 1000., 0.);
+        }
+
+        harness.gamepad(GamepadEventType::Connected);
+        for button in [GamepadButtonType::DPadUp, GamepadButtonType::DPadRight] {
+            harness.gamepad(GamepadEventType::ButtonChanged(button, 1.));
+            harness.update();
+            harness.gamepad(GamepadEventType::ButtonChanged(button, 0.));
+            harness.update();
+        }
+
+        let world = &mut harness.app.world;
+        let focused: Vec<_> = world
+            .query_filtered::<&TargetButton, With<crate::SelectedOption>>()
+            .iter(world)
+            .map(|target| target.0)
+            .collect();
+        assert_eq!(vec![1], focused);
+        for color in world
+            .query_filtered::<&UiColor, With<EnemyPortrait>>()
+            .iter(world)
+        {
+            assert_eq!(Color::WHITE, color.0);
+        }
+    }
+
+    #[test]
+    fn attacks_wait_for_a_target_when_several_enemies_are_up() {
+        let mut harness = battle(&[0, 1]);
+        let gold = harness.resource::<global::Player>().stats.gold;
+        let enemy_gold = enemy(&mut harness, 0).stats.gold + enemy(&mut harness, 1).stats.gold;
+
+        harness.click(PlayerButtonAction::Attack);
+        harness.update();
+        assert_eq!(BattleState::Idle, battle_state(&harness.app.world));
+
+        harness.click(TargetButton(1));
+        run_until(&mut harness, BattleState::Idle);
+        assert!(enemy(&mut harness, 0).stats.hp > 0);
+        assert_eq!(0, enemy(&mut harness, 1).stats.hp);
+
+        // The last enemy standing is attacked straight away, and both pay out.
+        harness.click(PlayerButtonAction::Attack);
+        harness.run_until_state(global::GameState::Overworld);
+        assert_eq!(
+            gold + enemy_gold,
+            harness.resource::<global::Player>().stats.gold
+        );
+    }
+}
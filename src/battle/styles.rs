@@ -1,8 +1,8 @@
 use bevy::prelude::*;
 
-use crate::{global, FontAssets};
+use crate::{combat, global, FontAssets};
 
-use super::PlayerButtonAction;
+use super::{join_names, PlayerButtonAction};
 
 pub fn common_text_style(font_assets: &Res<FontAssets>) -> TextStyle {
     TextStyle {
@@ -212,7 +212,7 @@ pub fn styled_player_action_button_container() -> NodeBundle {
 pub fn styled_player_action_button() -> ButtonBundle {
     ButtonBundle {
         style: Style {
-            size: Size::new(Val::Px(250.0), Val::Percent(23.0)),
+            size: Size::new(Val::Px(250.0), Val::Percent(18.5)),
             margin: Rect::all(Val::Px(1.0)),
             flex_shrink: 0.,
             justify_content: JustifyContent::Center,
@@ -308,13 +308,13 @@ pub fn styled_announcement_container() -> NodeBundle {
     }
 }
 
-pub fn styled_announcement_text(font_assets: &Res<FontAssets>, name: String) -> TextBundle {
+pub fn styled_announcement_text(font_assets: &Res<FontAssets>, names: &[String]) -> TextBundle {
+    let text = match names {
+        [name] => format!("A wild {} appeared!", name),
+        names => format!("{} appeared!", join_names(names)),
+    };
     TextBundle {
-        text: Text::with_section(
-            format!("A wild {} appeared!", name),
-            common_text_style(&font_assets),
-            Default::default(),
-        ),
+        text: Text::with_section(text, common_text_style(&font_assets), Default::default()),
         ..default()
     }
 }
@@ -342,10 +342,31 @@ pub fn styled_battle_portrait(image: Handle<Image>) -> ImageBundle {
     }
 }
 
-pub fn styled_enemy_portrait_container() -> NodeBundle {
+// Portraits shrink to fit bigger formations side by side.
+fn enemy_portrait_size(count: usize) -> f32 {
+    match count {
+        0 | 1 => 256.,
+        2 => 192.,
+        _ => 144.,
+    }
+}
+
+pub fn styled_enemy_formation_container() -> NodeBundle {
     NodeBundle {
         style: Style {
             size: Size::new(Val::Percent(50.), Val::Percent(100.)),
+            flex_direction: FlexDirection::Row,
+            justify_content: JustifyContent::SpaceEvenly,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+pub fn styled_enemy_portrait_container(count: usize) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100. / count as f32), Val::Percent(100.)),
             align_content: AlignContent::Center,
             align_items: AlignItems::Center,
             flex_direction: FlexDirection::ColumnReverse,
@@ -356,13 +377,211 @@ pub fn styled_enemy_portrait_container() -> NodeBundle {
     }
 }
 
+// Frames the enemy's portrait, which is picked as the target by clicking it.
+pub fn styled_target_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            margin: Rect {
+                left: Val::Auto,
+                right: Val::Auto,
+                ..default()
+            },
+            padding: Rect::all(Val::Px(4.)),
+            ..default()
+        },
+        color: global::NORMAL_BUTTON.into(),
+        ..default()
+    }
+}
+
+pub fn styled_enemy_portrait(image: Handle<Image>, count: usize) -> ImageBundle {
+    let size = enemy_portrait_size(count);
+    ImageBundle {
+        style: Style {
+            size: Size::new(Val::Px(size), Val::Px(size)),
+            ..default()
+        },
+        image: UiImage(image),
+        ..default()
+    }
+}
+
+pub fn styled_enemy_hp_bar_container(count: usize) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Px(enemy_portrait_size(count)), Val::Px(12.)),
+            ..default()
+        },
+        color: Color::GRAY.into(),
+        ..default()
+    }
+}
+
 pub fn styled_enemy_hp_bar() -> NodeBundle {
     NodeBundle {
         style: Style {
-            size: Size::new(Val::Px(256.), Val::Px(12.)),
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
             ..default()
         },
         color: Color::RED.into(),
         ..default()
     }
 }
+
+pub fn styled_status_icons_container() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Auto, Val::Px(28.)),
+            margin: Rect {
+                top: Val::Px(4.),
+                ..default()
+            },
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_status_icon(kind: global::StatusEffectKind) -> NodeBundle {
+    let color = match kind {
+        global::StatusEffectKind::Poison => Color::PURPLE,
+        global::StatusEffectKind::Burn => Color::ORANGE_RED,
+        global::StatusEffectKind::Paralysis => Color::GOLD,
+        global::StatusEffectKind::Silence => Color::GRAY,
+        global::StatusEffectKind::Regen => Color::LIME_GREEN,
+    };
+    NodeBundle {
+        style: Style {
+            padding: Rect::all(Val::Px(2.)),
+            margin: Rect {
+                right: Val::Px(4.),
+                ..default()
+            },
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: color.into(),
+        ..default()
+    }
+}
+
+pub fn styled_status_icon_text(
+    font_assets: &Res<FontAssets>,
+    kind: global::StatusEffectKind,
+    turns_left: u8,
+) -> TextBundle {
+    let label = match kind {
+        global::StatusEffectKind::Poison => "PSN",
+        global::StatusEffectKind::Burn => "BRN",
+        global::StatusEffectKind::Paralysis => "PAR",
+        global::StatusEffectKind::Silence => "SIL",
+        global::StatusEffectKind::Regen => "RGN",
+    };
+    TextBundle {
+        text: Text::with_section(
+            format!("{} {}", label, turns_left),
+            TextStyle {
+                font: font_assets.font.clone(),
+                font_size: 18.,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        ),
+        ..default()
+    }
+}
+
+pub fn styled_turn_order_strip() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(8.),
+                top: Val::Px(8.),
+                ..default()
+            },
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_turn_order_icon(side: combat::Side) -> NodeBundle {
+    let color = match side {
+        combat::Side::Player => Color::BLUE,
+        combat::Side::Enemy(_) => Color::CRIMSON,
+    };
+    NodeBundle {
+        style: Style {
+            padding: Rect::all(Val::Px(4.)),
+            margin: Rect {
+                right: Val::Px(4.),
+                ..default()
+            },
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: color.into(),
+        ..default()
+    }
+}
+
+pub fn styled_turn_order_text<T: Into<String>>(
+    font_assets: &Res<FontAssets>,
+    label: T,
+) -> TextBundle {
+    TextBundle {
+        text: Text::with_section(
+            label,
+            TextStyle {
+                font: font_assets.font.clone(),
+                font_size: 18.,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        ),
+        ..default()
+    }
+}
+
+// Where a damage number sits over its portrait, given how far it has floated up (0 to 1).
+pub fn damage_number_top(progress: f32) -> Val {
+    Val::Px(96. - 64. * progress)
+}
+
+pub fn styled_damage_number(
+    font_assets: &Res<FontAssets>,
+    damage: i32,
+    hit: combat::Hit,
+) -> TextBundle {
+    let (label, font_size, color) = match hit {
+        combat::Hit::Missed => ("Miss".to_string(), 32., Color::GRAY),
+        combat::Hit::Critical => (format!("{}!", damage), 48., Color::ORANGE_RED),
+        combat::Hit::Normal if damage < 0 => (format!("+{}", -damage), 36., Color::LIME_GREEN),
+        combat::Hit::Normal => (damage.to_string(), 36., Color::CRIMSON),
+    };
+    TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Percent(40.),
+                top: damage_number_top(0.),
+                ..default()
+            },
+            ..default()
+        },
+        text: Text::with_section(
+            label,
+            TextStyle {
+                font: font_assets.font.clone(),
+                font_size,
+                color,
+            },
+            Default::default(),
+        ),
+        ..default()
+    }
+}
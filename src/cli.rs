@@ -0,0 +1,215 @@
+use std::{fmt, path::PathBuf};
+
+use crate::simulate;
+
+pub(crate) const USAGE: &str =
+    "Usage: rapid_fantasy_remake [--seed <number>] [--record <file> | --replay <file>]
+       rapid_fantasy_remake simulate [--seed <number>] [--level <1-5>] [--weapon <item id>]
+           [--armor <item id>] [--accessory <item id>] [--magic] [--limit]
+           [--potion-below <percent>] [--area <area id>] [--battles <number>]";
+
+// Options given on the command line, e.g. `cargo run -- --seed 1234`.
+#[derive(Default, Debug, PartialEq)]
+pub(crate) struct Args {
+    pub(crate) seed: Option<u64>,                   // seed for new games
+    pub(crate) record: Option<PathBuf>,             // where to save a recording of the session
+    pub(crate) replay: Option<PathBuf>,             // recording to play back
+    pub(crate) simulate: Option<simulate::Options>, // run battles instead of the game
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ArgsError {
+    MissingValue(&'static str),
+    InvalidValue(&'static str, String),
+    Unknown(String),
+    Conflict(&'static str, &'static str),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::MissingValue(option) => write!(f, "{} needs a value", option),
+            ArgsError::InvalidValue(option, value) => {
+                write!(f, "invalid value for {}: {}", option, value)
+            }
+            ArgsError::Unknown(arg) => write!(f, "unknown argument {}", arg),
+            ArgsError::Conflict(a, b) => write!(f, "{} and {} can't be used together", a, b),
+        }
+    }
+}
+
+impl Args {
+    // Parses the arguments the game was started with, exiting with the usage if they're wrong.
+    pub(crate) fn from_env() -> Self {
+        match Args::parse(std::env::args().skip(1)) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("simulate") {
+            args.next();
+            return Args::parse_simulate(args);
+        }
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => parsed.seed = Some(number(&mut args, "--seed")?),
+                "--record" => {
+                    let path = args.next().ok_or(ArgsError::MissingValue("--record"))?;
+                    parsed.record = Some(path.into());
+                }
+                "--replay" => {
+                    let path = args.next().ok_or(ArgsError::MissingValue("--replay"))?;
+                    parsed.replay = Some(path.into());
+                }
+                _ => return Err(ArgsError::Unknown(arg)),
+            }
+        }
+
+        // A replay always uses the seed it was recorded with.
+        if parsed.replay.is_some() {
+            if parsed.record.is_some() {
+                return Err(ArgsError::Conflict("--record", "--replay"));
+            }
+            if parsed.seed.is_some() {
+                return Err(ArgsError::Conflict("--seed", "--replay"));
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn parse_simulate(mut args: impl Iterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut parsed = Args::default();
+        let mut options = simulate::Options::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => parsed.seed = Some(number(&mut args, "--seed")?),
+                "--level" => {
+                    options.level = number(&mut args, "--level")?;
+                    if !(1..=5).contains(&options.level) {
+                        let level = options.level.to_string();
+                        return Err(ArgsError::InvalidValue("--level", level));
+                    }
+                }
+                "--weapon" => options.equipment.push(number(&mut args, "--weapon")?),
+                "--armor" => options.equipment.push(number(&mut args, "--armor")?),
+                "--accessory" => options.equipment.push(number(&mut args, "--accessory")?),
+                "--magic" => options.policy.magic = true,
+                "--limit" => options.policy.limit = true,
+                "--potion-below" => {
+                    options.policy.potion_below = Some(number(&mut args, "--potion-below")?)
+                }
+                "--area" => options.area = Some(number(&mut args, "--area")?),
+                "--battles" => options.battles = number(&mut args, "--battles")?,
+                _ => return Err(ArgsError::Unknown(arg)),
+            }
+        }
+
+        parsed.simulate = Some(options);
+        Ok(parsed)
+    }
+}
+
+// Reads the value after an option as a number.
+fn number<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    option: &'static str,
+) -> Result<T, ArgsError> {
+    let value = args.next().ok_or(ArgsError::MissingValue(option))?;
+    value
+        .parse()
+        .map_err(|_| ArgsError::InvalidValue(option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, ArgsError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn seed_is_parsed() {
+        assert_eq!(Ok(Args::default()), parse(&[]));
+        assert_eq!(Some(1234), parse(&["--seed", "1234"]).unwrap().seed);
+    }
+
+    #[test]
+    fn recording_options_are_parsed() {
+        let args = parse(&["--seed", "5", "--record", "bug.ron"]).unwrap();
+        assert_eq!(Some(5), args.seed);
+        assert_eq!(Some(PathBuf::from("bug.ron")), args.record);
+        assert_eq!(
+            Some(PathBuf::from("bug.ron")),
+            parse(&["--replay", "bug.ron"]).unwrap().replay
+        );
+        assert_eq!(
+            Err(ArgsError::Conflict("--seed", "--replay")),
+            parse(&["--replay", "bug.ron", "--seed", "5"])
+        );
+    }
+
+    #[test]
+    fn simulate_options_are_parsed() {
+        let args = parse(&[
+            "simulate",
+            "--level",
+            "3",
+            "--weapon",
+            "10",
+            "--armor",
+            "20",
+            "--limit",
+            "--potion-below",
+            "30",
+            "--seed",
+            "7",
+        ])
+        .unwrap();
+        assert_eq!(Some(7), args.seed);
+        assert_eq!(
+            Some(simulate::Options {
+                level: 3,
+                equipment: vec![10, 20],
+                policy: simulate::Policy {
+                    magic: false,
+                    limit: true,
+                    potion_below: Some(30),
+                },
+                ..Default::default()
+            }),
+            args.simulate
+        );
+        assert_eq!(
+            Err(ArgsError::InvalidValue("--level", "6".to_string())),
+            parse(&["simulate", "--level", "6"])
+        );
+        assert_eq!(
+            Err(ArgsError::Unknown("--record".to_string())),
+            parse(&["simulate", "--record", "bug.ron"])
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        assert_eq!(Err(ArgsError::MissingValue("--seed")), parse(&["--seed"]));
+        assert_eq!(
+            Err(ArgsError::InvalidValue("--seed", "abc".to_string())),
+            parse(&["--seed", "abc"])
+        );
+        assert_eq!(
+            Err(ArgsError::Unknown("--fast".to_string())),
+            parse(&["--fast"])
+        );
+    }
+}
@@ -0,0 +1,1189 @@
+// The rules of a battle, kept apart from Bevy. The battle plugin takes a snapshot of the player and
+// enemy, applies each turn to it here and turns the returned events into announcements.
+use crate::global::{
+    self, AttackCondition, EnemyAi, EnemyAttack, EnemyAttackType, EnemyMemory, Item, ItemTable,
+    PlayerAttack, PlayerAttackType, StatusEffectKind, StatusEffects,
+};
+
+use rand::{seq::SliceRandom, Rng};
+use std::fmt;
+
+mod status;
+
+// Everything about one side of a battle that the rules need.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Combatant {
+    pub(crate) hp: i32,
+    pub(crate) hp_max: i32,
+    pub(crate) mp: i32,
+    pub(crate) mp_max: i32,
+    pub(crate) strength: i32,
+    pub(crate) wisdom: i32,
+    pub(crate) defense: i32,
+    pub(crate) speed: i32,
+    pub(crate) crit_chance: i32,
+    pub(crate) crit_damage: i32,
+    pub(crate) evasion: i32,
+    pub(crate) level: i32,
+    pub(crate) element: Option<global::Element>,
+    pub(crate) status_effects: StatusEffects,
+}
+
+impl Combatant {
+    fn new(
+        stats: &global::Stats,
+        element: Option<global::Element>,
+        status_effects: &StatusEffects,
+    ) -> Self {
+        Combatant {
+            hp: stats.hp,
+            hp_max: stats.hp_max,
+            mp: stats.mp,
+            mp_max: stats.mp_max,
+            strength: stats.strength,
+            wisdom: stats.wisdom,
+            defense: stats.defense,
+            speed: stats.speed,
+            crit_chance: stats.crit_chance,
+            crit_damage: stats.crit_damage,
+            evasion: stats.evasion,
+            level: stats.level,
+            element,
+            status_effects: status_effects.clone(),
+        }
+    }
+
+    fn write_back(&self, stats: &mut global::Stats, status_effects: &mut StatusEffects) {
+        stats.hp = self.hp;
+        stats.mp = self.mp;
+        *status_effects = self.status_effects.clone();
+    }
+}
+
+// Who an event is about.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Target {
+    Player,
+    Enemy(String), // name
+}
+
+impl Target {
+    // Start of a sentence about the target, e.g. "You are" or "Slime is".
+    fn subject(&self) -> String {
+        match self {
+            Target::Player => "You are".to_string(),
+            Target::Enemy(name) => format!("{} is", name),
+        }
+    }
+}
+
+// Snapshot of the player and one enemy they are fighting. With several enemies, each turn takes a
+// snapshot of the one acting or being acted against.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BattleState {
+    pub(crate) player: Combatant,
+    pub(crate) enemy: Combatant,
+    pub(crate) enemy_name: String,
+    pub(crate) limit: u8,
+    pub(crate) blocking: bool, // player takes less damage from the next enemy attack
+    pub(crate) escapable: bool, // player can run from the battle
+    pub(crate) variance: f32,  // the enemy's damage variance, for both sides
+    pub(crate) enemy_memory: EnemyMemory,
+}
+
+impl BattleState {
+    pub(crate) fn new(player: &global::Player, enemy: &global::Enemy, blocking: bool) -> Self {
+        BattleState {
+            player: Combatant::new(&player.stats, None, &player.status_effects),
+            enemy: Combatant::new(
+                &enemy.stats,
+                enemy.enemy_stats.element.clone(),
+                &enemy.status_effects,
+            ),
+            enemy_name: enemy.enemy_stats.name.clone(),
+            limit: player.limit,
+            blocking,
+            escapable: !enemy.enemy_stats.unescapable,
+            variance: enemy.enemy_stats.damage_variance,
+            enemy_memory: enemy.memory.clone(),
+        }
+    }
+
+    // Copies HP, MP, limit, status effects and what the enemy remembers back onto the resources the
+    // snapshot was taken from.
+    pub(crate) fn write_back(&self, player: &mut global::Player, enemy: &mut global::Enemy) {
+        self.player
+            .write_back(&mut player.stats, &mut player.status_effects);
+        self.enemy
+            .write_back(&mut enemy.stats, &mut enemy.status_effects);
+        player.limit = self.limit;
+        enemy.memory = self.enemy_memory.clone();
+    }
+
+    fn enemy_target(&self) -> Target {
+        Target::Enemy(self.enemy_name.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum PlayerAction {
+    Attack(PlayerAttack),
+    Block,
+    UseItem(Item),
+    Run,
+}
+
+// Something that happened during a turn, in the order it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum BattleEvent {
+    PlayerAttacked {
+        attack: String,
+        damage: i32,
+        hit: Hit,
+    },
+    Blocked,
+    ItemUsed {
+        item: String,
+        hp: i32,
+        mp: i32,
+    },
+    EnemyAttacked {
+        enemy: String,
+        attack: String,
+        damage: i32,
+        hit: Hit,
+    },
+    Escaped,
+    EscapeFailed,
+    Paralyzed(Target),
+    StatusInflicted(Target, StatusEffectKind),
+    StatusDamage(Target, StatusEffectKind, i32),
+    StatusHealed(Target, i32),
+    StatusExpired(Target, StatusEffectKind),
+    Defeated {
+        enemy: String,
+    },
+    GainedExperience(i32),
+    LeveledUp,
+    Unlocked(&'static str),
+    GainedGold(i32),
+    Looted {
+        item: String,
+    },
+}
+
+// The announcement shown for each event.
+impl fmt::Display for BattleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BattleEvent::PlayerAttacked {
+                attack,
+                hit: Hit::Missed,
+                ..
+            } => write!(f, "You used {}, but it missed!", attack),
+            BattleEvent::PlayerAttacked {
+                attack,
+                damage,
+                hit: Hit::Critical,
+            } => write!(
+                f,
+                "You used {}, landing a critical hit for {} damage!",
+                attack, damage
+            ),
+            BattleEvent::PlayerAttacked { attack, damage, .. } if *damage >= 0 => {
+                write!(f, "You used {}, dealing {} damage.", attack, damage)
+            }
+            BattleEvent::PlayerAttacked { attack, damage, .. } => {
+                write!(f, "You used {}, healing {} to the enemy!", attack, -damage)
+            }
+            BattleEvent::Blocked => write!(f, "You blocked their next attack."),
+            BattleEvent::ItemUsed { item, hp, mp } => {
+                write!(f, "You used {}, healing", item)?;
+                if *hp > 0 {
+                    write!(f, " {} HP", hp)?;
+                }
+                if *mp > 0 {
+                    write!(f, " {} MP", mp)?;
+                }
+                Ok(())
+            }
+            BattleEvent::EnemyAttacked {
+                enemy,
+                attack,
+                hit: Hit::Missed,
+                ..
+            } => write!(f, "{} used {}, but you dodged it!", enemy, attack),
+            BattleEvent::EnemyAttacked {
+                enemy,
+                attack,
+                damage,
+                hit: Hit::Critical,
+            } => write!(
+                f,
+                "{} used {}, landing a critical hit for {} damage to you!",
+                enemy, attack, damage
+            ),
+            BattleEvent::EnemyAttacked {
+                enemy,
+                attack,
+                damage,
+                ..
+            } => write!(
+                f,
+                "{} used {}, dealing {} damage to you!",
+                enemy, attack, damage
+            ),
+            BattleEvent::Escaped => write!(f, "You got away safely!"),
+            BattleEvent::EscapeFailed => write!(f, "You couldn't get away!"),
+            BattleEvent::Paralyzed(target) => {
+                write!(f, "{} paralyzed and can't move!", target.subject())
+            }
+            BattleEvent::StatusInflicted(target, kind) => {
+                write!(f, "{} {}!", target.subject(), status::adjective(*kind))
+            }
+            BattleEvent::StatusDamage(target, kind, amount) => write!(
+                f,
+                "{} hurt by the {}, losing {} HP.",
+                target.subject(),
+                if *kind == StatusEffectKind::Poison {
+                    "poison"
+                } else {
+                    "burn"
+                },
+                amount
+            ),
+            BattleEvent::StatusHealed(target, amount) => {
+                write!(f, "{} healed for {} HP.", target.subject(), amount)
+            }
+            BattleEvent::StatusExpired(target, kind) => {
+                write!(
+                    f,
+                    "{} no longer {}.",
+                    target.subject(),
+                    status::adjective(*kind)
+                )
+            }
+            BattleEvent::Defeated { enemy } => write!(f, "You defeated {}!", enemy),
+            BattleEvent::GainedExperience(experience) => {
+                write!(f, "You gained {} experience!", experience)
+            }
+            BattleEvent::LeveledUp => write!(f, "You leveled up!"),
+            BattleEvent::Unlocked(what) => write!(f, "You've unlocked {}!", what),
+            BattleEvent::GainedGold(gold) => write!(f, "You gained {} gold!", gold),
+            BattleEvent::Looted { item } => write!(f, "You've gained {}", item),
+        }
+    }
+}
+
+// Resolves the player's turn. Spending the item itself is left to the caller, since the
+// inventory isn't part of the battle.
+pub(crate) fn apply_player_action(
+    state: &mut BattleState,
+    action: &PlayerAction,
+    rng: &mut impl Rng,
+) -> Vec<BattleEvent> {
+    let mut events = vec![];
+    state.blocking = false;
+    state.enemy_memory.weakness_hit = false;
+
+    // Paralysis skips whatever was chosen, including blocking.
+    if state.player.status_effects.has(StatusEffectKind::Paralysis) {
+        events.push(BattleEvent::Paralyzed(Target::Player));
+    } else {
+        match action {
+            PlayerAction::Attack(attack) => {
+                let (damage, hit) = roll_hit(
+                    player_attack_damage(attack, &state.player, &state.enemy),
+                    &state.player,
+                    &state.enemy,
+                    state.variance,
+                    rng,
+                );
+                state.enemy.hp = (state.enemy.hp - damage).clamp(0, state.enemy.hp_max);
+                if let Some(PlayerAttackType::Limit) = attack.attack_type {
+                    state.limit = 0;
+                }
+                state.player.mp -= attack.mp_use;
+                state.enemy_memory.weakness_hit = hit != Hit::Missed
+                    && element_modifier(&attack.element, &state.enemy.element) > 1.0;
+                events.push(BattleEvent::PlayerAttacked {
+                    attack: attack.name.clone(),
+                    damage,
+                    hit,
+                });
+
+                if let Some(proc) = attack.effect.as_ref().filter(|_| hit != Hit::Missed) {
+                    let enemy_target = state.enemy_target();
+                    events.extend(status::roll_proc(
+                        proc,
+                        (&mut state.player.status_effects, &Target::Player),
+                        (&mut state.enemy.status_effects, &enemy_target),
+                        rng,
+                    ));
+                }
+            }
+            PlayerAction::Block => {
+                state.blocking = true;
+                events.push(BattleEvent::Blocked);
+            }
+            PlayerAction::UseItem(item) => {
+                if item.stats.hp > 0 {
+                    state.player.hp = (state.player.hp + item.stats.hp).min(state.player.hp_max);
+                }
+                if item.stats.mp > 0 {
+                    state.player.mp = (state.player.mp + item.stats.mp).min(state.player.mp_max);
+                }
+                events.push(BattleEvent::ItemUsed {
+                    item: item.name.clone(),
+                    hp: item.stats.hp,
+                    mp: item.stats.mp,
+                });
+            }
+            PlayerAction::Run => {
+                let chance = if state.escapable {
+                    run_chance(state.player.level, state.enemy.level)
+                } else {
+                    0
+                };
+                if rng.gen_range(0..100) < chance {
+                    events.push(BattleEvent::Escaped);
+                } else {
+                    events.push(BattleEvent::EscapeFailed);
+                }
+            }
+        }
+    }
+
+    events.extend(status::tick(&mut state.player, &Target::Player));
+    events
+}
+
+// Resolves the enemy's turn, picking one of its attacks the way its AI says.
+pub(crate) fn apply_enemy_turn(
+    state: &mut BattleState,
+    ai: &EnemyAi,
+    attacks: &[EnemyAttack],
+    rng: &mut impl Rng,
+) -> Vec<BattleEvent> {
+    let mut events = vec![];
+    let enemy_target = state.enemy_target();
+
+    if state.enemy.status_effects.has(StatusEffectKind::Paralysis) {
+        events.push(BattleEvent::Paralyzed(enemy_target.clone()));
+    } else {
+        let attack = choose_enemy_attack(state, ai, attacks, rng);
+        state.enemy_memory.turns += 1;
+        let (damage, hit) = roll_hit(
+            enemy_attack_damage(&attack, &state.enemy, &state.player, state.blocking),
+            &state.enemy,
+            &state.player,
+            state.variance,
+            rng,
+        );
+        state.limit = limit_after_damage(state.limit, damage, state.player.hp_max);
+        state.player.hp = (state.player.hp - damage).clamp(0, state.player.hp_max);
+        state.enemy.mp -= attack.mp_use;
+        events.push(BattleEvent::EnemyAttacked {
+            enemy: state.enemy_name.clone(),
+            attack: attack.name.clone(),
+            damage,
+            hit,
+        });
+
+        if let Some(proc) = attack.effect.as_ref().filter(|_| hit != Hit::Missed) {
+            events.extend(status::roll_proc(
+                proc,
+                (&mut state.enemy.status_effects, &enemy_target),
+                (&mut state.player.status_effects, &Target::Player),
+                rng,
+            ));
+        }
+    }
+    // A block only lasts for one enemy turn.
+    state.blocking = false;
+
+    events.extend(status::tick(&mut state.enemy, &enemy_target));
+    events
+}
+
+// Picks the enemy's attack. A desperation or opener attack comes first, otherwise it's a weighted
+// pick among the attacks whose conditions hold, keeping the MP reserve for the conditional ones if
+// it can. Only attacks there is MP for can be used, and silenced enemies can't use magic. If that
+// rules out everything, the enemy struggles.
+pub(crate) fn choose_enemy_attack(
+    state: &mut BattleState,
+    ai: &EnemyAi,
+    attacks: &[EnemyAttack],
+    rng: &mut impl Rng,
+) -> EnemyAttack {
+    let enemy = &state.enemy;
+    let silenced = enemy.status_effects.has(StatusEffectKind::Silence);
+    let usable = |attack: &EnemyAttack| {
+        attack.mp_use <= enemy.mp
+            && !(silenced && matches!(attack.attack_type, Some(EnemyAttackType::Magic)))
+    };
+    let named = |name: &str| {
+        attacks
+            .iter()
+            .find(|attack| attack.name == name)
+            .filter(|attack| usable(attack))
+    };
+
+    if let Some(desperation) = &ai.desperation {
+        if !state.enemy_memory.desperate && hp_below(enemy, desperation.hp_below) {
+            if let Some(attack) = named(&desperation.attack) {
+                state.enemy_memory.desperate = true;
+                return attack.clone();
+            }
+        }
+    }
+    if let Some(name) = ai.opener.get(state.enemy_memory.turns as usize) {
+        if let Some(attack) = named(name) {
+            return attack.clone();
+        }
+    }
+
+    let condition_holds = |attack: &EnemyAttack| match &attack.condition {
+        None => true,
+        Some(AttackCondition::HpBelow(percent)) => hp_below(&state.enemy, *percent),
+        Some(AttackCondition::PlayerHpBelow(percent)) => hp_below(&state.player, *percent),
+        Some(AttackCondition::PlayerBlocking) => state.blocking,
+        Some(AttackCondition::WeaknessHit) => state.enemy_memory.weakness_hit,
+    };
+    let candidates: Vec<_> = attacks
+        .iter()
+        .filter(|attack| usable(attack) && condition_holds(attack))
+        .collect();
+    let within_reserve: Vec<_> = candidates
+        .iter()
+        .copied()
+        .filter(|attack| {
+            attack.condition.is_some() || state.enemy.mp - attack.mp_use >= ai.mp_reserve
+        })
+        .collect();
+
+    for pool in [within_reserve, candidates] {
+        if let Ok(attack) = pool.choose_weighted(rng, |attack| attack.weight) {
+            return (*attack).clone();
+        }
+    }
+    struggle()
+}
+
+fn hp_below(combatant: &Combatant, percent: u8) -> bool {
+    combatant.hp * 100 < combatant.hp_max * percent as i32
+}
+
+// A weak physical attack for when an enemy has nothing else it can use.
+fn struggle() -> EnemyAttack {
+    EnemyAttack {
+        name: "Struggle".to_string(),
+        damage_modifier: 1.0,
+        ..Default::default()
+    }
+}
+
+// How far a damage roll can land either side of the base damage, as a fraction of it. Enemies can
+// set their own `damage_variance` in their content file, which applies to the whole fight.
+pub(crate) const DAMAGE_VARIANCE: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Hit {
+    Normal,
+    Critical,
+    Missed,
+}
+
+// Rolls the damage an attack actually deals from its base damage: the defender gets a chance to
+// evade it, then it's spread within the variance band and the attacker gets a chance to crit.
+// Healing can't crit. Chances of 0 don't touch the rng, so battles without them stay predictable.
+fn roll_hit(
+    base: i32,
+    attacker: &Combatant,
+    defender: &Combatant,
+    variance: f32,
+    rng: &mut impl Rng,
+) -> (i32, Hit) {
+    if defender.evasion > 0 && rng.gen_range(0..100) < defender.evasion {
+        return (0, Hit::Missed);
+    }
+
+    let mut damage = base as f32;
+    if variance > 0. {
+        damage *= 1. + rng.gen_range(-variance..=variance);
+    }
+    let mut hit = Hit::Normal;
+    if base > 0 && attacker.crit_chance > 0 && rng.gen_range(0..100) < attacker.crit_chance {
+        damage *= 1. + attacker.crit_damage as f32 / 100.;
+        hit = Hit::Critical;
+    }
+    (damage.round() as i32, hit)
+}
+
+pub(crate) fn player_attack_damage(
+    attack: &PlayerAttack,
+    player: &Combatant,
+    enemy: &Combatant,
+) -> i32 {
+    // Get attack power.
+    let mut power = if let Some(PlayerAttackType::Limit) = attack.attack_type {
+        match attack.tier {
+            3 => 120. + 2.5 * player.strength as f32,
+            2 => 80. + 2.0 * player.strength as f32,
+            1 | _ => 40. + 1.5 * player.strength as f32,
+        }
+    } else if let Some(PlayerAttackType::Magic) = attack.attack_type {
+        match attack.tier {
+            3 => 45. + 1.2 * player.wisdom as f32,
+            2 => 15. + 1.0 * player.wisdom as f32,
+            1 | _ => 5. + 0.8 * player.wisdom as f32,
+        }
+    } else {
+        // Normal attack
+        1.5 * player.strength as f32
+    };
+
+    // Apply elemental modifier.
+    power *= element_modifier(&attack.element, &enemy.element);
+
+    // Get damage reduction.
+    let mut damage_reduction = enemy.defense as f32 + enemy.defense as f32 / 300. * power;
+    if let Some(PlayerAttackType::Magic) = attack.attack_type {
+        damage_reduction *= 0.2;
+    }
+
+    (power - damage_reduction).round() as i32
+}
+
+pub(crate) fn enemy_attack_damage(
+    attack: &EnemyAttack,
+    enemy: &Combatant,
+    player: &Combatant,
+    player_block: bool,
+) -> i32 {
+    // Get attack power.
+    let power = attack.damage_modifier
+        * if let Some(EnemyAttackType::Magic) = attack.attack_type {
+            enemy.wisdom as f32
+        } else if let Some(EnemyAttackType::Percentile) = attack.attack_type {
+            player.hp_max as f32
+        } else {
+            // Physical attack.
+            enemy.strength as f32
+        };
+
+    // Get damage reduction.
+    let mut damage_reduction = player.defense as f32 + player.defense as f32 / 300. * power;
+    if let Some(EnemyAttackType::Magic) = attack.attack_type {
+        damage_reduction *= 0.2;
+    }
+    if player_block {
+        damage_reduction *= 2.;
+    }
+
+    // enemy damage cannot go below 0
+    std::cmp::max(0, (power - damage_reduction).round() as i32)
+}
+
+pub(crate) fn element_modifier(
+    attack: &Option<global::Element>,
+    receiver: &Option<global::Element>,
+) -> f32 {
+    if attack.is_none() || receiver.is_none() {
+        return 1.0;
+    }
+
+    let (attack, receiver) = (attack.clone().unwrap(), receiver.clone().unwrap());
+    use global::Element::*;
+    match (&attack, &receiver) {
+        (Water, Fire)
+        | (Fire, Earth)
+        | (Earth, Electric)
+        | (Electric, Water)
+        | (Dark, Light)
+        | (Light, Dark) => 2.0,
+        (Fire, Water) | (Earth, Fire) | (Electric, Earth) | (Water, Electric) => 0.5,
+        _ if &attack == &receiver => -1.0,
+        _ => 1.0,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Side {
+    Player,
+    Enemy(usize), // slot in the formation
+}
+
+// Time it takes a combatant with a speed of 1 to get a turn. Faster ones take proportionally less.
+const TURN_TIME: u32 = 1200;
+
+// Who acts when. Each side gets its next turn a set time after its last, shorter the faster it is,
+// so a side twice as fast as another acts twice as often.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Timeline {
+    turns: Vec<Turn>, // the player first, then the enemies in slot order
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Turn {
+    side: Side,
+    speed: i32,
+    next: u32, // time of the side's next turn
+}
+
+impl Timeline {
+    pub(crate) fn new(player_speed: i32, enemy_speeds: &[i32]) -> Self {
+        let enemies = enemy_speeds
+            .iter()
+            .enumerate()
+            .map(|(slot, &speed)| (Side::Enemy(slot), speed));
+        Timeline {
+            turns: std::iter::once((Side::Player, player_speed))
+                .chain(enemies)
+                .map(|(side, speed)| Turn {
+                    side,
+                    speed,
+                    next: Timeline::turn_time(speed),
+                })
+                .collect(),
+        }
+    }
+
+    fn turn_time(speed: i32) -> u32 {
+        TURN_TIME / speed.max(1) as u32
+    }
+
+    fn turn(&mut self, side: Side) -> Option<&mut Turn> {
+        self.turns.iter_mut().find(|turn| turn.side == side)
+    }
+
+    // Whose turn it is. On a tie the player goes first, then the enemies from left to right.
+    pub(crate) fn current(&self) -> Side {
+        self.turns
+            .iter()
+            .min_by_key(|turn| turn.next)
+            .map_or(Side::Player, |turn| turn.side)
+    }
+
+    // Ends the current turn, returning whose it was.
+    pub(crate) fn advance(&mut self) -> Side {
+        let side = self.current();
+        if let Some(turn) = self.turn(side) {
+            turn.next += Timeline::turn_time(turn.speed);
+        }
+        side
+    }
+
+    // Takes a defeated side out of the turn order.
+    pub(crate) fn remove(&mut self, side: Side) {
+        self.turns.retain(|turn| turn.side != side);
+    }
+
+    // Changes how often a side acts from its next turn on, e.g. when an enemy changes form.
+    pub(crate) fn set_speed(&mut self, side: Side, speed: i32) {
+        if let Some(turn) = self.turn(side) {
+            turn.speed = speed;
+        }
+    }
+
+    // The coming turns, starting with the current one.
+    pub(crate) fn upcoming(&self, count: usize) -> Vec<Side> {
+        let mut timeline = self.clone();
+        (0..count).map(|_| timeline.advance()).collect()
+    }
+}
+
+// Percent chance of running away. Even, and 10% better for each level the player is above the
+// enemy, but never certain either way.
+pub(crate) fn run_chance(player_level: i32, enemy_level: i32) -> u8 {
+    (50 + 10 * (player_level - enemy_level)).clamp(10, 90) as u8
+}
+
+// Taking damage fills the limit bar, twice as fast as the player's HP drains.
+pub(crate) fn limit_after_damage(limit: u8, damage: i32, hp_max: i32) -> u8 {
+    let limit_addition = 200. * (damage as f32) / (hp_max as f32);
+    let new_limit = limit as f32 + limit_addition;
+    if new_limit < 100. {
+        new_limit as u8
+    } else {
+        100
+    }
+}
+
+// Rewards for defeating the enemies.
+pub(crate) struct Victory {
+    pub(crate) events: Vec<BattleEvent>,
+    pub(crate) unlocked_attacks: Vec<u32>, // attack ids
+    pub(crate) loot: Vec<usize>,           // item ids
+}
+
+// Gives the player the summed experience and gold of every defeated enemy, levels them up and rolls
+// each enemy's loot tables.
+pub(crate) fn resolve_victory(
+    player: &mut global::Stats,
+    enemies: &[global::Enemy],
+    item_table: &ItemTable,
+    rng: &mut impl Rng,
+) -> Victory {
+    let mut victory = Victory {
+        events: enemies
+            .iter()
+            .map(|enemy| BattleEvent::Defeated {
+                enemy: enemy.enemy_stats.name.clone(),
+            })
+            .collect(),
+        unlocked_attacks: vec![],
+        loot: vec![],
+    };
+    let experience: i32 = enemies.iter().map(|enemy| enemy.stats.experience).sum();
+    let gold: i32 = enemies.iter().map(|enemy| enemy.stats.gold).sum();
+
+    // Level up
+    player.experience += experience;
+    victory
+        .events
+        .push(BattleEvent::GainedExperience(experience));
+    if player.level < 5 && player.experience >= global::XP_TABLE[player.level as usize - 1] {
+        player.experience %= global::XP_TABLE[player.level as usize - 1];
+        level_up(player);
+        victory.events.push(BattleEvent::LeveledUp);
+
+        if let Some((unlocked, attack_ids)) = unlocks_at(player.level) {
+            victory.events.push(BattleEvent::Unlocked(unlocked));
+            victory.unlocked_attacks = attack_ids;
+        }
+    } else if player.level == 5 {
+        player.experience = 1;
+    }
+
+    player.gold += gold;
+    victory.events.push(BattleEvent::GainedGold(gold));
+
+    for loot_table in enemies.iter().flat_map(|enemy| enemy.loot_table.iter()) {
+        if let Some(drop_item_id) = loot_table.get_item_id(rng) {
+            victory.events.push(BattleEvent::Looted {
+                item: item_table.get(&drop_item_id).unwrap().name.clone(),
+            });
+            victory.loot.push(drop_item_id);
+        }
+    }
+
+    victory
+}
+
+// Raises the player's stats for their new level and fully heals them.
+pub(crate) fn level_up(player: &mut global::Stats) {
+    player.level += 1;
+
+    player.hp_max += player.level * 50;
+    player.hp = player.hp_max;
+    player.strength += player.level * 5;
+    player.wisdom += player.level * 5;
+    player.mp_max += 40 + player.wisdom * 5;
+    player.mp = player.mp_max;
+    player.speed += 2;
+}
+
+// What the player gets for reaching a level, as the announcement and the attack ids.
+pub(crate) fn unlocks_at(level: i32) -> Option<(&'static str, Vec<u32>)> {
+    match level {
+        2 => Some(("tier 2 magic", (10..16).collect())),
+        3 => Some(("tier 2 limit break", vec![2])),
+        4 => Some(("tier 3 magic", (16..22).collect())),
+        5 => Some(("tier 3 limit break", vec![3])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use global::Element;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn combatant(strength: i32, wisdom: i32, defense: i32) -> Combatant {
+        Combatant {
+            hp: 100,
+            hp_max: 100,
+            mp: 50,
+            mp_max: 50,
+            strength,
+            wisdom,
+            defense,
+            ..Default::default()
+        }
+    }
+
+    fn state() -> BattleState {
+        BattleState {
+            player: combatant(20, 20, 10),
+            enemy: combatant(10, 10, 0),
+            enemy_name: "Slime".to_string(),
+            limit: 0,
+            blocking: false,
+            escapable: true,
+            variance: 0.,
+            enemy_memory: EnemyMemory::default(),
+        }
+    }
+
+    fn enemy_attack(attack_type: Option<EnemyAttackType>, mp_use: i32) -> EnemyAttack {
+        EnemyAttack {
+            name: "Tackle".to_string(),
+            damage_modifier: 2.,
+            mp_use,
+            attack_type,
+            effect: None,
+            weight: 1,
+            condition: None,
+        }
+    }
+
+    fn named_attack(name: &str, mp_use: i32, weight: u32) -> EnemyAttack {
+        EnemyAttack {
+            name: name.to_string(),
+            mp_use,
+            weight,
+            ..Default::default()
+        }
+    }
+
+    // Name of the attack the enemy used on its turn.
+    fn enemy_turn(state: &mut BattleState, ai: &EnemyAi, attacks: &[EnemyAttack]) -> String {
+        let events = apply_enemy_turn(state, ai, attacks, &mut StdRng::seed_from_u64(0));
+        match &events[0] {
+            BattleEvent::EnemyAttacked { attack, .. } => attack.clone(),
+            event => panic!("enemy didn't attack: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn element_matchups() {
+        let modifier = |a, r| element_modifier(&Some(a), &Some(r));
+        assert_eq!(2.0, modifier(Element::Water, Element::Fire));
+        assert_eq!(0.5, modifier(Element::Fire, Element::Water));
+        assert_eq!(-1.0, modifier(Element::Fire, Element::Fire));
+        assert_eq!(1.0, modifier(Element::Fire, Element::Dark));
+        assert_eq!(1.0, element_modifier(&None, &Some(Element::Fire)));
+    }
+
+    #[test]
+    fn player_damage_by_attack_type() {
+        let player = combatant(20, 40, 0);
+        let enemy = combatant(0, 0, 30);
+
+        let normal = PlayerAttack::default();
+        // 1.5 * 20 = 30 power, 30 + 30 / 300 * 30 = 33 reduction
+        assert_eq!(-3, player_attack_damage(&normal, &player, &enemy));
+
+        let magic = PlayerAttack {
+            attack_type: Some(PlayerAttackType::Magic),
+            tier: 2,
+            ..Default::default()
+        };
+        // 15 + 40 = 55 power, (30 + 30 / 300 * 55) * 0.2 = 7.1 reduction
+        assert_eq!(48, player_attack_damage(&magic, &player, &enemy));
+    }
+
+    #[test]
+    fn blocking_doubles_damage_reduction() {
+        let enemy = combatant(50, 0, 0);
+        let player = combatant(0, 0, 20);
+        let attack = enemy_attack(None, 0);
+
+        // 100 power, 20 + 20 / 300 * 100 = 26.67 reduction
+        assert_eq!(73, enemy_attack_damage(&attack, &enemy, &player, false));
+        assert_eq!(47, enemy_attack_damage(&attack, &enemy, &player, true));
+    }
+
+    #[test]
+    fn enemy_damage_never_heals() {
+        let enemy = combatant(1, 0, 0);
+        let player = combatant(0, 0, 50);
+        assert_eq!(
+            0,
+            enemy_attack_damage(&enemy_attack(None, 0), &enemy, &player, false)
+        );
+    }
+
+    #[test]
+    fn hits_vary_crit_and_miss() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut attacker = combatant(0, 0, 0);
+        let mut defender = combatant(0, 0, 0);
+
+        for _ in 0..50 {
+            let (damage, hit) = roll_hit(100, &attacker, &defender, 0.1, &mut rng);
+            assert_eq!(Hit::Normal, hit);
+            assert!((90..=110).contains(&damage));
+        }
+
+        attacker.crit_chance = 100;
+        attacker.crit_damage = 50;
+        assert_eq!(
+            (150, Hit::Critical),
+            roll_hit(100, &attacker, &defender, 0., &mut rng)
+        );
+        // healing never crits
+        assert_eq!(
+            (-10, Hit::Normal),
+            roll_hit(-10, &attacker, &defender, 0., &mut rng)
+        );
+
+        defender.evasion = 100;
+        assert_eq!(
+            (0, Hit::Missed),
+            roll_hit(100, &attacker, &defender, 0.1, &mut rng)
+        );
+    }
+
+    #[test]
+    fn missed_attacks_dont_proc_or_hit_weaknesses() {
+        let mut state = state();
+        state.enemy.evasion = 100;
+        state.enemy.element = Some(Element::Fire);
+        let water = PlayerAttack {
+            name: "Splash".to_string(),
+            element: Some(Element::Water),
+            effect: Some(global::StatusEffectProc {
+                kind: StatusEffectKind::Poison,
+                chance: 100,
+                turns: 3,
+            }),
+            ..Default::default()
+        };
+
+        let events = apply_player_action(
+            &mut state,
+            &PlayerAction::Attack(water),
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(100, state.enemy.hp);
+        assert!(!state.enemy_memory.weakness_hit);
+        assert!(!state.enemy.status_effects.has(StatusEffectKind::Poison));
+        assert_eq!("You used Splash, but it missed!", events[0].to_string());
+    }
+
+    #[test]
+    fn limit_fills_twice_as_fast_as_hp_drains() {
+        assert_eq!(40, limit_after_damage(20, 10, 100));
+        assert_eq!(100, limit_after_damage(90, 10, 100));
+    }
+
+    #[test]
+    fn limit_break_empties_the_limit_bar() {
+        let mut state = state();
+        state.limit = 100;
+        let limit = PlayerAttack {
+            name: "Limit".to_string(),
+            attack_type: Some(PlayerAttackType::Limit),
+            tier: 1,
+            ..Default::default()
+        };
+
+        let events = apply_player_action(
+            &mut state,
+            &PlayerAction::Attack(limit),
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(0, state.limit);
+        // 40 + 1.5 * 20 = 70 against no defense
+        assert_eq!(30, state.enemy.hp);
+        assert_eq!("You used Limit, dealing 70 damage.", events[0].to_string());
+    }
+
+    #[test]
+    fn paralysis_skips_the_players_action() {
+        let mut state = state();
+        state
+            .player
+            .status_effects
+            .inflict(StatusEffectKind::Paralysis, 1);
+
+        let events = apply_player_action(
+            &mut state,
+            &PlayerAction::Block,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert!(!state.blocking);
+        assert_eq!(
+            vec![
+                BattleEvent::Paralyzed(Target::Player),
+                BattleEvent::StatusExpired(Target::Player, StatusEffectKind::Paralysis),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn faster_sides_act_more_often() {
+        use Side::*;
+        assert_eq!(
+            vec![Player, Enemy(0), Player, Enemy(0)],
+            Timeline::new(10, &[10]).upcoming(4)
+        );
+        assert_eq!(
+            vec![Player, Player, Enemy(0), Player, Player, Enemy(0)],
+            Timeline::new(20, &[10]).upcoming(6)
+        );
+        assert_eq!(
+            vec![Enemy(0), Enemy(0), Player, Enemy(0), Enemy(0), Player],
+            Timeline::new(10, &[25]).upcoming(6)
+        );
+
+        let mut timeline = Timeline::new(5, &[10]);
+        assert_eq!(Enemy(0), timeline.advance());
+        assert_eq!(Player, timeline.current());
+    }
+
+    #[test]
+    fn every_enemy_in_a_formation_gets_turns() {
+        use Side::*;
+        let mut timeline = Timeline::new(10, &[10, 20]);
+        assert_eq!(
+            vec![Enemy(1), Player, Enemy(0), Enemy(1), Enemy(1), Player],
+            timeline.upcoming(6)
+        );
+
+        timeline.remove(Enemy(1));
+        assert_eq!(
+            vec![Player, Enemy(0), Player, Enemy(0)],
+            timeline.upcoming(4)
+        );
+    }
+
+    #[test]
+    fn run_chance_follows_level_difference() {
+        assert_eq!(50, run_chance(3, 3));
+        assert_eq!(70, run_chance(3, 1));
+        assert_eq!(10, run_chance(1, 5));
+        assert_eq!(90, run_chance(5, 1));
+    }
+
+    #[test]
+    fn bosses_cant_be_run_from() {
+        let mut state = state();
+        state.player.level = 5;
+        state.escapable = false;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let events = apply_player_action(&mut state, &PlayerAction::Run, &mut rng);
+            assert_eq!(vec![BattleEvent::EscapeFailed], events);
+        }
+    }
+
+    #[test]
+    fn silenced_enemies_only_use_physical_attacks() {
+        let mut state = state();
+        state
+            .enemy
+            .status_effects
+            .inflict(StatusEffectKind::Silence, 3);
+        let attacks = [
+            enemy_attack(Some(EnemyAttackType::Magic), 0),
+            enemy_attack(None, 0),
+        ];
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let attack = choose_enemy_attack(&mut state, &EnemyAi::default(), &attacks, &mut rng);
+            assert!(attack.attack_type.is_none());
+        }
+
+        // With nothing it can use, it struggles rather than doing nothing.
+        assert_eq!(
+            "Struggle",
+            enemy_turn(&mut state, &EnemyAi::default(), &attacks[..1])
+        );
+    }
+
+    #[test]
+    fn openers_and_desperation_attacks_are_scripted() {
+        let mut state = state();
+        let attacks = [
+            named_attack("Peck", 0, 1),
+            named_attack("Ignition", 5, 0),
+            named_attack("Flamethrower", 12, 0),
+        ];
+        let ai = EnemyAi {
+            opener: vec!["Ignition".to_string()],
+            desperation: Some(global::Desperation {
+                hp_below: 30,
+                attack: "Flamethrower".to_string(),
+            }),
+            mp_reserve: 0,
+        };
+
+        assert_eq!("Ignition", enemy_turn(&mut state, &ai, &attacks));
+        assert_eq!("Peck", enemy_turn(&mut state, &ai, &attacks));
+        state.enemy.hp = 20;
+        assert_eq!("Flamethrower", enemy_turn(&mut state, &ai, &attacks));
+        // Only once.
+        assert_eq!("Peck", enemy_turn(&mut state, &ai, &attacks));
+    }
+
+    #[test]
+    fn conditional_attacks_wait_for_their_condition() {
+        let mut state = state();
+        state.enemy.element = Some(Element::Fire);
+        let mut counter = named_attack("Counter", 0, 1);
+        counter.condition = Some(AttackCondition::WeaknessHit);
+        let attacks = [
+            named_attack("Peck", 0, 1),
+            counter,
+            named_attack("Blast", 30, 1),
+        ];
+        // 50 MP with 30 kept back, so Blast is never used either.
+        let ai = EnemyAi {
+            mp_reserve: 30,
+            ..Default::default()
+        };
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let attack = choose_enemy_attack(&mut state, &ai, &attacks, &mut rng);
+            assert_eq!("Peck", attack.name);
+        }
+
+        let water = PlayerAttack {
+            attack_type: Some(PlayerAttackType::Magic),
+            element: Some(Element::Water),
+            ..Default::default()
+        };
+        apply_player_action(&mut state, &PlayerAction::Attack(water), &mut rng);
+        assert!(state.enemy_memory.weakness_hit);
+        assert!((0..20)
+            .any(|_| choose_enemy_attack(&mut state, &ai, &attacks, &mut rng).name == "Counter"));
+    }
+
+    #[test]
+    fn victory_levels_up_and_unlocks_attacks() {
+        let tables = crate::content::Tables::for_tests();
+        let mut player = global::Stats {
+            hp_max: 100,
+            hp: 10,
+            level: 1,
+            experience: global::XP_TABLE[0] - 5,
+            ..Default::default()
+        };
+        let mut enemy = global::Enemy::default();
+        enemy.stats.experience = 5;
+        enemy.stats.gold = 7;
+
+        let victory = resolve_victory(
+            &mut player,
+            &[enemy.clone(), enemy],
+            &tables.items,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(2, player.level);
+        assert_eq!(5, player.experience);
+        assert_eq!(200, player.hp);
+        assert_eq!(14, player.gold);
+        assert_eq!((10..16).collect::<Vec<_>>(), victory.unlocked_attacks);
+        assert!(victory
+            .events
+            .contains(&BattleEvent::Unlocked("tier 2 magic")));
+    }
+}
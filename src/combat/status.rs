@@ -0,0 +1,189 @@
+use super::{BattleEvent, Combatant, Target};
+use crate::global::{StatusEffectKind, StatusEffectProc, StatusEffects};
+
+use rand::Rng;
+
+pub(super) fn adjective(kind: StatusEffectKind) -> &'static str {
+    match kind {
+        StatusEffectKind::Poison => "poisoned",
+        StatusEffectKind::Burn => "burned",
+        StatusEffectKind::Paralysis => "paralyzed",
+        StatusEffectKind::Silence => "silenced",
+        StatusEffectKind::Regen => "regenerating",
+    }
+}
+
+// Effects that help whoever has them. Attacks inflict these on the attacker instead of the target.
+fn is_beneficial(kind: StatusEffectKind) -> bool {
+    kind == StatusEffectKind::Regen
+}
+
+// Rolls an attack's chance to inflict its status effect, returning the event if it did.
+pub(super) fn roll_proc(
+    proc: &StatusEffectProc,
+    attacker: (&mut StatusEffects, &Target),
+    target: (&mut StatusEffects, &Target),
+    rng: &mut impl Rng,
+) -> Option<BattleEvent> {
+    if rng.gen_range(0..100) >= proc.chance {
+        return None;
+    }
+
+    let (effects, who) = if is_beneficial(proc.kind) {
+        attacker
+    } else {
+        target
+    };
+    effects.inflict(proc.kind, proc.turns);
+    Some(BattleEvent::StatusInflicted(who.clone(), proc.kind))
+}
+
+// Applies damage and healing over time and counts down every effect by a turn. Returns what
+// happened.
+pub(super) fn tick(combatant: &mut Combatant, who: &Target) -> Vec<BattleEvent> {
+    let mut events = vec![];
+
+    for effect in combatant.status_effects.iter_mut() {
+        let amount = match effect.kind {
+            StatusEffectKind::Poison => combatant.hp_max / 12,
+            StatusEffectKind::Burn => combatant.hp_max / 10,
+            StatusEffectKind::Regen => -combatant.hp_max / 10,
+            StatusEffectKind::Paralysis | StatusEffectKind::Silence => 0,
+        };
+        if amount > 0 {
+            let amount = amount.max(1);
+            combatant.hp = (combatant.hp - amount).max(0);
+            events.push(BattleEvent::StatusDamage(who.clone(), effect.kind, amount));
+        } else if amount < 0 {
+            let amount = -amount;
+            combatant.hp = (combatant.hp + amount).min(combatant.hp_max);
+            events.push(BattleEvent::StatusHealed(who.clone(), amount));
+        }
+
+        effect.turns_left = effect.turns_left.saturating_sub(1);
+        if effect.turns_left == 0 {
+            events.push(BattleEvent::StatusExpired(who.clone(), effect.kind));
+        }
+    }
+    combatant
+        .status_effects
+        .retain(|effect| effect.turns_left > 0);
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn combatant() -> Combatant {
+        Combatant {
+            hp_max: 100,
+            hp: 50,
+            ..Default::default()
+        }
+    }
+
+    fn slime() -> Target {
+        Target::Enemy("Slime".to_string())
+    }
+
+    #[test]
+    fn poison_deals_damage_until_it_wears_off() {
+        let mut player = combatant();
+        player.status_effects.inflict(StatusEffectKind::Poison, 2);
+
+        let events = tick(&mut player, &Target::Player);
+        assert_eq!(42, player.hp);
+        assert_eq!(
+            vec!["You are hurt by the poison, losing 8 HP.".to_string()],
+            events.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+
+        let events = tick(&mut player, &Target::Player);
+        assert_eq!(34, player.hp);
+        assert_eq!("You are no longer poisoned.", events[1].to_string());
+        assert!(player.status_effects.is_empty());
+    }
+
+    #[test]
+    fn regen_heals_up_to_max_hp() {
+        let mut player = combatant();
+        player.hp = 95;
+        player.status_effects.inflict(StatusEffectKind::Regen, 3);
+
+        tick(&mut player, &Target::Player);
+        assert_eq!(100, player.hp);
+        assert!(player.status_effects.has(StatusEffectKind::Regen));
+    }
+
+    #[test]
+    fn inflicting_again_refreshes_duration() {
+        let mut effects = StatusEffects::default();
+        effects.inflict(StatusEffectKind::Burn, 1);
+        effects.inflict(StatusEffectKind::Burn, 3);
+        assert_eq!(1, effects.len());
+        assert_eq!(3, effects[0].turns_left);
+    }
+
+    #[test]
+    fn procs_land_on_the_right_combatant() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut attacker = StatusEffects::default();
+        let mut target = StatusEffects::default();
+
+        let paralysis = StatusEffectProc {
+            kind: StatusEffectKind::Paralysis,
+            chance: 100,
+            turns: 1,
+        };
+        let event = roll_proc(
+            &paralysis,
+            (&mut attacker, &Target::Player),
+            (&mut target, &slime()),
+            &mut rng,
+        );
+        assert_eq!(
+            Some("Slime is paralyzed!".to_string()),
+            event.map(|e| e.to_string())
+        );
+        assert!(target.has(StatusEffectKind::Paralysis));
+
+        let regen = StatusEffectProc {
+            kind: StatusEffectKind::Regen,
+            chance: 100,
+            turns: 3,
+        };
+        roll_proc(
+            &regen,
+            (&mut attacker, &Target::Player),
+            (&mut target, &slime()),
+            &mut rng,
+        );
+        assert!(attacker.has(StatusEffectKind::Regen));
+        assert!(!target.has(StatusEffectKind::Regen));
+    }
+
+    #[test]
+    fn procs_can_miss() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut attacker = StatusEffects::default();
+        let mut target = StatusEffects::default();
+        let never = StatusEffectProc {
+            kind: StatusEffectKind::Poison,
+            chance: 0,
+            turns: 3,
+        };
+        assert_eq!(
+            None,
+            roll_proc(
+                &never,
+                (&mut attacker, &Target::Player),
+                (&mut target, &slime()),
+                &mut rng,
+            )
+        );
+        assert!(target.is_empty());
+    }
+}
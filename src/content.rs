@@ -0,0 +1,763 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt, fs,
+    marker::PhantomData,
+    path::Path,
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use bevy_asset_loader::AssetCollection;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    area_table::AreaTableAsset, enemy_table::EnemyTableAsset, global, item_table::ItemTableAsset,
+    player_attack_table::PlayerAttackTableAsset, shop_table::ShopTableAsset, tile_map, ImageAssets,
+};
+
+// Where the content files live when read without the asset server, relative to the crate root.
+pub(crate) const CONTENT_DIR: &str = "assets/content";
+const ENEMIES_FILE: &str = "base.enemies.ron";
+const ITEMS_FILE: &str = "base.items.ron";
+const ATTACKS_FILE: &str = "base.attacks.ron";
+const AREAS_FILE: &str = "base.areas.ron";
+const SHOPS_FILE: &str = "base.shops.ron";
+
+pub struct ContentPlugin;
+
+impl Plugin for ContentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<EnemyTableAsset>()
+            .add_asset::<ItemTableAsset>()
+            .add_asset::<PlayerAttackTableAsset>()
+            .add_asset::<AreaTableAsset>()
+            .add_asset::<ShopTableAsset>()
+            .add_asset_loader(RonLoader::<EnemyTableAsset>::new(&["enemies.ron"]))
+            .add_asset_loader(RonLoader::<ItemTableAsset>::new(&["items.ron"]))
+            .add_asset_loader(RonLoader::<PlayerAttackTableAsset>::new(&["attacks.ron"]))
+            .add_asset_loader(RonLoader::<AreaTableAsset>::new(&["areas.ron"]))
+            .add_asset_loader(RonLoader::<ShopTableAsset>::new(&["shops.ron"]))
+            .add_system_set(
+                SystemSet::on_exit(global::GameState::Initialization).with_system(build_tables),
+            );
+    }
+}
+
+#[derive(AssetCollection)]
+pub struct ContentAssets {
+    #[asset(path = "content/base.enemies.ron")]
+    pub(crate) enemies: Handle<EnemyTableAsset>,
+    #[asset(path = "content/base.items.ron")]
+    pub(crate) items: Handle<ItemTableAsset>,
+    #[asset(path = "content/base.attacks.ron")]
+    pub(crate) attacks: Handle<PlayerAttackTableAsset>,
+    #[asset(path = "content/base.areas.ron")]
+    pub(crate) areas: Handle<AreaTableAsset>,
+    #[asset(path = "content/base.shops.ron")]
+    pub(crate) shops: Handle<ShopTableAsset>,
+}
+
+// Loads a content file into its asset type. Each table gets its own extension, e.g.
+// `base.enemies.ron`, so that the asset server knows which type to load it as.
+struct RonLoader<T> {
+    extensions: &'static [&'static str],
+    asset: PhantomData<fn() -> T>,
+}
+
+impl<T> RonLoader<T> {
+    fn new(extensions: &'static [&'static str]) -> Self {
+        RonLoader {
+            extensions,
+            asset: PhantomData,
+        }
+    }
+}
+
+impl<T> AssetLoader for RonLoader<T>
+where
+    T: TypeUuid + DeserializeOwned + Send + Sync + 'static,
+{
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let asset = ron::de::from_bytes::<T>(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+}
+
+// A problem with a content file, pointing at the table and entry it was found in.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ContentError {
+    pub(crate) table: &'static str,
+    pub(crate) entry: String,
+    pub(crate) message: String,
+}
+
+impl ContentError {
+    pub(crate) fn new(
+        table: &'static str,
+        entry: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        ContentError {
+            table,
+            entry: entry.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ContentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.table, self.entry, self.message)
+    }
+}
+
+// Every content table, built from the content files.
+pub(crate) struct Tables {
+    pub(crate) enemies: global::EnemyTable,
+    pub(crate) items: global::ItemTable,
+    pub(crate) attacks: global::PlayerAttackTable,
+    pub(crate) areas: global::Areas,
+    pub(crate) shops: global::ShopTable,
+}
+
+impl Tables {
+    // Builds and validates every table, returning all errors found rather than just the first.
+    pub(crate) fn build(
+        enemies: &EnemyTableAsset,
+        items: &ItemTableAsset,
+        attacks: &PlayerAttackTableAsset,
+        areas: &AreaTableAsset,
+        shops: &ShopTableAsset,
+        image_assets: &ImageAssets,
+    ) -> Result<Self, Vec<ContentError>> {
+        Tables::build_each(enemies, items, attacks, areas, shops, image_assets)?.checked()
+    }
+
+    // Like build, but with the entries in `overrides` replacing the ones with the same id or being
+    // added to them before the tables are checked against each other.
+    #[cfg(any(feature = "dev", test))]
+    pub(crate) fn build_with_overrides(
+        enemies: &EnemyTableAsset,
+        items: &ItemTableAsset,
+        attacks: &PlayerAttackTableAsset,
+        areas: &AreaTableAsset,
+        shops: &ShopTableAsset,
+        overrides: &Overrides,
+        image_assets: &ImageAssets,
+    ) -> Result<Self, Vec<ContentError>> {
+        let mut tables = Tables::build_each(enemies, items, attacks, areas, shops, image_assets)?;
+        let mut errors = vec![];
+        if let Some(enemies) = &overrides.enemies {
+            match enemies.build(image_assets) {
+                Ok(enemies) => tables.enemies.table.extend(enemies.table),
+                Err(e) => errors.extend(e),
+            }
+        }
+        if let Some(items) = &overrides.items {
+            match items.build() {
+                Ok(items) => tables.items.0.extend(items.0),
+                Err(e) => errors.extend(e),
+            }
+        }
+        if let Some(attacks) = &overrides.attacks {
+            match attacks.build() {
+                Ok(attacks) => tables.attacks.table.extend(attacks.table),
+                Err(e) => errors.extend(e),
+            }
+        }
+        if let Some(areas) = &overrides.areas {
+            match areas.build(image_assets) {
+                Ok(areas) => tables.areas.0.extend(areas.0),
+                Err(e) => errors.extend(e),
+            }
+        }
+        if let Some(shops) = &overrides.shops {
+            match shops.build() {
+                Ok(shops) => {
+                    tables.shops.shops.extend(shops.shops);
+                    tables.shops.prices.extend(shops.prices);
+                }
+                Err(e) => errors.extend(e),
+            }
+        }
+
+        if errors.is_empty() {
+            tables.checked()
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Builds each table on its own, without checking how they refer to each other.
+    fn build_each(
+        enemies: &EnemyTableAsset,
+        items: &ItemTableAsset,
+        attacks: &PlayerAttackTableAsset,
+        areas: &AreaTableAsset,
+        shops: &ShopTableAsset,
+        image_assets: &ImageAssets,
+    ) -> Result<Self, Vec<ContentError>> {
+        match (
+            enemies.build(image_assets),
+            items.build(),
+            attacks.build(),
+            areas.build(image_assets),
+            shops.build(),
+        ) {
+            (Ok(enemies), Ok(items), Ok(attacks), Ok(areas), Ok(shops)) => Ok(Tables {
+                enemies,
+                items,
+                attacks,
+                areas,
+                shops,
+            }),
+            (enemies, items, attacks, areas, shops) => Err([
+                enemies.err(),
+                items.err(),
+                attacks.err(),
+                areas.err(),
+                shops.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect()),
+        }
+    }
+
+    fn checked(self) -> Result<Self, Vec<ContentError>> {
+        let errors = check_references(&self);
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Reads the content files straight from disk, for when there is no asset server to load them.
+    pub(crate) fn load(dir: &Path, image_assets: &ImageAssets) -> Result<Self, Vec<ContentError>> {
+        match (
+            read::<EnemyTableAsset>(&dir.join(ENEMIES_FILE), "enemies"),
+            read::<ItemTableAsset>(&dir.join(ITEMS_FILE), "items"),
+            read::<PlayerAttackTableAsset>(&dir.join(ATTACKS_FILE), "attacks"),
+            read::<AreaTableAsset>(&dir.join(AREAS_FILE), "areas"),
+            read::<ShopTableAsset>(&dir.join(SHOPS_FILE), "shops"),
+        ) {
+            (Ok(enemies), Ok(items), Ok(attacks), Ok(areas), Ok(shops)) => {
+                Tables::build(&enemies, &items, &attacks, &areas, &shops, image_assets)
+            }
+            (enemies, items, attacks, areas, shops) => Err([
+                enemies.err(),
+                items.err(),
+                attacks.err(),
+                areas.err(),
+                shops.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
+    }
+
+    // The game's own content files, without any images.
+    pub(crate) fn from_content_dir() -> Result<Self, Vec<ContentError>> {
+        Tables::load(Path::new(CONTENT_DIR), &ImageAssets::default())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn for_tests() -> Self {
+        Tables::from_content_dir().unwrap()
+    }
+
+    pub(crate) fn insert(self, commands: &mut Commands) {
+        commands.insert_resource(self.enemies);
+        commands.insert_resource(self.items);
+        commands.insert_resource(self.attacks);
+        commands.insert_resource(self.areas);
+        commands.insert_resource(self.shops);
+    }
+}
+
+fn read<T: DeserializeOwned>(path: &Path, table: &'static str) -> Result<T, ContentError> {
+    let error = |message: String| ContentError::new(table, path.display().to_string(), message);
+    let contents = fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    ron::from_str(&contents).map_err(|e| error(e.to_string()))
+}
+
+// Development only: entries from files next to the base ones, e.g. `base.enemies.override.ron`,
+// that replace the base entries with the same id. Any of the files can be left out. They're kept
+// out of the repository so that numbers can be tuned without touching the base files.
+#[cfg(any(feature = "dev", test))]
+#[derive(Default)]
+pub(crate) struct Overrides {
+    enemies: Option<EnemyTableAsset>,
+    items: Option<ItemTableAsset>,
+    attacks: Option<PlayerAttackTableAsset>,
+    areas: Option<AreaTableAsset>,
+    shops: Option<ShopTableAsset>,
+}
+
+#[cfg(any(feature = "dev", test))]
+impl Overrides {
+    pub(crate) fn paths(dir: &Path) -> [std::path::PathBuf; 5] {
+        [
+            ENEMIES_FILE,
+            ITEMS_FILE,
+            ATTACKS_FILE,
+            AREAS_FILE,
+            SHOPS_FILE,
+        ]
+        .map(|file| dir.join(file.replace(".ron", ".override.ron")))
+    }
+
+    pub(crate) fn read(dir: &Path) -> Result<Self, Vec<ContentError>> {
+        fn read_if_there<T: DeserializeOwned>(
+            path: &Path,
+            table: &'static str,
+        ) -> Result<Option<T>, ContentError> {
+            if path.exists() {
+                read(path, table).map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+
+        let [enemies, items, attacks, areas, shops] = Overrides::paths(dir);
+        match (
+            read_if_there(&enemies, "enemies"),
+            read_if_there(&items, "items"),
+            read_if_there(&attacks, "attacks"),
+            read_if_there(&areas, "areas"),
+            read_if_there(&shops, "shops"),
+        ) {
+            (Ok(enemies), Ok(items), Ok(attacks), Ok(areas), Ok(shops)) => Ok(Overrides {
+                enemies,
+                items,
+                attacks,
+                areas,
+                shops,
+            }),
+            (enemies, items, attacks, areas, shops) => Err([
+                enemies.err(),
+                items.err(),
+                attacks.err(),
+                areas.err(),
+                shops.err(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()),
+        }
+    }
+}
+
+// Checks that the tables agree with each other, and that everything in them can actually be found
+// in a playthrough.
+fn check_references(tables: &Tables) -> Vec<ContentError> {
+    let shop_table = &tables.shops;
+    let mut errors = vec![];
+    let enemy_entry = |id: &usize| match tables.enemies.table.get(id) {
+        Some((enemy_stats, ..)) => format!("enemy {} \"{}\"", id, enemy_stats.name),
+        None => format!("enemy {}", id),
+    };
+    let enemy_ids: BTreeSet<_> = tables.enemies.table.keys().copied().collect();
+    let area_ids: BTreeSet<_> = tables.areas.keys().copied().collect();
+    let shop_ids: BTreeSet<_> = shop_table.shops.keys().copied().collect();
+
+    // Enemies and the phases they turn into.
+    for id in &enemy_ids {
+        let (enemy_stats, _, _, loot_tables) = &tables.enemies.table[id];
+        for (i, loot_table) in loot_tables.iter().enumerate() {
+            for (item_id, _) in &loot_table.items {
+                if !tables.items.contains_key(item_id) {
+                    errors.push(ContentError::new(
+                        "enemies",
+                        enemy_entry(id),
+                        format!("loot table {} drops unknown item {}", i, item_id),
+                    ));
+                }
+            }
+        }
+
+        let mut seen = HashSet::from([*id]);
+        let mut phase = enemy_stats.next_phase;
+        while let Some(next) = phase {
+            if !seen.insert(next) {
+                errors.push(ContentError::new(
+                    "enemies",
+                    enemy_entry(id),
+                    format!("next_phase chain never ends, enemy {} comes up twice", next),
+                ));
+                break;
+            }
+            match tables.enemies.table.get(&next) {
+                Some((next_stats, ..)) => phase = next_stats.next_phase,
+                None => {
+                    errors.push(ContentError::new(
+                        "enemies",
+                        enemy_entry(id),
+                        format!("next_phase chain leads to unknown enemy {}", next),
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+
+    // The final boss is started by id and its last phase ends the game.
+    let mut final_phase = Some(global::FINAL_BOSS_ID);
+    for _ in 0..=enemy_ids.len() {
+        match final_phase.and_then(|id| tables.enemies.table.get(&id)) {
+            Some((enemy_stats, ..)) if enemy_stats.next_phase.is_some() => {
+                final_phase = enemy_stats.next_phase
+            }
+            _ => break,
+        }
+    }
+    if final_phase != Some(global::FINAL_BOSS_LAST_PHASE_ID) {
+        errors.push(ContentError::new(
+            "enemies",
+            enemy_entry(&global::FINAL_BOSS_ID),
+            format!(
+                "final boss phases must end with enemy {}",
+                global::FINAL_BOSS_LAST_PHASE_ID
+            ),
+        ));
+    }
+
+    // Areas and what can be found in them.
+    let mut reachable_enemies = HashSet::new();
+    let mut reachable_shops = HashSet::new();
+    for id in &area_ids {
+        let area = &tables.areas[id];
+        let entry = format!("area {}", id);
+        for (i, formation) in area.formations.iter().enumerate() {
+            for enemy_id in &formation.enemies {
+                if enemy_ids.contains(enemy_id) {
+                    reachable_enemies.insert(*enemy_id);
+                } else {
+                    errors.push(ContentError::new(
+                        "areas",
+                        &entry,
+                        format!("formation {} has unknown enemy {}", i, enemy_id),
+                    ));
+                }
+            }
+        }
+        for interactable in &area.interactables {
+            match interactable.kind {
+                global::InteractableKind::FinalBoss => {
+                    reachable_enemies.insert(global::FINAL_BOSS_ID);
+                }
+                global::InteractableKind::Shop(shop_id) if shop_ids.contains(&shop_id) => {
+                    reachable_shops.insert(shop_id);
+                }
+                global::InteractableKind::Shop(shop_id) => errors.push(ContentError::new(
+                    "areas",
+                    &entry,
+                    format!("unknown shop {}", shop_id),
+                )),
+            }
+        }
+        // The player has to land somewhere they can stand, and not straight onto another exit.
+        for (i, exit) in area.exits.iter().enumerate() {
+            let message = match tables.areas.get(&exit.to) {
+                None => Some(format!("exit {} leads to unknown area {}", i, exit.to)),
+                Some(to) => {
+                    let (x, y) = exit.arrive;
+                    if to.map.tile(x as i32, y as i32) == tile_map::Tile::Solid {
+                        Some(format!("exit {} doesn't arrive on a walkable tile", i))
+                    } else if to
+                        .exits
+                        .iter()
+                        .any(|e| e.tiles(&to.map).contains(exit.arrive))
+                    {
+                        Some(format!("exit {} arrives on an exit", i))
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(message) = message {
+                errors.push(ContentError::new("areas", &entry, message));
+            }
+        }
+    }
+    // Later phases are reached by beating the one before.
+    let mut phases: Vec<_> = reachable_enemies.iter().copied().collect();
+    while let Some(id) = phases.pop() {
+        if let Some(next) = tables.enemies.table.get(&id).and_then(|e| e.0.next_phase) {
+            if reachable_enemies.insert(next) {
+                phases.push(next);
+            }
+        }
+    }
+    for id in enemy_ids.difference(&reachable_enemies.iter().copied().collect()) {
+        errors.push(ContentError::new(
+            "enemies",
+            enemy_entry(id),
+            "isn't in any area, so it can never be fought",
+        ));
+    }
+
+    // Shops and prices, which refer to items from the item table.
+    let mut priced_items: Vec<_> = shop_table.prices.keys().copied().collect();
+    priced_items.sort_unstable();
+    for item_id in priced_items {
+        if !tables.items.contains_key(&item_id) {
+            errors.push(ContentError::new(
+                "shops",
+                format!("price of item {}", item_id),
+                "unknown item",
+            ));
+        }
+    }
+    for id in &shop_ids {
+        let shop = &shop_table.shops[id];
+        for item_id in &shop.stock {
+            if !tables.items.contains_key(item_id) {
+                errors.push(ContentError::new(
+                    "shops",
+                    format!("shop {} \"{}\"", id, shop.name),
+                    format!("sells unknown item {}", item_id),
+                ));
+            } else if shop_table.buy_price(*item_id).is_none() {
+                errors.push(ContentError::new(
+                    "shops",
+                    format!("shop {} \"{}\"", id, shop.name),
+                    format!("sells item {} which has no price", item_id),
+                ));
+            }
+        }
+    }
+
+    // Items are found in the starting inventory, as loot or in shops.
+    let mut reachable_items: HashSet<_> =
+        global::PlayerItemInventory::new().keys().copied().collect();
+    for id in &reachable_enemies {
+        if let Some((_, _, _, loot_tables)) = tables.enemies.table.get(id) {
+            for loot_table in loot_tables {
+                reachable_items.extend(loot_table.items.iter().map(|(item_id, _)| *item_id));
+            }
+        }
+    }
+    for id in &reachable_shops {
+        reachable_items.extend(shop_table.shops[id].stock.iter().copied());
+    }
+    let mut item_ids: Vec<_> = tables.items.keys().copied().collect();
+    item_ids.sort_unstable();
+    for id in item_ids {
+        if !reachable_items.contains(&id) {
+            errors.push(ContentError::new(
+                "items",
+                format!("item {} \"{}\"", id, tables.items[&id].name),
+                "isn't sold, dropped or given at the start, so it can never be found",
+            ));
+        }
+    }
+
+    errors
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_tables(
+    mut commands: Commands,
+    content: Res<ContentAssets>,
+    image_assets: Res<ImageAssets>,
+    enemies: Res<Assets<EnemyTableAsset>>,
+    items: Res<Assets<ItemTableAsset>>,
+    attacks: Res<Assets<PlayerAttackTableAsset>>,
+    areas: Res<Assets<AreaTableAsset>>,
+    shops: Res<Assets<ShopTableAsset>>,
+) {
+    match Tables::build(
+        enemies.get(&content.enemies).unwrap(),
+        items.get(&content.items).unwrap(),
+        attacks.get(&content.attacks).unwrap(),
+        areas.get(&content.areas).unwrap(),
+        shops.get(&content.shops).unwrap(),
+        &image_assets,
+    ) {
+        Ok(tables) => tables.insert(&mut commands),
+        Err(errors) => {
+            for error in &errors {
+                error!("{}", error);
+            }
+            panic!("content has {} error(s), see above", errors.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_content_is_valid() {
+        if let Err(errors) = Tables::load(Path::new(CONTENT_DIR), &ImageAssets::default()) {
+            panic!(
+                "{}",
+                errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_entry() {
+        let enemies: EnemyTableAsset = ron::from_str(
+            r#"(
+                enemies: [
+                    (
+                        id: 3,
+                        name: "Ghost",
+                        description: "",
+                        damage_variance: 1.5,
+                        sprite: "enemy99",
+                        stats: (hp: 10, mp: 0, strength: 1, wisdom: 1, defense: 1, speed: 1, level: 1, experience: 1, gold: 1),
+                        attacks: [(name: "Boo", damage_modifier: 1.0, mp_use: 0)],
+                        loot: [],
+                    ),
+                ],
+            )"#,
+        )
+        .unwrap();
+
+        let errors = enemies.build(&ImageAssets::default()).err().unwrap();
+        assert_eq!(
+            vec![
+                ContentError::new(
+                    "enemies",
+                    "enemy 3 \"Ghost\"",
+                    "damage_variance must be at least 0 and below 1"
+                ),
+                ContentError::new("enemies", "enemy 3 \"Ghost\"", "unknown sprite \"enemy99\""),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn dangling_ids_and_endless_phases_are_reported() {
+        let mut tables = Tables::for_tests();
+        tables.areas.0.get_mut(&1).unwrap().formations[0]
+            .enemies
+            .push(99);
+        tables.enemies.table.get_mut(&0).unwrap().3[0]
+            .items
+            .push((99, 1));
+        tables.enemies.table.get_mut(&13).unwrap().0.next_phase = Some(12);
+        tables.areas.0.get_mut(&0).unwrap().exits[0].to = 99;
+        tables.shops.shops.get_mut(&0).unwrap().stock.push(98);
+        tables.shops.prices.insert(99, 10);
+
+        let errors: Vec<_> = check_references(&tables)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert!(errors.contains(&"areas: area 1: formation 0 has unknown enemy 99".to_string()));
+        assert!(errors.contains(&"areas: area 0: exit 0 leads to unknown area 99".to_string()));
+        assert!(errors.contains(
+            &"enemies: enemy 0 \"Slime\": loot table 0 drops unknown item 99".to_string()
+        ));
+        assert!(
+            errors.contains(&"shops: shop 0 \"Village Shop\": sells unknown item 98".to_string())
+        );
+        assert!(errors.contains(&"shops: price of item 99: unknown item".to_string()));
+        assert!(errors
+            .iter()
+            .any(|e| e.starts_with("enemies: enemy 11") && e.contains("never ends")));
+    }
+
+    // Walking back out of the tile an exit arrives on should lead back next to where it was taken.
+    #[test]
+    fn every_exit_leads_back() {
+        let tables = Tables::for_tests();
+        let next_to = |rect: tile_map::TileRect, (x, y): (usize, usize)| {
+            x + 1 >= rect.x
+                && x <= rect.x + rect.width
+                && y + 1 >= rect.y
+                && y <= rect.y + rect.height
+        };
+
+        for (id, area) in tables.areas.iter() {
+            for (i, exit) in area.exits.iter().enumerate() {
+                let to = &tables.areas[&exit.to];
+                let leads_back = to.exits.iter().any(|back| {
+                    back.to == *id
+                        && next_to(back.tiles(&to.map), exit.arrive)
+                        && next_to(exit.tiles(&area.map), back.arrive)
+                });
+                assert!(
+                    leads_back,
+                    "exit {} of area {} has no way back from area {}",
+                    i, id, exit.to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn overrides_replace_entries_with_the_same_id() {
+        let dir = std::env::temp_dir().join("rapid_fantasy_override_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("base.items.override.ron"),
+            "(items: [(id: 0, name: \"Big Potion\", item_type: Consumable, stats: (hp: 500))])",
+        )
+        .unwrap();
+
+        let base = Path::new(CONTENT_DIR);
+        let tables = Tables::build_with_overrides(
+            &read(&base.join(ENEMIES_FILE), "enemies").unwrap(),
+            &read(&base.join(ITEMS_FILE), "items").unwrap(),
+            &read(&base.join(ATTACKS_FILE), "attacks").unwrap(),
+            &read(&base.join(AREAS_FILE), "areas").unwrap(),
+            &read(&base.join(SHOPS_FILE), "shops").unwrap(),
+            &Overrides::read(&dir).unwrap(),
+            &ImageAssets::default(),
+        )
+        .unwrap();
+        assert_eq!("Big Potion", tables.items[&0].name);
+        assert_eq!(Tables::for_tests().items.len(), tables.items.len());
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_file() {
+        let dir = std::env::temp_dir().join("rapid_fantasy_content_test");
+        fs::create_dir_all(&dir).unwrap();
+        for file in [
+            ENEMIES_FILE,
+            ITEMS_FILE,
+            ATTACKS_FILE,
+            AREAS_FILE,
+            SHOPS_FILE,
+        ] {
+            fs::copy(Path::new(CONTENT_DIR).join(file), dir.join(file)).unwrap();
+        }
+        fs::write(dir.join(ITEMS_FILE), "(items: [(id: 0, name: \"Potion\")])").unwrap();
+
+        let errors = Tables::load(&dir, &ImageAssets::default()).err().unwrap();
+        assert_eq!(1, errors.len());
+        assert_eq!("items", errors[0].table);
+        assert!(errors[0].entry.ends_with(ITEMS_FILE));
+    }
+}
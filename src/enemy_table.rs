@@ -1,652 +1,164 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::{reflect::TypeUuid, utils::HashMap};
+use serde::Deserialize;
 
 use crate::{
-    global::{
-        Element::*, EnemyAttack, EnemyAttackType::*, EnemyStats, EnemyTable, LootTable, Stats,
-    },
+    combat,
+    content::ContentError,
+    global::{self, Element, EnemyAi, EnemyAttack, EnemyStats, EnemyTable, LootTable, Stats},
     ImageAssets,
 };
 
-impl FromWorld for EnemyTable {
-    fn from_world(world: &mut World) -> Self {
-        let image_assets = world.get_resource_mut::<ImageAssets>().unwrap();
-        let mut enemies = HashMap::new();
+// Contents of an `.enemies.ron` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "35ceb47f-cb13-4769-99f9-af37f84a6888"]
+pub(crate) struct EnemyTableAsset {
+    enemies: Vec<EnemyEntry>,
+}
 
-        enemies.insert(
-            0,
-            (
-                EnemyStats {
-                    id: 0,
-                    name: "Slime".to_string(),
-                    description: "I wonder if it\'s edible?".to_string(),
-                    element: None,
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 39,
-                    hp: 39,
-                    mp_max: 10,
-                    mp: 10,
-                    strength: 8,
-                    wisdom: 8,
-                    defense: 5,
-                    level: 1,
-                    experience: 180,
-                    gold: 50,
-                    battle_sprite: image_assets.enemy1.clone(),
-                },
-                vec![EnemyAttack::new("Bounce", 2., None, 0)],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(0, 9), (5, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (10, 1),
-                            (11, 1),
-                            (12, 1),
-                            (19, 1),
-                            (20, 1),
-                            (21, 1),
-                            (28, 1),
-                            (29, 1),
-                            (30, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            1,
-            (
-                EnemyStats {
-                    id: 1,
-                    name: "Rocky".to_string(),
-                    description: "He\'s a bit of a blockhead.".to_string(),
-                    element: Some(Earth),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 50,
-                    hp: 50,
-                    mp_max: 8,
-                    mp: 8,
-                    strength: 7,
-                    wisdom: 7,
-                    defense: 8,
-                    level: 1,
-                    gold: 70,
-                    experience: 200,
-                    battle_sprite: image_assets.enemy2.clone(),
-                },
-                vec![EnemyAttack::new("Rock throw", 2., None, 0)],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(0, 9), (5, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (10, 1),
-                            (11, 1),
-                            (12, 1),
-                            (19, 1),
-                            (20, 1),
-                            (21, 1),
-                            (28, 1),
-                            (29, 1),
-                            (30, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            2,
-            (
-                EnemyStats {
-                    id: 2,
-                    name: "Mushroom".to_string(),
-                    description: "Shrooms will mess you up, that\'s why they\'re illegal."
-                        .to_string(),
-                    element: Some(Earth),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 48,
-                    hp: 48,
-                    mp_max: 15,
-                    mp: 15,
-                    strength: 5,
-                    wisdom: 10,
-                    defense: 5,
-                    level: 1,
-                    gold: 55,
-                    experience: 250,
-                    battle_sprite: image_assets.enemy3.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Hallucinate", 2., Some(Magic), 5),
-                    EnemyAttack::new("Spores", 2.5, None, 0),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(0, 9), (5, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (10, 1),
-                            (11, 1),
-                            (12, 1),
-                            (19, 1),
-                            (20, 1),
-                            (21, 1),
-                            (28, 1),
-                            (29, 1),
-                            (30, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            3,
-            (
-                EnemyStats {
-                    id: 3,
-                    name: "Duck".to_string(),
-                    description: "He\'s a blast in the bath!".to_string(),
-                    element: Some(Fire),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 72,
-                    hp: 72,
-                    mp_max: 10,
-                    mp: 10,
-                    strength: 13,
-                    wisdom: 10,
-                    defense: 8,
-                    level: 2,
-                    gold: 100,
-                    experience: 1000,
-                    battle_sprite: image_assets.enemy4.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Tackle", 2., None, 0),
-                    EnemyAttack::new("Explosion", 3., Some(Magic), 4),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(1, 9), (6, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (10, 1),
-                            (11, 1),
-                            (12, 1),
-                            (19, 1),
-                            (20, 1),
-                            (21, 1),
-                            (28, 1),
-                            (29, 1),
-                            (30, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            4,
-            (
-                EnemyStats {
-                    id: 4,
-                    name: "Triple A battery".to_string(),
-                    description: "Nobody likes triple A batteries.".to_string(),
-                    element: Some(Electric),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 96,
-                    hp: 96,
-                    mp_max: 15,
-                    mp: 15,
-                    strength: 10,
-                    wisdom: 15,
-                    defense: 10,
-                    level: 2,
-                    gold: 80,
-                    experience: 1200,
-                    battle_sprite: image_assets.enemy5.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Hit", 2., None, 0),
-                    EnemyAttack::new("Spark", 2., Some(Magic), 5),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(1, 9), (6, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (10, 1),
-                            (11, 1),
-                            (12, 1),
-                            (19, 1),
-                            (20, 1),
-                            (21, 1),
-                            (28, 1),
-                            (29, 1),
-                            (30, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            5,
-            (
-                EnemyStats {
-                    id: 5,
-                    name: "Skeleton".to_string(),
-                    description: "Never skip leg day.".to_string(),
-                    element: Some(Dark),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 156,
-                    hp: 156,
-                    mp_max: 15,
-                    mp: 15,
-                    strength: 28,
-                    wisdom: 22,
-                    defense: 15,
-                    level: 3,
-                    gold: 125,
-                    experience: 2700,
-                    battle_sprite: image_assets.enemy6.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Bone Crush", 2., None, 0),
-                    EnemyAttack::new("Curse", 2., Some(Magic), 5),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(2, 9), (7, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (13, 1),
-                            (14, 1),
-                            (15, 1),
-                            (22, 1),
-                            (23, 1),
-                            (24, 1),
-                            (31, 1),
-                            (32, 1),
-                            (33, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            6,
-            (
-                EnemyStats {
-                    id: 6,
-                    name: "Mixtape".to_string(),
-                    description: "An underappreciated mixtape, spits straight fire.".to_string(),
-                    element: Some(Fire),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 135,
-                    hp: 135,
-                    mp_max: 20,
-                    mp: 20,
-                    strength: 20,
-                    wisdom: 28,
-                    defense: 14,
-                    level: 3,
-                    gold: 150,
-                    experience: 3200,
-                    battle_sprite: image_assets.enemy7.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Drop The Beat", 2., Some(Magic), 7),
-                    EnemyAttack::new("Lay A Verse", 1.8, Some(Magic), 4),
-                    EnemyAttack::new("Tangle", 2., None, 0),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(2, 9), (7, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (13, 1),
-                            (14, 1),
-                            (15, 1),
-                            (22, 1),
-                            (23, 1),
-                            (24, 1),
-                            (31, 1),
-                            (32, 1),
-                            (33, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            7,
-            (
-                EnemyStats {
-                    id: 7,
-                    name: "Squirrel".to_string(),
-                    description: "It\'s nuts.".to_string(),
-                    element: None,
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 304,
-                    hp: 304,
-                    mp_max: 20,
-                    mp: 20,
-                    strength: 52,
-                    wisdom: 40,
-                    defense: 25,
-                    level: 4,
-                    gold: 200,
-                    experience: 4500,
-                    battle_sprite: image_assets.enemy8.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Bite", 2., None, 0),
-                    EnemyAttack::new("Gnaw", 1.5, None, 0),
-                    EnemyAttack::new("Acornucopia of pain", 2., Some(Magic), 5),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(3, 9), (8, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (13, 1),
-                            (14, 1),
-                            (15, 1),
-                            (22, 1),
-                            (23, 1),
-                            (24, 1),
-                            (31, 1),
-                            (32, 1),
-                            (33, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            8,
-            (
-                EnemyStats {
-                    id: 8,
-                    name: "Book".to_string(),
-                    description: "Full of questionable knowledge.".to_string(),
-                    element: Some(Light),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 273,
-                    hp: 273,
-                    mp_max: 30,
-                    mp: 30,
-                    strength: 37,
-                    wisdom: 54,
-                    defense: 24,
-                    level: 4,
-                    gold: 225,
-                    experience: 5800,
-                    battle_sprite: image_assets.enemy9.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Body Slam", 2., None, 0),
-                    EnemyAttack::new("Confusion", 2., Some(Magic), 6),
-                    EnemyAttack::new("Face the Book", 2.2, None, 0),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(3, 9), (8, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (13, 1),
-                            (14, 1),
-                            (15, 1),
-                            (22, 1),
-                            (23, 1),
-                            (24, 1),
-                            (31, 1),
-                            (32, 1),
-                            (33, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            9,
-            (
-                EnemyStats {
-                    id: 9,
-                    name: "Battery Rat".to_string(),
-                    description: "This enemy seems familiar...".to_string(),
-                    element: Some(Electric),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 513,
-                    hp: 513,
-                    mp_max: 30,
-                    mp: 30,
-                    strength: 70,
-                    wisdom: 70,
-                    defense: 36,
-                    level: 5,
-                    gold: 300,
-                    experience: 12500,
-                    battle_sprite: image_assets.enemy10.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Thunder Punch", 2., None, 0),
-                    EnemyAttack::new("Thunder Shock", 2., Some(Magic), 10),
-                    EnemyAttack::new("Thunder Wave", 1.8, Some(Magic), 5),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(4, 9), (9, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (16, 1),
-                            (17, 1),
-                            (18, 1),
-                            (25, 1),
-                            (26, 1),
-                            (27, 1),
-                            (34, 1),
-                            (35, 1),
-                            (36, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            10,
-            (
-                EnemyStats {
-                    id: 10,
-                    name: "Penguin".to_string(),
-                    description: "Noot Noot!".to_string(),
-                    element: Some(Water),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 472,
-                    hp: 472,
-                    mp_max: 40,
-                    mp: 40,
-                    strength: 93,
-                    wisdom: 68,
-                    defense: 34,
-                    level: 5,
-                    gold: 350,
-                    experience: 12500,
-                    battle_sprite: image_assets.enemy11.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Water Gun", 2., Some(Magic), 8),
-                    EnemyAttack::new("Doot Doot", 2., None, 0),
-                    EnemyAttack::new("Peck", 1.8, None, 0),
-                ],
-                vec![
-                    LootTable {
-                        no_drop_weight: 10,
-                        items: vec![(4, 9), (9, 6)],
-                    },
-                    LootTable {
-                        no_drop_weight: 13,
-                        items: vec![
-                            (16, 1),
-                            (17, 1),
-                            (18, 1),
-                            (25, 1),
-                            (26, 1),
-                            (27, 1),
-                            (34, 1),
-                            (35, 1),
-                            (36, 1),
-                        ],
-                    },
-                ],
-            ),
-        );
-        enemies.insert(
-            11,
-            (
-                EnemyStats {
-                    id: 11,
-                    name: "Emperor Penguin".to_string(),
-                    description: "".to_string(),
-                    element: Some(Fire),
-                    next_phase: Some(13),
-                },
-                Stats {
-                    hp_max: 576,
-                    hp: 576,
-                    mp_max: 100,
-                    mp: 100,
-                    strength: 92,
-                    wisdom: 117,
-                    defense: 45,
-                    level: 5,
-                    gold: 0,
-                    experience: 0,
-                    battle_sprite: image_assets.enemy12.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Wing", 2., None, 0),
-                    EnemyAttack::new("Ignition", 2., Some(Magic), 5),
-                    EnemyAttack::new("Peck", 2.2, None, 0),
-                    EnemyAttack::new("Flamethrower", 2.2, Some(Magic), 12),
-                ],
-                vec![],
-            ),
-        );
-        enemies.insert(
-            12,
-            (
-                EnemyStats {
-                    id: 12,
-                    name: "Emperor Penguin".to_string(),
-                    description: "".to_string(),
-                    element: Some(Electric),
-                    next_phase: Some(14),
-                },
-                Stats {
-                    hp_max: 645,
-                    hp: 645,
-                    mp_max: 50,
-                    mp: 50,
-                    strength: 118,
-                    wisdom: 90,
-                    defense: 50,
-                    level: 5,
-                    gold: 0,
-                    experience: 0,
-                    battle_sprite: image_assets.enemy13.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Static Peck", 2., None, 0),
-                    EnemyAttack::new("Shock Volt", 2., Some(Magic), 5),
-                    EnemyAttack::new("Tesla Contact", 2.2, None, 0),
-                    EnemyAttack::new("Monarch\'s Thunder", 2.2, Some(Magic), 7),
-                ],
-                vec![],
-            ),
-        );
-        enemies.insert(
-            13,
-            (
-                EnemyStats {
-                    id: 13,
-                    name: "Emperor Penguin".to_string(),
-                    description: "".to_string(),
-                    element: Some(Water),
-                    next_phase: None,
-                },
-                Stats {
-                    hp_max: 304,
-                    hp: 304,
-                    mp_max: 100,
-                    mp: 100,
-                    strength: 78,
-                    wisdom: 80,
-                    defense: 55,
-                    level: 5,
-                    gold: 1000000,
-                    experience: 1000000,
-                    battle_sprite: image_assets.enemy14.clone(),
-                },
-                vec![
-                    EnemyAttack::new("Frigid Onslaught", 2.5, None, 0),
-                    EnemyAttack::new("Tsunami", 2.5, Some(Magic), 7),
-                    EnemyAttack::new("Royal Decree", 0.5, Some(Percentile), 30),
-                ],
-                vec![],
-            ),
-        );
+#[derive(Deserialize)]
+struct EnemyEntry {
+    id: usize,
+    name: String,
+    description: String,
+    #[serde(default)]
+    element: Option<Element>,
+    #[serde(default)]
+    next_phase: Option<usize>,
+    #[serde(default)]
+    unescapable: bool,
+    #[serde(default = "default_damage_variance")]
+    damage_variance: f32,
+    sprite: String, // name of an image in ImageAssets
+    stats: EnemyEntryStats,
+    attacks: Vec<EnemyAttack>,
+    #[serde(default)]
+    ai: EnemyAi,
+    loot: Vec<LootTable>,
+}
+
+// Enemies always start a battle at full HP and MP.
+#[derive(Deserialize)]
+struct EnemyEntryStats {
+    hp: i32,
+    mp: i32,
+    strength: i32,
+    wisdom: i32,
+    defense: i32,
+    speed: i32,
+    #[serde(default)]
+    crit_chance: i32,
+    #[serde(default = "default_crit_damage")]
+    crit_damage: i32,
+    #[serde(default)]
+    evasion: i32,
+    level: i32,
+    experience: i32,
+    gold: i32,
+}
+
+fn default_crit_damage() -> i32 {
+    global::BASE_CRIT_DAMAGE
+}
+
+fn default_damage_variance() -> f32 {
+    combat::DAMAGE_VARIANCE
+}
+
+impl EnemyTableAsset {
+    pub(crate) fn build(
+        &self,
+        image_assets: &ImageAssets,
+    ) -> Result<EnemyTable, Vec<ContentError>> {
+        let mut table = HashMap::new();
+        let mut errors = vec![];
+
+        for enemy in &self.enemies {
+            let entry = format!("enemy {} \"{}\"", enemy.id, enemy.name);
+            let mut error =
+                |message: String| errors.push(ContentError::new("enemies", entry.clone(), message));
+
+            if table.contains_key(&enemy.id) {
+                error("duplicate id".to_string());
+            }
+            if enemy.stats.hp <= 0 {
+                error("hp must be above 0".to_string());
+            }
+            if enemy.stats.speed <= 0 {
+                error("speed must be above 0".to_string());
+            }
+            for (name, chance) in [
+                ("crit_chance", enemy.stats.crit_chance),
+                ("evasion", enemy.stats.evasion),
+            ] {
+                if !(0..=100).contains(&chance) {
+                    error(format!("{} must be between 0 and 100", name));
+                }
+            }
+            if !(0. ..1.).contains(&enemy.damage_variance) {
+                error("damage_variance must be at least 0 and below 1".to_string());
+            }
+            if enemy.attacks.is_empty() {
+                error("has no attacks".to_string());
+            }
+            let desperation = enemy.ai.desperation.as_ref().map(|d| &d.attack);
+            for name in enemy.ai.opener.iter().chain(desperation) {
+                if !enemy.attacks.iter().any(|attack| &attack.name == name) {
+                    error(format!("ai uses unknown attack \"{}\"", name));
+                }
+            }
+            for (i, loot_table) in enemy.loot.iter().enumerate() {
+                if loot_table.get_total_weight() == 0 {
+                    error(format!("loot table {} has no weight", i));
+                }
+            }
+            let battle_sprite = match image_assets.get(&enemy.sprite) {
+                Some(sprite) => sprite,
+                None => {
+                    error(format!("unknown sprite \"{}\"", enemy.sprite));
+                    continue;
+                }
+            };
+
+            table.insert(
+                enemy.id,
+                (
+                    EnemyStats {
+                        id: enemy.id,
+                        name: enemy.name.clone(),
+                        description: enemy.description.clone(),
+                        element: enemy.element.clone(),
+                        next_phase: enemy.next_phase,
+                        unescapable: enemy.unescapable,
+                        damage_variance: enemy.damage_variance,
+                        ai: enemy.ai.clone(),
+                    },
+                    Stats {
+                        hp_max: enemy.stats.hp,
+                        hp: enemy.stats.hp,
+                        mp_max: enemy.stats.mp,
+                        mp: enemy.stats.mp,
+                        strength: enemy.stats.strength,
+                        wisdom: enemy.stats.wisdom,
+                        defense: enemy.stats.defense,
+                        speed: enemy.stats.speed,
+                        crit_chance: enemy.stats.crit_chance,
+                        crit_damage: enemy.stats.crit_damage,
+                        evasion: enemy.stats.evasion,
+                        level: enemy.stats.level,
+                        experience: enemy.stats.experience,
+                        gold: enemy.stats.gold,
+                        battle_sprite,
+                    },
+                    enemy.attacks.clone(),
+                    enemy.loot.clone(),
+                ),
+            );
+        }
 
-        EnemyTable { table: enemies }
+        if errors.is_empty() {
+            Ok(EnemyTable { table })
+        } else {
+            Err(errors)
+        }
     }
 }
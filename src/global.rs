@@ -1,25 +1,47 @@
+use std::time::Duration;
+
 use bevy::{
     math::const_vec2,
     prelude::*,
     utils::{HashMap, HashSet},
 };
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::Deserialize;
 
-use crate::ImageAssets;
+use crate::{
+    tile_map::{Edge, TileMap, TileRect},
+    ImageAssets,
+};
 
 pub(crate) const TEXT_COLOR: Color = Color::BLACK;
 pub(crate) const BACKGROUND_SIZE: Vec2 = const_vec2!([1280., 720.]);
+pub(crate) const START_POSITION: Vec2 = const_vec2!([640., 360.]); // middle of the first area
 pub(crate) const BACKGROUND_COLOR: Color = Color::BLACK;
 
 pub(crate) const NORMAL_BUTTON: Color = Color::rgb(0.6, 0.6, 0.6);
 pub(crate) const HOVERED_BUTTON: Color = Color::rgb(0.8, 0.8, 0.8);
 pub(crate) const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 pub(crate) const PRESSED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+pub(crate) const DISABLED_BUTTON: Color = Color::rgba(0.6, 0.6, 0.6, 0.4);
 
 pub(crate) const MENU_TOGGLE_DURATION: f32 = 0.2;
 
 pub(crate) const XP_TABLE: [i32; 5] = [1000, 8000, 27000, 64000, 1];
 
+pub(crate) const FINAL_BOSS_ID: usize = 11; // first phase
+pub(crate) const FINAL_BOSS_LAST_PHASE_ID: usize = 13;
+
+pub(crate) const MAX_FORMATION_SIZE: usize = 3;
+
+// the player's speed before equipment
+pub(crate) const BASE_SPEED: i32 = 10;
+
+// the player's chances before accessories, all in percent
+pub(crate) const BASE_CRIT_CHANCE: i32 = 5;
+pub(crate) const BASE_CRIT_DAMAGE: i32 = 50;
+pub(crate) const BASE_EVASION: i32 = 5;
+
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub(crate) enum GameState {
     Initialization,
@@ -27,6 +49,7 @@ pub(crate) enum GameState {
     Overworld,
     Menu,
     Battle,
+    Shop,
     Lose,
     FinalVictory,
     Exit,
@@ -35,38 +58,77 @@ pub(crate) enum GameState {
 #[derive(Default)]
 pub(crate) struct Area {
     pub(crate) id: usize,
-    pub(crate) enemies: Vec<usize>, // enemy ids
+    pub(crate) formations: Vec<Formation>, // empty in safe areas
     pub(crate) background: Handle<Image>,
+    pub(crate) map: TileMap,
+    pub(crate) exits: Vec<Exit>,
+    pub(crate) interactables: Vec<Interactable>,
+}
+
+// A way out of an area. Walking onto any of its tiles takes the player to area `to`, on the
+// `arrive` tile (x, y).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) struct Exit {
+    pub(crate) at: ExitShape,
+    pub(crate) to: usize,
+    pub(crate) arrive: (usize, usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) enum ExitShape {
+    Edge(Edge), // a whole side of the area
+    Rect(TileRect),
 }
 
-impl Area {
-    fn new(id: usize, enemies: Vec<usize>, background: Handle<Image>) -> Area {
-        Area {
-            id,
-            enemies,
-            background,
+impl Exit {
+    pub(crate) fn tiles(&self, map: &TileMap) -> TileRect {
+        match self.at {
+            ExitShape::Edge(edge) => map.edge(edge),
+            ExitShape::Rect(rect) => rect,
         }
     }
 }
 
-#[derive(Deref)]
-pub(crate) struct Areas(HashMap<usize, Area>);
+// A group of enemies that can be met together in an area.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) struct Formation {
+    pub(crate) enemies: Vec<usize>, // enemy ids, from left to right
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32, // how likely it is to be met over the area's other formations
+}
 
-impl FromWorld for Areas {
-    fn from_world(world: &mut World) -> Self {
-        let image_assets = world.get_resource_mut::<ImageAssets>().unwrap();
-        let mut areas = HashMap::new();
-        areas.insert(0, Area::new(0, vec![], image_assets.area0.clone()));
-        areas.insert(1, Area::new(1, vec![0, 1, 2], image_assets.area1.clone()));
-        areas.insert(2, Area::new(2, vec![3, 4], image_assets.area2.clone()));
-        areas.insert(3, Area::new(3, vec![5, 6], image_assets.area3.clone()));
-        areas.insert(4, Area::new(4, vec![7, 8], image_assets.area4.clone()));
-        areas.insert(5, Area::new(5, vec![9, 10], image_assets.area5.clone()));
+// Formations and enemy attacks that aren't given a weight are as likely as each other.
+fn default_weight() -> u32 {
+    1
+}
 
-        Areas(areas)
-    }
+// Enemy ids of the formation the player ran into, set before going into battle.
+#[derive(Default)]
+pub(crate) struct Encounter {
+    pub(crate) enemies: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) enum InteractableKind {
+    FinalBoss,
+    Shop(usize), // shop id
+}
+
+// Something in an area the player can walk up to and interact with.
+#[derive(Clone)]
+pub(crate) struct Interactable {
+    pub(crate) kind: InteractableKind,
+    pub(crate) position: Vec2,
+    pub(crate) sprite: Handle<Image>,
 }
 
+#[derive(Deref)]
+pub(crate) struct Areas(pub(crate) HashMap<usize, Area>);
+
+// The camera that looks at the overworld, as opposed to the one drawing the UI.
+#[derive(Component)]
+pub(crate) struct WorldCamera;
+
 #[derive(Default)]
 pub(crate) struct Player {
     pub(crate) entity: Option<Entity>,
@@ -75,22 +137,147 @@ pub(crate) struct Player {
     pub(crate) stats: Stats,
     pub(crate) limit: u8,
     pub(crate) area: usize,
+    pub(crate) status_effects: StatusEffects, // only last for a battle
 }
 
 impl Player {
     pub(crate) fn new(image_assets: &ImageAssets) -> Self {
         Player {
             entity: None,
-            x: 0.,
-            y: 0.,
+            x: START_POSITION.x,
+            y: START_POSITION.y,
             stats: Stats::new(image_assets.player_battle.clone()),
             limit: 0,
             area: 0,
+            status_effects: StatusEffects::default(),
         }
     }
 }
 
-#[derive(Component, Clone, Default)]
+// Everything that makes up the player's progress in a game, inserted as resources when a game
+// starts or is loaded.
+pub(crate) struct PlayerProgress {
+    pub(crate) player: Player,
+    pub(crate) run_stats: RunStats,
+    pub(crate) equipment_equipped: PlayerEquipmentEquipped,
+    pub(crate) magic_equipped: PlayerMagicEquipped,
+    pub(crate) limit_equipped: PlayerLimitEquipped,
+    pub(crate) item_inventory: PlayerItemInventory,
+    pub(crate) attack_inventory: PlayerAttackInventory,
+    pub(crate) rng: GameRng,
+}
+
+impl PlayerProgress {
+    pub(crate) fn new(
+        image_assets: &ImageAssets,
+        attack_table: &PlayerAttackTable,
+        seed: u64,
+    ) -> Self {
+        PlayerProgress {
+            player: Player::new(image_assets),
+            run_stats: RunStats::default(),
+            equipment_equipped: PlayerEquipmentEquipped::default(),
+            magic_equipped: PlayerMagicEquipped::new(attack_table),
+            limit_equipped: PlayerLimitEquipped::new(attack_table),
+            item_inventory: PlayerItemInventory::new(),
+            attack_inventory: PlayerAttackInventory::new(attack_table),
+            rng: GameRng::new(seed),
+        }
+    }
+
+    pub(crate) fn insert(self, commands: &mut Commands) {
+        commands.insert_resource(self.player);
+        commands.insert_resource(self.run_stats);
+        commands.insert_resource(self.equipment_equipped);
+        commands.insert_resource(self.magic_equipped);
+        commands.insert_resource(self.limit_equipped);
+        commands.insert_resource(self.item_inventory);
+        commands.insert_resource(self.attack_inventory);
+        commands.insert_resource(self.rng);
+    }
+}
+
+// Seeded randomness for everything that changes how a playthrough goes, so that a run can be
+// reproduced from its seed. Encounters, loot and enemy AI each draw from their own stream, so e.g.
+// an extra loot roll doesn't change which enemies show up afterwards.
+pub(crate) struct GameRng {
+    pub(crate) seed: u64,
+    pub(crate) encounters: ChaCha12Rng,
+    pub(crate) loot: ChaCha12Rng,
+    pub(crate) ai: ChaCha12Rng, // also rolls status effects for the player's attacks
+}
+
+impl GameRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        GameRng::restore(seed, [0; 3])
+    }
+
+    // Picks up each stream where a saved game left off.
+    pub(crate) fn restore(seed: u64, positions: [u64; 3]) -> Self {
+        let stream = |stream: u64| {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            rng.set_stream(stream);
+            rng.set_word_pos(positions[stream as usize] as u128);
+            rng
+        };
+        GameRng {
+            seed,
+            encounters: stream(0),
+            loot: stream(1),
+            ai: stream(2),
+        }
+    }
+
+    // How far into each stream the game is, in the order restore() takes them.
+    pub(crate) fn positions(&self) -> [u64; 3] {
+        [&self.encounters, &self.loot, &self.ai].map(|rng| rng.get_word_pos() as u64)
+    }
+}
+
+// Seed for new games, from `--seed` on the command line. New games get a random seed otherwise.
+#[derive(Default)]
+pub(crate) struct NewGameSeed(pub(crate) Option<u64>);
+
+impl NewGameSeed {
+    pub(crate) fn pick(&self) -> u64 {
+        self.0.unwrap_or_else(|| thread_rng().gen())
+    }
+}
+
+// How long the current frame lasts in the game. Everything that moves or counts down goes by this
+// rather than bevy's Time, which can't be told how long a frame took, so that replays and tests
+// can decide for themselves.
+#[derive(Default)]
+pub(crate) struct FrameTime(pub(crate) Duration);
+
+impl FrameTime {
+    pub(crate) fn delta(&self) -> Duration {
+        self.0
+    }
+
+    pub(crate) fn delta_seconds(&self) -> f32 {
+        self.0.as_secs_f32()
+    }
+}
+
+// Progress of the current playthrough, shown on the victory screen.
+#[derive(Default)]
+pub(crate) struct RunStats {
+    pub(crate) play_time: f32, // seconds
+    pub(crate) battles_won: u32,
+}
+
+pub(crate) fn format_play_time(play_time: f32) -> String {
+    let seconds = play_time as u32;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[derive(Component, Clone, Default, Debug, PartialEq)]
 pub(crate) struct Stats {
     pub(crate) hp_max: i32,
     pub(crate) mp_max: i32,
@@ -99,6 +286,10 @@ pub(crate) struct Stats {
     pub(crate) strength: i32,
     pub(crate) wisdom: i32,
     pub(crate) defense: i32,
+    pub(crate) speed: i32, // decides how often a side gets to act in battle
+    pub(crate) crit_chance: i32, // percent
+    pub(crate) crit_damage: i32, // extra percent of damage dealt on a critical hit
+    pub(crate) evasion: i32, // percent
     pub(crate) level: i32,
     pub(crate) experience: i32,
     pub(crate) gold: i32,
@@ -114,6 +305,10 @@ impl Stats {
         self.strength += item_stats.strength;
         self.wisdom += item_stats.wisdom;
         self.defense += item_stats.defense;
+        self.speed += item_stats.speed;
+        self.crit_chance += item_stats.crit_chance;
+        self.crit_damage += item_stats.crit_damage;
+        self.evasion += item_stats.evasion;
     }
 
     pub(crate) fn subtract_item_stats(&mut self, item_stats: &ItemStats) {
@@ -124,43 +319,74 @@ impl Stats {
         self.strength -= item_stats.strength;
         self.wisdom -= item_stats.wisdom;
         self.defense -= item_stats.defense;
+        self.speed -= item_stats.speed;
+        self.crit_chance -= item_stats.crit_chance;
+        self.crit_damage -= item_stats.crit_damage;
+        self.evasion -= item_stats.evasion;
     }
 }
 
-#[derive(Clone, Hash, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Deserialize)]
+pub(crate) enum StatusEffectKind {
+    Poison,
+    Burn,
+    Paralysis,
+    Silence,
+    Regen,
+}
+
+// Chance for an attack to inflict a status effect.
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize)]
+pub(crate) struct StatusEffectProc {
+    pub(crate) kind: StatusEffectKind,
+    pub(crate) chance: u8, // percent
+    pub(crate) turns: u8,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct StatusEffect {
+    pub(crate) kind: StatusEffectKind,
+    pub(crate) turns_left: u8,
+}
+
+#[derive(Component, Clone, Default, PartialEq, Debug, Deref, DerefMut)]
+pub(crate) struct StatusEffects(pub(crate) Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub(crate) fn has(&self, kind: StatusEffectKind) -> bool {
+        self.iter().any(|effect| effect.kind == kind)
+    }
+
+    // Inflicts the effect, refreshing its duration if it's already active.
+    pub(crate) fn inflict(&mut self, kind: StatusEffectKind, turns: u8) {
+        match self.iter_mut().find(|effect| effect.kind == kind) {
+            Some(effect) => effect.turns_left = effect.turns_left.max(turns),
+            None => self.push(StatusEffect {
+                kind,
+                turns_left: turns,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, Debug, Deserialize)]
 pub(crate) enum PlayerAttackType {
     Limit,
     Magic,
 }
 
-#[derive(Default, Clone, Component, Hash, PartialEq, Eq, Debug)]
+#[derive(Default, Clone, Component, Hash, PartialEq, Eq, Debug, Deserialize)]
 pub(crate) struct PlayerAttack {
     pub(crate) id: usize,
     pub(crate) name: String,
+    #[serde(default)]
     pub(crate) attack_type: Option<PlayerAttackType>, // None = standard attack
-    pub(crate) element: Option<Element>,              // None = no type
+    #[serde(default)]
+    pub(crate) element: Option<Element>, // None = no type
     pub(crate) mp_use: i32,
     pub(crate) tier: u8,
-}
-
-impl PlayerAttack {
-    pub(crate) fn new(
-        id: usize,
-        name: &str,
-        attack_type: Option<PlayerAttackType>,
-        element: Option<Element>,
-        mp_use: i32,
-        tier: u8,
-    ) -> Self {
-        PlayerAttack {
-            id,
-            name: name.to_string(),
-            attack_type,
-            element,
-            mp_use,
-            tier,
-        }
-    }
+    #[serde(default)]
+    pub(crate) effect: Option<StatusEffectProc>,
 }
 
 pub(crate) struct PlayerAttackTable {
@@ -257,6 +483,10 @@ impl Stats {
             strength: 12,
             wisdom: 12,
             defense: 5,
+            speed: BASE_SPEED,
+            crit_chance: BASE_CRIT_CHANCE,
+            crit_damage: BASE_CRIT_DAMAGE,
+            evasion: BASE_EVASION,
             level: 1,
             experience: 0,
             gold: 0,
@@ -268,7 +498,7 @@ impl Stats {
 #[derive(Component)]
 pub(crate) struct LimitBreak(i32);
 
-#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+#[derive(Clone, Eq, PartialEq, Debug, Hash, Deserialize)]
 pub(crate) enum Element {
     Fire,
     Earth,
@@ -278,13 +508,100 @@ pub(crate) enum Element {
     Dark,
 } // TODO: implement damage lookup table
 
-#[derive(Default, Component)]
+// An enemy in battle. Each one is spawned as an entity with these as its components.
+#[derive(Bundle, Clone, Default)]
 pub(crate) struct Enemy {
-    pub(crate) entity: Option<Entity>,
     pub(crate) stats: Stats,
     pub(crate) enemy_stats: EnemyStats,
-    pub(crate) attacks: Vec<EnemyAttack>,
-    pub(crate) loot_table: Vec<LootTable>,
+    pub(crate) attacks: EnemyAttacks,
+    pub(crate) loot_table: EnemyLoot,
+    pub(crate) status_effects: StatusEffects,
+    pub(crate) memory: EnemyMemory,
+}
+
+#[derive(Component, Clone, Default, Deref, DerefMut)]
+pub(crate) struct EnemyAttacks(pub(crate) Vec<EnemyAttack>);
+
+#[derive(Component, Clone, Default, Deref, DerefMut)]
+pub(crate) struct EnemyLoot(pub(crate) Vec<LootTable>);
+
+// Every component of an enemy, for queries that need all of it.
+pub(crate) type EnemyComponents = (
+    &'static mut Stats,
+    &'static mut EnemyStats,
+    &'static mut EnemyAttacks,
+    &'static mut EnemyLoot,
+    &'static mut StatusEffects,
+    &'static mut EnemyMemory,
+);
+
+impl Enemy {
+    pub(crate) fn from_table(enemy_table: &EnemyTable, id: usize) -> Self {
+        let mut enemy = Enemy::default();
+        enemy.set_from_table(enemy_table, id);
+        enemy
+    }
+
+    // Replaces the enemy with the given enemy table entry.
+    pub(crate) fn set_from_table(&mut self, enemy_table: &EnemyTable, id: usize) {
+        let (enemy_stats, stats, attacks, loot_table) = enemy_table.table.get(&id).unwrap().clone();
+        self.stats = stats;
+        self.enemy_stats = enemy_stats;
+        self.attacks = EnemyAttacks(attacks);
+        self.loot_table = EnemyLoot(loot_table);
+        self.status_effects = StatusEffects::default();
+        self.memory = EnemyMemory::default();
+    }
+
+    // Gathers an enemy's components into a whole Enemy for code that works on one, like the battle
+    // rules, and puts them back on the entity afterwards.
+    pub(crate) fn lend<R>(
+        (mut stats, mut enemy_stats, mut attacks, mut loot_table, mut status_effects, mut memory): (
+            Mut<Stats>,
+            Mut<EnemyStats>,
+            Mut<EnemyAttacks>,
+            Mut<EnemyLoot>,
+            Mut<StatusEffects>,
+            Mut<EnemyMemory>,
+        ),
+        f: impl FnOnce(&mut Enemy) -> R,
+    ) -> R {
+        let mut enemy = Enemy {
+            stats: std::mem::take(&mut *stats),
+            enemy_stats: std::mem::take(&mut *enemy_stats),
+            attacks: std::mem::take(&mut *attacks),
+            loot_table: std::mem::take(&mut *loot_table),
+            status_effects: std::mem::take(&mut *status_effects),
+            memory: std::mem::take(&mut *memory),
+        };
+        let result = f(&mut enemy);
+        *stats = enemy.stats;
+        *enemy_stats = enemy.enemy_stats;
+        *attacks = enemy.attacks;
+        *loot_table = enemy.loot_table;
+        *status_effects = enemy.status_effects;
+        *memory = enemy.memory;
+        result
+    }
+
+    // Picks up changes to the enemy's table entry mid-fight, keeping its current HP, MP and status
+    // effects.
+    #[cfg(any(feature = "dev", test))]
+    pub(crate) fn refresh_from_table(&mut self, enemy_table: &EnemyTable) {
+        let id = self.enemy_stats.id;
+        if !enemy_table.table.contains_key(&id) {
+            return;
+        }
+
+        let (hp, mp) = (self.stats.hp, self.stats.mp);
+        let status_effects = std::mem::take(&mut self.status_effects);
+        let memory = std::mem::take(&mut self.memory);
+        self.set_from_table(enemy_table, id);
+        self.stats.hp = hp.min(self.stats.hp_max);
+        self.stats.mp = mp.min(self.stats.mp_max);
+        self.status_effects = status_effects;
+        self.memory = memory;
+    }
 }
 
 #[derive(Component, Clone, Default)]
@@ -293,44 +610,86 @@ pub(crate) struct EnemyStats {
     pub(crate) name: String,
     pub(crate) description: String,
     pub(crate) element: Option<Element>,
-    pub(crate) next_phase: Option<usize>, // id? maybe another enemystats?
+    pub(crate) next_phase: Option<usize>, // id of the enemy this one turns into when defeated
+    pub(crate) unescapable: bool,         // player can't run from it
+    pub(crate) damage_variance: f32,      // see combat::DAMAGE_VARIANCE
+    pub(crate) ai: EnemyAi,
 }
 
-#[derive(Clone)]
+// How an enemy picks its attacks, on top of their weights and conditions. Attacks are referred to
+// by name.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct EnemyAi {
+    pub(crate) opener: Vec<String>, // used in order on the enemy's first turns
+    pub(crate) desperation: Option<Desperation>,
+    pub(crate) mp_reserve: i32, // MP only spent on attacks whose condition is met
+}
+
+// Attack used once, the first time the enemy's HP drops below a share of its max HP.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Desperation {
+    pub(crate) hp_below: u8, // percent
+    pub(crate) attack: String,
+}
+
+// What an enemy remembers during a fight, for its AI. Every phase starts with a clean slate.
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub(crate) struct EnemyMemory {
+    pub(crate) turns: u32,         // turns taken so far
+    pub(crate) desperate: bool,    // desperation attack has been used
+    pub(crate) weakness_hit: bool, // player's last attack hit its weakness
+}
+
+#[derive(Clone, Deserialize)]
 pub(crate) enum EnemyAttackType {
     Magic,
     Percentile,
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone, Deserialize)]
 pub(crate) struct EnemyAttack {
     pub(crate) name: String,
     pub(crate) damage_modifier: f32,
     pub(crate) mp_use: i32,
+    #[serde(default)]
     pub(crate) attack_type: Option<EnemyAttackType>, // None = Physical
+    #[serde(default)]
+    pub(crate) effect: Option<StatusEffectProc>,
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32, // how likely it is to be picked over the enemy's other attacks
+    #[serde(default)]
+    pub(crate) condition: Option<AttackCondition>, // only used when this holds
 }
 
-impl EnemyAttack {
-    pub(crate) fn new(
-        name: &str,
-        damage_modifier: f32,
-        attack_type: Option<EnemyAttackType>,
-        mp_use: i32,
-    ) -> Self {
+// Matches the content files, where leaving the weight out means 1 rather than never being picked.
+impl Default for EnemyAttack {
+    fn default() -> Self {
         EnemyAttack {
-            name: name.to_string(),
-            damage_modifier,
-            attack_type,
-            mp_use,
+            name: String::new(),
+            damage_modifier: 0.,
+            mp_use: 0,
+            attack_type: None,
+            effect: None,
+            weight: default_weight(),
+            condition: None,
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub(crate) enum AttackCondition {
+    HpBelow(u8),       // enemy's HP is below this percent
+    PlayerHpBelow(u8), // player's HP is below this percent
+    PlayerBlocking,
+    WeaknessHit, // player's last attack hit the enemy's weakness
+}
+
 pub(crate) struct EnemyTable {
     pub(crate) table: HashMap<usize, (EnemyStats, Stats, Vec<EnemyAttack>, Vec<LootTable>)>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub(crate) enum ItemType {
     Consumable,
     Weapon,
@@ -338,7 +697,8 @@ pub(crate) enum ItemType {
     Accessory,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
 pub(crate) struct ItemStats {
     pub(crate) hp_max: i32,
     pub(crate) mp_max: i32,
@@ -347,48 +707,30 @@ pub(crate) struct ItemStats {
     pub(crate) strength: i32,
     pub(crate) wisdom: i32,
     pub(crate) defense: i32,
+    pub(crate) speed: i32,
+    pub(crate) crit_chance: i32,
+    pub(crate) crit_damage: i32,
+    pub(crate) evasion: i32,
 }
 
 impl ItemStats {
-    pub(crate) fn new_potion(hp: i32, mp: i32) -> Self {
-        Self {
-            hp_max: 0,
-            mp_max: 0,
-            hp,
-            mp,
-            strength: 0,
-            wisdom: 0,
-            defense: 0,
-        }
-    }
-
-    pub(crate) fn new_eqiup(
-        hp_max: i32,
-        mp_max: i32,
-        strength: i32,
-        wisdom: i32,
-        defense: i32,
-    ) -> Self {
-        Self {
-            hp_max,
-            mp_max,
-            hp: 0,
-            mp: 0,
-            strength,
-            wisdom,
-            defense,
-        }
-    }
-
     pub(crate) fn print_equip_stats(&self) -> String {
         format!(
-            "Max HP: {}, Max MP: {},\nStrength: {},\nWisdom: {},\nDefense: {}",
-            self.hp_max, self.mp_max, self.strength, self.wisdom, self.defense
+            "Max HP: {}, Max MP: {},\nStrength: {},\nWisdom: {},\nDefense: {},\nSpeed: {},\nCrit: {}% (+{}%), Evasion: {}%",
+            self.hp_max,
+            self.mp_max,
+            self.strength,
+            self.wisdom,
+            self.defense,
+            self.speed,
+            self.crit_chance,
+            self.crit_damage,
+            self.evasion
         )
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize)]
 pub(crate) struct Item {
     pub(crate) id: usize,
     pub(crate) name: String,
@@ -396,29 +738,63 @@ pub(crate) struct Item {
     pub(crate) stats: ItemStats,
 }
 
-impl Item {
-    pub(crate) fn new(id: usize, name: &str, item_type: ItemType, stats: ItemStats) -> Self {
-        Self {
-            id,
+#[derive(Deref)]
+pub(crate) struct ItemTable(pub(crate) HashMap<usize, Item>);
+
+#[derive(Clone)]
+pub(crate) struct Shop {
+    pub(crate) name: String,
+    pub(crate) stock: Vec<usize>, // item ids
+    // Share of an item's price the shop pays when buying it from the player.
+    pub(crate) consumable_sell_ratio: f32,
+    pub(crate) equipment_sell_ratio: f32,
+}
+
+impl Shop {
+    pub(crate) fn new(
+        name: &str,
+        stock: Vec<usize>,
+        consumable_sell_ratio: f32,
+        equipment_sell_ratio: f32,
+    ) -> Self {
+        Shop {
             name: name.to_string(),
-            item_type,
-            stats,
+            stock,
+            consumable_sell_ratio,
+            equipment_sell_ratio,
         }
     }
 }
 
-#[derive(Deref)]
-pub(crate) struct ItemTable(pub(crate) HashMap<usize, Item>);
+pub(crate) struct ShopTable {
+    pub(crate) shops: HashMap<usize, Shop>,
+    pub(crate) prices: HashMap<usize, i32>, // item id, price
+}
 
-#[derive(Clone)]
+impl ShopTable {
+    pub(crate) fn buy_price(&self, item_id: usize) -> Option<i32> {
+        self.prices.get(&item_id).copied()
+    }
+
+    pub(crate) fn sell_price(&self, shop_id: usize, item: &Item) -> Option<i32> {
+        let shop = self.shops.get(&shop_id)?;
+        let ratio = match item.item_type {
+            ItemType::Consumable => shop.consumable_sell_ratio,
+            _ => shop.equipment_sell_ratio,
+        };
+        Some((self.buy_price(item.id)? as f32 * ratio) as i32)
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub(crate) struct LootTable {
     pub(crate) no_drop_weight: usize,
     pub(crate) items: Vec<(usize, usize)>, // item id, weight
 }
 
 impl LootTable {
-    pub(crate) fn get_item_id(&self) -> Option<usize> {
-        let roll = thread_rng().gen_range(0..self.get_total_weight());
+    pub(crate) fn get_item_id(&self, rng: &mut impl Rng) -> Option<usize> {
+        let roll = rng.gen_range(0..self.get_total_weight());
 
         let mut cur_weight = self.no_drop_weight;
         for item in &self.items {
@@ -431,7 +807,7 @@ impl LootTable {
         None
     }
 
-    fn get_total_weight(&self) -> usize {
+    pub(crate) fn get_total_weight(&self) -> usize {
         self.no_drop_weight + self.items.iter().map(|x| x.1).sum::<usize>()
     }
 }
@@ -440,6 +816,32 @@ impl LootTable {
 mod tests {
     use super::*;
 
+    #[test]
+    fn attacks_built_in_code_have_the_same_weight_as_in_content_files() {
+        let attack: EnemyAttack =
+            ron::from_str("(name: \"Bite\", damage_modifier: 1.0, mp_use: 0)").unwrap();
+        assert_eq!(attack.weight, EnemyAttack::default().weight);
+    }
+
+    #[test]
+    fn enemy_refresh_keeps_current_hp() {
+        let mut enemy_table = crate::content::Tables::for_tests().enemies;
+        let mut enemy = Enemy::default();
+        enemy.set_from_table(&enemy_table, 0);
+        enemy.stats.hp = 7;
+        enemy.status_effects.inflict(StatusEffectKind::Poison, 2);
+
+        let (_, stats, attacks, _) = enemy_table.table.get_mut(&0).unwrap();
+        stats.strength = 99;
+        attacks[0].damage_modifier = 5.;
+        enemy.refresh_from_table(&enemy_table);
+
+        assert_eq!(7, enemy.stats.hp);
+        assert_eq!(99, enemy.stats.strength);
+        assert_eq!(5., enemy.attacks[0].damage_modifier);
+        assert!(enemy.status_effects.has(StatusEffectKind::Poison));
+    }
+
     #[test]
     fn loot_table_get_total_weight() {
         let loot_table = LootTable {
@@ -455,7 +857,8 @@ mod tests {
             no_drop_weight: 6,
             items: vec![],
         };
-        assert_eq!(None, loot_table.get_item_id());
+        let mut rng = GameRng::new(0).loot;
+        assert!((0..100).all(|_| loot_table.get_item_id(&mut rng).is_none()));
     }
 
     #[test]
@@ -464,15 +867,74 @@ mod tests {
             no_drop_weight: 0,
             items: vec![(1, 3)],
         };
-        assert_eq!(Some(1), loot_table.get_item_id());
+        let mut rng = GameRng::new(0).loot;
+        assert!((0..100).all(|_| loot_table.get_item_id(&mut rng) == Some(1)));
     }
 
     #[test]
-    fn loot_table_get_item() {
+    fn loot_table_get_item_by_weight() {
         let loot_table = LootTable {
-            no_drop_weight: 0,
-            items: vec![(32, 1)],
+            no_drop_weight: 1,
+            items: vec![(1, 1), (32, 2)],
+        };
+        let mut rng = GameRng::new(0).loot;
+        let mut drops = HashMap::new();
+        for _ in 0..400 {
+            *drops.entry(loot_table.get_item_id(&mut rng)).or_insert(0) += 1;
+        }
+        assert_eq!(3, drops.len());
+        assert!(drops[&Some(32)] > drops[&Some(1)]);
+        assert!(drops[&Some(32)] > drops[&None]);
+    }
+
+    #[test]
+    fn loot_table_rolls_are_reproducible() {
+        let loot_table = LootTable {
+            no_drop_weight: 3,
+            items: vec![(1, 2), (2, 5)],
         };
-        assert_eq!(Some(32), loot_table.get_item_id());
+        let rolls = |rng: &mut ChaCha12Rng| {
+            (0..50)
+                .map(|_| loot_table.get_item_id(rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            rolls(&mut GameRng::new(7).loot),
+            rolls(&mut GameRng::new(7).loot)
+        );
+        assert_ne!(
+            rolls(&mut GameRng::new(7).loot),
+            rolls(&mut GameRng::new(8).loot)
+        );
+    }
+
+    #[test]
+    fn game_rng_streams_are_independent() {
+        let mut rng = GameRng::new(7);
+        let encounter: u32 = rng.encounters.gen();
+        assert_ne!(encounter, rng.loot.gen::<u32>());
+
+        // Drawing loot doesn't change the next encounter.
+        let mut other = GameRng::new(7);
+        for _ in 0..10 {
+            other.loot.gen::<u32>();
+        }
+        assert_eq!(encounter, other.encounters.gen::<u32>());
+    }
+
+    #[test]
+    fn game_rng_restores_positions() {
+        let mut rng = GameRng::new(42);
+        for _ in 0..5 {
+            rng.encounters.gen::<u64>();
+            rng.ai.gen::<u32>();
+        }
+        let mut restored = GameRng::restore(rng.seed, rng.positions());
+        assert_eq!(
+            rng.encounters.gen::<u64>(),
+            restored.encounters.gen::<u64>()
+        );
+        assert_eq!(rng.loot.gen::<u64>(), restored.loot.gen::<u64>());
+        assert_eq!(rng.ai.gen::<u64>(), restored.ai.gen::<u64>());
     }
 }
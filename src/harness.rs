@@ -0,0 +1,504 @@
+// Runs the whole game without a window, for tests that play through several screens. Images and
+// fonts are left as empty handles and the content tables are read straight from disk.
+use std::{
+    fmt::Debug,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bevy::{
+    ecs::event::Events,
+    input::{
+        gamepad::{Gamepad, GamepadEventRaw, GamepadEventType},
+        keyboard::KeyboardInput,
+        ElementState, InputPlugin,
+    },
+    prelude::*,
+};
+
+use crate::{
+    battle::EnemySlot,
+    content, global,
+    replay::{Recording, ReplayPlugin},
+    save, settings, FontAssets, FrameTimeSystem, ImageAssets,
+};
+
+// Frames to wait for something to happen before failing the test.
+const MAX_FRAMES: usize = 3000;
+// Frame rate the game runs at unless a test asks for another.
+const FRAME_RATE: f32 = 60.;
+// Seconds spent walking one way while pacing, short enough to stay clear of the area's exits.
+const PACE_SECONDS: f32 = 0.5;
+
+pub(crate) struct Harness {
+    pub(crate) app: App,
+    frame_rate: f32,
+    skip_delays: bool,
+}
+
+// How long every frame takes in game time.
+struct FixedFrameTime(Duration);
+
+impl Harness {
+    // Starts the game on the main menu. New games use the given seed.
+    pub(crate) fn new(seed: u64) -> Self {
+        Harness::with_frame_rate(seed, FRAME_RATE)
+    }
+
+    pub(crate) fn with_frame_rate(seed: u64, frame_rate: f32) -> Self {
+        let mut harness = Harness::empty(seed, frame_rate);
+        crate::add_game_plugins(&mut harness.app);
+        harness
+    }
+
+    // Records the session to a file like `--record` does. Delays play out in full, as they would
+    // when the recording is replayed in the game.
+    pub(crate) fn record(seed: u64, path: &Path) -> Self {
+        let mut harness = Harness::empty(seed, FRAME_RATE);
+        harness.skip_delays = false;
+        crate::add_game_plugins(&mut harness.app);
+        harness
+            .app
+            .add_plugin(ReplayPlugin::Record(path.to_path_buf()));
+        harness
+    }
+
+    // Plays back a recording, taking every frame as long as it took when recorded. Run it until
+    // the Replayer has finished.
+    pub(crate) fn replay(recording: &Recording) -> Self {
+        let mut harness = Harness::empty(recording.seed, FRAME_RATE);
+        harness.skip_delays = false;
+        harness.app.world.remove_resource::<FixedFrameTime>();
+        crate::add_game_plugins(&mut harness.app);
+        harness
+            .app
+            .add_plugin(ReplayPlugin::Replay(recording.clone()));
+        harness
+    }
+
+    // Everything but the game's own plugins.
+    fn empty(seed: u64, frame_rate: f32) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(FixedFrameTime(Duration::from_secs_f32(1. / frame_rate)))
+            .add_system_to_stage(CoreStage::First, fixed_frame_time.after(FrameTimeSystem))
+            .add_plugin(InputPlugin)
+            .insert_resource(Windows::default())
+            .insert_resource(ImageAssets::default())
+            .insert_resource(FontAssets::default())
+            .insert_resource(settings::Settings::default())
+            .insert_resource(global::NewGameSeed(Some(seed)))
+            .insert_resource(save_dir())
+            .add_state(global::GameState::MainMenu);
+
+        let tables = content::Tables::for_tests();
+        app.insert_resource(tables.enemies)
+            .insert_resource(tables.items)
+            .insert_resource(tables.attacks)
+            .insert_resource(tables.areas)
+            .insert_resource(tables.shops);
+
+        Harness {
+            app,
+            frame_rate,
+            skip_delays: true,
+        }
+    }
+
+    // Runs one frame. Unless the session is recorded or replayed, announcements and other delays
+    // finish straight away so tests don't depend on how fast they run. Everything else sees the
+    // same time pass whatever the frame rate.
+    pub(crate) fn update(&mut self) {
+        if self.skip_delays {
+            if let Some(mut timer) = self.app.world.get_resource_mut::<Timer>() {
+                let duration = timer.duration();
+                timer.set_elapsed(duration);
+            }
+        }
+        self.app.update();
+    }
+
+    // Runs frames until the condition holds.
+    pub(crate) fn run_until(&mut self, what: &str, condition: impl Fn(&mut World) -> bool) {
+        for _ in 0..MAX_FRAMES {
+            if condition(&mut self.app.world) {
+                return;
+            }
+            self.update();
+        }
+        panic!("gave up waiting for {}", what);
+    }
+
+    pub(crate) fn run_until_state(&mut self, state: global::GameState) {
+        self.run_until(&format!("{:?}", state), |world| {
+            *world
+                .get_resource::<State<global::GameState>>()
+                .unwrap()
+                .current()
+                == state
+        });
+    }
+
+    pub(crate) fn game_state(&self) -> global::GameState {
+        self.resource::<State<global::GameState>>()
+            .current()
+            .clone()
+    }
+
+    // Clicks the button with the given action, waiting for it to show up first.
+    pub(crate) fn click<T: Component + Debug>(&mut self, action: T) {
+        let action = format!("{:?}", action);
+        let find = |world: &mut World| {
+            world
+                .query::<(Entity, &T)>()
+                .iter(world)
+                .find(|(_, a)| format!("{:?}", a) == action)
+                .map(|(entity, _)| entity)
+        };
+
+        self.run_until(&format!("button {}", action), |world| find(world).is_some());
+        let button = find(&mut self.app.world).unwrap();
+        *self.app.world.get_mut::<Interaction>(button).unwrap() = Interaction::Clicked;
+        self.update();
+        // The mouse lets go again.
+        if let Some(mut interaction) = self.app.world.get_mut::<Interaction>(button) {
+            *interaction = Interaction::None;
+        }
+    }
+
+    pub(crate) fn press(&mut self, key: KeyCode) {
+        self.key(key, ElementState::Pressed);
+    }
+
+    pub(crate) fn release(&mut self, key: KeyCode) {
+        self.key(key, ElementState::Released);
+    }
+
+    // Sends the key as an event, like a window would, so recordings pick it up.
+    fn key(&mut self, key: KeyCode, state: ElementState) {
+        self.resource_mut::<Events<KeyboardInput>>()
+            .send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key),
+                state,
+            });
+    }
+
+    // Sends an event from the first gamepad, as if it had been plugged in or used.
+    pub(crate) fn gamepad(&mut self, event: GamepadEventType) {
+        self.resource_mut::<Events<GamepadEventRaw>>()
+            .send(GamepadEventRaw(Gamepad(0), event));
+    }
+
+    pub(crate) fn resource<R: Send + Sync + 'static>(&self) -> &R {
+        self.app.world.get_resource::<R>().unwrap()
+    }
+
+    pub(crate) fn resource_mut<R: Send + Sync + 'static>(&mut self) -> Mut<'_, R> {
+        self.app.world.get_resource_mut::<R>().unwrap()
+    }
+
+    // Slots of the enemies still standing in the current battle, from left to right.
+    pub(crate) fn living_enemies(&mut self) -> Vec<usize> {
+        let mut living: Vec<_> = self
+            .app
+            .world
+            .query::<(&EnemySlot, &global::Stats)>()
+            .iter(&self.app.world)
+            .filter(|(_, stats)| stats.hp > 0)
+            .map(|(slot, _)| slot.0)
+            .collect();
+        living.sort_unstable();
+        living
+    }
+
+    // Starts a new game from the main menu.
+    pub(crate) fn new_game(&mut self) {
+        self.click(crate::mainmenu::MenuButtonAction::Play);
+        self.run_until_state(global::GameState::Overworld);
+    }
+
+    // Puts the player straight into a battle with the given enemies, as if they had been met in
+    // the overworld.
+    pub(crate) fn start_battle(&mut self, enemies: &[usize]) {
+        self.resource_mut::<global::Encounter>().enemies = enemies.to_vec();
+        self.resource_mut::<State<global::GameState>>()
+            .set(global::GameState::Battle)
+            .unwrap();
+        self.run_until_state(global::GameState::Battle);
+    }
+
+    // Starts a new game from the main menu, walks north out of the starting area and paces up and
+    // down until an enemy shows up.
+    pub(crate) fn new_game_until_battle(&mut self) {
+        self.new_game();
+
+        let keys = &self.resource::<settings::Settings>().key_bindings;
+        let (up, down) = (keys.up, keys.down);
+        self.press(up);
+        self.run_until("the player to leave the starting area", |world| {
+            world.get_resource::<global::Player>().unwrap().area != 0
+        });
+        self.release(up);
+
+        let pace_frames = (PACE_SECONDS * self.frame_rate) as usize;
+        for pace in 0..MAX_FRAMES / pace_frames {
+            let key = if pace % 2 == 0 { up } else { down };
+            self.press(key);
+            for _ in 0..pace_frames {
+                if self.game_state() == global::GameState::Battle {
+                    self.release(key);
+                    return;
+                }
+                self.update();
+            }
+            self.release(key);
+        }
+        panic!("gave up waiting for an enemy to show up");
+    }
+}
+
+// Tests run at the same time, so each harness saves to a directory of its own. It's removed again
+// when the harness is dropped.
+fn save_dir() -> save::SaveDir {
+    static HARNESSES: AtomicUsize = AtomicUsize::new(0);
+    let harness = HARNESSES.fetch_add(1, Ordering::Relaxed);
+    save::SaveDir(std::env::temp_dir().join(format!(
+        "rapid_fantasy_saves_{}_{}",
+        std::process::id(),
+        harness
+    )))
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.resource::<save::SaveDir>().0);
+    }
+}
+
+// Replaces the time measured for this frame, like replay_frame() does when there's a replay.
+fn fixed_frame_time(fixed: Option<Res<FixedFrameTime>>, mut frame_time: ResMut<global::FrameTime>) {
+    if let Some(fixed) = fixed {
+        frame_time.0 = fixed.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        battle::{PlayerButtonAction, TargetButton},
+        replay::Replayer,
+        save,
+    };
+    use bevy::app::AppExit;
+
+    // A new game played through its first battle. Anything that changes how that plays out, like
+    // new content or rules, needs it recorded again with
+    // `cargo test record_the_replay_session -- --ignored`.
+    const REPLAY: &str = "tests/replays/first_battle.ron";
+
+    #[test]
+    fn replays_end_where_they_were_recorded() {
+        let recording = Recording::read(Path::new(REPLAY)).unwrap();
+        let mut harness = Harness::replay(&recording);
+        harness.run_until("the replay to finish", |world| {
+            world.get_resource::<Replayer>().unwrap().finished()
+        });
+
+        let player = save::PlayerSave::new(harness.resource::<global::Player>());
+        assert_eq!(recording.final_player, Some(player));
+    }
+
+    #[test]
+    #[ignore]
+    fn record_the_replay_session() {
+        let mut harness = Harness::record(1, Path::new(REPLAY));
+        harness.new_game_until_battle();
+        let buttons = |world: &mut World| world.query::<&PlayerButtonAction>().iter(world).count();
+        while harness.game_state() == global::GameState::Battle {
+            // Attack turns into the limit break once the gauge is full.
+            let limit_break = harness.resource::<global::Player>().limit == 100;
+            harness.click(if limit_break {
+                PlayerButtonAction::LimitBreak
+            } else {
+                PlayerButtonAction::Attack
+            });
+            harness.update();
+            let living = harness.living_enemies();
+            if living.len() > 1 {
+                harness.click(TargetButton(living[0]));
+            }
+            // Delays play out in full, so the menu takes a moment to go away.
+            harness.run_until("the attack to start", |world| buttons(world) == 0);
+            harness.run_until("the attack to play out", |world| {
+                let state = world.get_resource::<State<global::GameState>>().unwrap();
+                *state.current() != global::GameState::Battle || buttons(world) > 0
+            });
+        }
+        harness.resource_mut::<Events<AppExit>>().send(AppExit);
+        harness.update();
+    }
+
+    #[test]
+    fn new_game_starts_in_the_first_area() {
+        let mut harness = Harness::new(1);
+        harness.update();
+        assert_eq!(global::GameState::MainMenu, harness.game_state());
+
+        harness.new_game();
+
+        let player = harness.resource::<global::Player>();
+        assert_eq!(0, player.area);
+        assert_eq!(1, player.stats.level);
+        assert_eq!(1, harness.resource::<global::GameRng>().seed);
+        assert!(!harness.resource::<global::PlayerItemInventory>().is_empty());
+    }
+
+    #[test]
+    fn walking_speed_doesnt_depend_on_frame_rate() {
+        let walked_in_half_a_second = |frame_rate: f32| {
+            let mut harness = Harness::with_frame_rate(1, frame_rate);
+            harness.new_game();
+
+            let start = harness.resource::<global::Player>().x;
+            let right = harness.resource::<settings::Settings>().key_bindings.right;
+            harness.press(right);
+            for _ in 0..(frame_rate / 2.) as usize {
+                harness.update();
+            }
+            harness.resource::<global::Player>().x - start
+        };
+
+        let at_60 = walked_in_half_a_second(60.);
+        assert!(at_60 > 0.);
+        for frame_rate in [30., 144.] {
+            let walked = walked_in_half_a_second(frame_rate);
+            assert!(
+                (walked - at_60).abs() < 0.01,
+                "{} at {} FPS",
+                walked,
+                frame_rate
+            );
+        }
+    }
+
+    #[test]
+    fn a_gamepad_walks_and_opens_the_menu() {
+        use bevy::input::gamepad::GamepadButtonType;
+
+        let mut harness = Harness::new(1);
+        harness.new_game();
+
+        let start = harness.resource::<global::Player>().x;
+        harness.gamepad(GamepadEventType::Connected);
+        harness.gamepad(GamepadEventType::ButtonChanged(
+            GamepadButtonType::DPadRight,
+            1.,
+        ));
+        for _ in 0..10 {
+            harness.update();
+        }
+        assert!(harness.resource::<global::Player>().x > start);
+
+        harness.gamepad(GamepadEventType::ButtonChanged(
+            GamepadButtonType::DPadRight,
+            0.,
+        ));
+        harness.gamepad(GamepadEventType::ButtonChanged(
+            GamepadButtonType::Start,
+            1.,
+        ));
+        harness.run_until_state(global::GameState::Menu);
+    }
+
+    #[test]
+    fn winning_a_battle_returns_to_the_overworld() {
+        let mut harness = Harness::new(1);
+        harness.new_game_until_battle();
+        assert_ne!(0, harness.resource::<global::Player>().area);
+        let enemy_table = harness.resource::<global::EnemyTable>();
+        let enemy_gold: i32 = harness
+            .resource::<global::Encounter>()
+            .enemies
+            .iter()
+            .map(|id| enemy_table.table[id].1.gold)
+            .sum();
+
+        {
+            let mut player = harness.resource_mut::<global::Player>();
+            player.stats.hp_max = 10000;
+            player.stats.hp = 10000;
+            player.stats.strength = 10000;
+        }
+        // Every attack that lands takes down one enemy, aimed at the leftmost one standing.
+        for _ in 0..20 {
+            if harness.game_state() != global::GameState::Battle {
+                break;
+            }
+            harness.click(PlayerButtonAction::Attack);
+            harness.update();
+            let living = harness.living_enemies();
+            if living.len() > 1 {
+                harness.click(TargetButton(living[0]));
+            }
+            harness.run_until("the attack to play out", |world| {
+                let state = world.get_resource::<State<global::GameState>>().unwrap();
+                *state.current() != global::GameState::Battle
+                    || world.query::<&PlayerButtonAction>().iter(world).count() > 0
+            });
+        }
+        harness.run_until_state(global::GameState::Overworld);
+
+        let player = harness.resource::<global::Player>();
+        assert_eq!(enemy_gold, player.stats.gold);
+        assert!(player.stats.experience > 0);
+        assert_eq!(1, harness.resource::<global::RunStats>().battles_won);
+    }
+
+    #[test]
+    fn losing_a_battle_ends_the_game() {
+        let mut harness = Harness::new(1);
+        harness.new_game_until_battle();
+
+        {
+            let mut player = harness.resource_mut::<global::Player>();
+            player.stats.hp = 1;
+            player.stats.defense = 0;
+        }
+        let items = harness.resource::<global::PlayerItemInventory>().0.clone();
+        for _ in 0..100 {
+            if harness.game_state() != global::GameState::Battle {
+                break;
+            }
+            harness.click(PlayerButtonAction::Block);
+            harness.run_until("the enemy's turn to end", |world| {
+                let state = world.get_resource::<State<global::GameState>>().unwrap();
+                *state.current() != global::GameState::Battle
+                    || world.query::<&PlayerButtonAction>().iter(world).count() > 0
+            });
+        }
+
+        assert_eq!(global::GameState::Lose, harness.game_state());
+        assert_eq!(0, harness.resource::<global::Player>().stats.hp);
+        assert_eq!(0, harness.resource::<global::RunStats>().battles_won);
+        assert_eq!(items, harness.resource::<global::PlayerItemInventory>().0);
+    }
+
+    #[test]
+    fn same_seed_meets_the_same_enemies() {
+        let encounter = |seed| {
+            let mut harness = Harness::new(seed);
+            harness.new_game_until_battle();
+            let player = harness.resource::<global::Player>();
+            (
+                harness.resource::<global::Encounter>().enemies.clone(),
+                player.x,
+                player.y,
+            )
+        };
+        assert_eq!(encounter(5), encounter(5));
+    }
+}
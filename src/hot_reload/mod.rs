@@ -0,0 +1,288 @@
+use crate::{
+    area_table::AreaTableAsset,
+    content::{self, ContentAssets, ContentError, Overrides, Tables},
+    enemy_table::EnemyTableAsset,
+    global,
+    item_table::ItemTableAsset,
+    player_attack_table::PlayerAttackTableAsset,
+    shop_table::ShopTableAsset,
+    FontAssets, ImageAssets,
+};
+
+use bevy::prelude::*;
+use std::{fs, path::Path, time::SystemTime};
+
+mod styles;
+use styles::*;
+
+const REPORT_DURATION: f32 = 4.;
+// Override files are read straight from disk rather than through the asset server, which only
+// watches files that existed when they were loaded, so they're checked for changes this often.
+const OVERRIDE_POLL_INTERVAL: f32 = 0.5;
+
+// Development only: rebuilds the content tables whenever a content file or one of the override
+// files layered over them (see content::Overrides) changes on disk, so balance can be tuned while
+// the game is running. Override files are read from content::CONTENT_DIR, so the game has to be run
+// from the crate root.
+pub struct HotReloadPlugin;
+
+impl Plugin for HotReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ContentReloaded>()
+            .init_resource::<OverrideFiles>()
+            .add_system(reload_tables)
+            .add_system(expire_reload_report)
+            // Commands inserting the new tables are applied at the end of the update stage.
+            .add_system_to_stage(CoreStage::PostUpdate, refresh_player)
+            .add_system_to_stage(CoreStage::PostUpdate, refresh_enemy)
+            .add_system_to_stage(CoreStage::PostUpdate, refresh_area)
+            .add_system_to_stage(CoreStage::PostUpdate, show_reload_report);
+    }
+}
+
+struct ContentReloaded(Result<(), Vec<ContentError>>);
+
+#[derive(Component, Deref, DerefMut)]
+struct ReloadReport(Timer);
+
+// When each override file was last modified, or None if it isn't there.
+struct OverrideFiles {
+    timer: Timer,
+    modified: [Option<SystemTime>; 5],
+}
+
+impl Default for OverrideFiles {
+    fn default() -> Self {
+        OverrideFiles {
+            timer: Timer::from_seconds(OVERRIDE_POLL_INTERVAL, true),
+            modified: Default::default(),
+        }
+    }
+}
+
+impl OverrideFiles {
+    // Whether any override file was created, changed or deleted since the last poll.
+    fn poll(&mut self) -> bool {
+        let modified = Overrides::paths(Path::new(content::CONTENT_DIR))
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+        let changed = modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reload_tables(
+    mut commands: Commands,
+    mut enemy_events: EventReader<AssetEvent<EnemyTableAsset>>,
+    mut item_events: EventReader<AssetEvent<ItemTableAsset>>,
+    mut attack_events: EventReader<AssetEvent<PlayerAttackTableAsset>>,
+    mut area_events: EventReader<AssetEvent<AreaTableAsset>>,
+    mut shop_events: EventReader<AssetEvent<ShopTableAsset>>,
+    mut reloaded: EventWriter<ContentReloaded>,
+    content: Option<Res<ContentAssets>>,
+    image_assets: Option<Res<ImageAssets>>,
+    enemies: Res<Assets<EnemyTableAsset>>,
+    items: Res<Assets<ItemTableAsset>>,
+    attacks: Res<Assets<PlayerAttackTableAsset>>,
+    areas: Res<Assets<AreaTableAsset>>,
+    shops: Res<Assets<ShopTableAsset>>,
+    time: Res<Time>,
+    mut override_files: ResMut<OverrideFiles>,
+) {
+    // Drain every reader so that one change to two files only reloads once.
+    let modified = [
+        enemy_events
+            .iter()
+            .filter(|e| matches!(e, AssetEvent::Modified { .. }))
+            .count()
+            > 0,
+        item_events
+            .iter()
+            .filter(|e| matches!(e, AssetEvent::Modified { .. }))
+            .count()
+            > 0,
+        attack_events
+            .iter()
+            .filter(|e| matches!(e, AssetEvent::Modified { .. }))
+            .count()
+            > 0,
+        area_events
+            .iter()
+            .filter(|e| matches!(e, AssetEvent::Modified { .. }))
+            .count()
+            > 0,
+        shop_events
+            .iter()
+            .filter(|e| matches!(e, AssetEvent::Modified { .. }))
+            .count()
+            > 0,
+    ];
+    // Before the content has finished loading for the first time there is nothing to reload. Override
+    // files that are already there get picked up by the first poll after that.
+    let (content, image_assets) = match (content, image_assets) {
+        (Some(content), Some(image_assets)) => (content, image_assets),
+        _ => return,
+    };
+    let overrides_modified =
+        override_files.timer.tick(time.delta()).just_finished() && override_files.poll();
+    if !modified.contains(&true) && !overrides_modified {
+        return;
+    }
+
+    match Overrides::read(Path::new(content::CONTENT_DIR)).and_then(|overrides| {
+        Tables::build_with_overrides(
+            enemies.get(&content.enemies).unwrap(),
+            items.get(&content.items).unwrap(),
+            attacks.get(&content.attacks).unwrap(),
+            areas.get(&content.areas).unwrap(),
+            shops.get(&content.shops).unwrap(),
+            &overrides,
+            &image_assets,
+        )
+    }) {
+        Ok(tables) => {
+            info!("Reloaded content");
+            tables.insert(&mut commands);
+            reloaded.send(ContentReloaded(Ok(())));
+        }
+        Err(errors) => {
+            for error in &errors {
+                error!("{}", error);
+            }
+            reloaded.send(ContentReloaded(Err(errors)));
+        }
+    }
+}
+
+// The player holds copies of their attacks and equipment, so swap in the reloaded versions.
+// Every system here runs from startup, before there are any tables to reload.
+#[allow(clippy::too_many_arguments)]
+fn refresh_player(
+    mut reloaded: EventReader<ContentReloaded>,
+    attack_table: Option<Res<global::PlayerAttackTable>>,
+    item_table: Option<Res<global::ItemTable>>,
+    mut player: ResMut<global::Player>,
+    attack_inventory: Option<ResMut<global::PlayerAttackInventory>>,
+    magic_equipped: Option<ResMut<global::PlayerMagicEquipped>>,
+    limit_equipped: Option<ResMut<global::PlayerLimitEquipped>>,
+    equipment_equipped: Option<ResMut<global::PlayerEquipmentEquipped>>,
+) {
+    let (attack_table, item_table) = match (attack_table, item_table) {
+        (Some(attack_table), Some(item_table)) if reloaded.iter().any(|r| r.0.is_ok()) => {
+            (attack_table, item_table)
+        }
+        _ => return,
+    };
+    let reloaded_attack = |attack: &global::PlayerAttack| {
+        attack_table
+            .table
+            .get(&(attack.id as u32))
+            .cloned()
+            .unwrap_or_else(|| attack.clone())
+    };
+
+    if let Some(mut attack_inventory) = attack_inventory {
+        attack_inventory.0 = attack_inventory.iter().map(reloaded_attack).collect();
+    }
+    if let Some(mut magic_equipped) = magic_equipped {
+        for attack in magic_equipped.iter_mut().flatten() {
+            *attack = reloaded_attack(attack);
+        }
+    }
+    if let Some(mut limit_equipped) = limit_equipped {
+        limit_equipped.0 = reloaded_attack(&limit_equipped);
+    }
+    if let Some(mut equipment_equipped) = equipment_equipped {
+        let equipment_equipped = &mut *equipment_equipped;
+        for item in [
+            &mut equipment_equipped.weapon,
+            &mut equipment_equipped.armor,
+            &mut equipment_equipped.accessory,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let Some(reloaded_item) = item_table.get(&item.id) {
+                player.stats.subtract_item_stats(&item.stats);
+                player.stats.add_item_stats(&reloaded_item.stats);
+                *item = reloaded_item.clone();
+            }
+        }
+    }
+}
+
+fn refresh_enemy(
+    mut reloaded: EventReader<ContentReloaded>,
+    game_state: Res<State<global::GameState>>,
+    enemy_table: Option<Res<global::EnemyTable>>,
+    mut enemies: Query<global::EnemyComponents>,
+) {
+    if let Some(enemy_table) = enemy_table {
+        if reloaded.iter().any(|r| r.0.is_ok())
+            && *game_state.current() == global::GameState::Battle
+        {
+            for enemy in enemies.iter_mut() {
+                global::Enemy::lend(enemy, |enemy| enemy.refresh_from_table(&enemy_table));
+            }
+        }
+    }
+}
+
+// The overworld is spawned from the area when it's entered, so enter it again to show the reloaded
+// one. If the game is about to change state anyway, it'll be spawned afresh when it comes back.
+fn refresh_area(
+    mut reloaded: EventReader<ContentReloaded>,
+    mut game_state: ResMut<State<global::GameState>>,
+) {
+    if reloaded.iter().any(|r| r.0.is_ok()) && *game_state.current() == global::GameState::Overworld
+    {
+        let _ = game_state.restart();
+    }
+}
+
+fn show_reload_report(
+    mut commands: Commands,
+    mut reloaded: EventReader<ContentReloaded>,
+    font_assets: Option<Res<FontAssets>>,
+    reports: Query<Entity, With<ReloadReport>>,
+) {
+    let font_assets = match font_assets {
+        Some(font_assets) => font_assets,
+        None => return,
+    };
+    let text = match reloaded.iter().last() {
+        Some(ContentReloaded(Ok(()))) => "Reloaded content.".to_string(),
+        Some(ContentReloaded(Err(errors))) => format!(
+            "Content reload failed, keeping the old tables:\n{}",
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        None => return,
+    };
+
+    for report in reports.iter() {
+        commands.entity(report).despawn_recursive();
+    }
+    commands
+        .spawn_bundle(styled_reload_report())
+        .insert(ReloadReport(Timer::from_seconds(REPORT_DURATION, false)))
+        .with_children(|p| {
+            p.spawn_bundle(styled_reload_report_text(&font_assets, text));
+        });
+}
+
+fn expire_reload_report(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut reports: Query<(Entity, &mut ReloadReport)>,
+) {
+    for (entity, mut timer) in reports.iter_mut() {
+        if timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
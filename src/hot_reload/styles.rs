@@ -0,0 +1,34 @@
+use crate::{global, FontAssets};
+use bevy::prelude::*;
+
+pub fn styled_reload_report() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(10.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            padding: Rect::all(Val::Px(10.)),
+            ..default()
+        },
+        color: Color::rgba(1., 1., 1., 0.8).into(),
+        ..default()
+    }
+}
+
+pub fn styled_reload_report_text(font_assets: &Res<FontAssets>, text: String) -> TextBundle {
+    TextBundle {
+        text: Text::with_section(
+            text,
+            TextStyle {
+                font: font_assets.font.clone(),
+                font_size: 20.,
+                color: global::TEXT_COLOR,
+            },
+            Default::default(),
+        ),
+        ..default()
+    }
+}
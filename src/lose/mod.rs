@@ -1,4 +1,4 @@
-use crate::{button_system, despawn_screen, global, FontAssets, ImageAssets};
+use crate::{button_system, despawn_screen, global, replay::RecordClicks, FontAssets, ImageAssets};
 
 use bevy::prelude::*;
 
@@ -8,7 +8,8 @@ pub struct LosePlugin;
 
 impl Plugin for LosePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(global::GameState::Lose).with_system(lose_setup))
+        app.record_clicks::<MenuButtonAction>()
+            .add_system_set(SystemSet::on_enter(global::GameState::Lose).with_system(lose_setup))
             .add_system_set(
                 SystemSet::on_update(global::GameState::Lose)
                     .with_system(menu_action)
@@ -21,7 +22,7 @@ impl Plugin for LosePlugin {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Debug)]
 enum MenuButtonAction {
     BackToMainMenu,
 }
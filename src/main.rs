@@ -1,48 +1,109 @@
+mod actions;
+mod area_table;
 mod battle;
+mod cli;
+mod combat;
+mod content;
 mod enemy_table;
 mod global;
+#[cfg(test)]
+mod harness;
+#[cfg(feature = "dev")]
+mod hot_reload;
 mod item_table;
 mod lose;
 mod mainmenu;
 mod menu;
 mod overworld;
 mod player_attack_table;
-use bevy::prelude::*;
+mod replay;
+mod save;
+mod settings;
+mod shop;
+mod shop_table;
+mod simulate;
+mod tile_map;
+mod victory;
+use bevy::{core::CoreSystem, prelude::*};
 use bevy_asset_loader::{AssetCollection, AssetLoader};
 
 fn main() {
+    let args = cli::Args::from_env();
+    if let Some(options) = &args.simulate {
+        let seed = global::NewGameSeed(args.seed).pick();
+        if let Err(e) = simulate::run(options, seed) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut app = App::new();
     AssetLoader::new(global::GameState::Initialization)
         .continue_to_state(global::GameState::MainMenu) // TODO: change back to MainMenu after done testing
         .with_collection::<ImageAssets>()
         .with_collection::<FontAssets>()
+        .with_collection::<content::ContentAssets>()
         .build(&mut app);
-    app.insert_resource(WindowDescriptor {
-        title: "Rapid Fantasy - Remake".to_string(),
-        resizable: false,
+    // Watch the asset folder so that content files can be edited while the game runs.
+    #[cfg(feature = "dev")]
+    app.insert_resource(bevy::asset::AssetServerSettings {
+        watch_for_changes: true,
         ..default()
-    })
-    .insert_resource(ClearColor(global::BACKGROUND_COLOR))
-    .init_resource::<global::PlayerAttackTable>()
-    .init_resource::<global::ItemTable>()
-    .init_resource::<global::Player>()
-    .init_resource::<global::Enemy>()
-    .add_state(global::GameState::Initialization)
-    .add_startup_system(setup_main)
-    .add_plugins(DefaultPlugins)
-    .add_plugin(mainmenu::MainMenuPlugin)
-    .add_plugin(overworld::OverworldPlugin)
-    .add_plugin(menu::MenuPlugin)
-    .add_plugin(battle::BattlePlugin)
-    .add_plugin(lose::LosePlugin)
-    .run();
+    });
+    let settings = settings::Settings::load();
+    app.insert_resource(settings.window_descriptor())
+        .insert_resource(settings)
+        .insert_resource(ClearColor(global::BACKGROUND_COLOR))
+        .insert_resource(global::NewGameSeed(args.seed))
+        .add_state(global::GameState::Initialization)
+        .add_startup_system(setup_main)
+        .add_plugins(DefaultPlugins)
+        .add_plugin(content::ContentPlugin);
+    add_game_plugins(&mut app);
+    #[cfg(feature = "dev")]
+    app.add_plugin(hot_reload::HotReloadPlugin);
+    if let Some(replay) = replay::ReplayPlugin::from_args(&args) {
+        app.add_plugin(replay);
+    }
+    app.run();
+}
+
+// The game's own resources and screens. The headless test harness runs these too, so anything
+// that needs a window or the asset server goes in main() instead.
+fn add_game_plugins(app: &mut App) -> &mut App {
+    app.init_resource::<global::FrameTime>()
+        .add_system_to_stage(
+            CoreStage::First,
+            measure_frame_time
+                .label(FrameTimeSystem)
+                .after(CoreSystem::Time),
+        )
+        .init_resource::<global::Player>()
+        .init_resource::<global::Encounter>()
+        .init_resource::<global::RunStats>()
+        .init_resource::<save::SaveDir>()
+        .add_system(tick_play_time)
+        .add_plugin(actions::ActionsPlugin)
+        .add_plugin(settings::SettingsPlugin)
+        .add_plugin(mainmenu::MainMenuPlugin)
+        .add_plugin(overworld::OverworldPlugin)
+        .add_plugin(menu::MenuPlugin)
+        .add_plugin(battle::BattlePlugin)
+        .add_plugin(shop::ShopPlugin)
+        .add_plugin(lose::LosePlugin)
+        .add_plugin(victory::VictoryPlugin)
 }
 
 // Tag component used to mark which setting is currently selected
 #[derive(Component)]
 struct SelectedOption;
 
-#[derive(AssetCollection)]
+// Tag component used to mark buttons that can't be clicked
+#[derive(Component)]
+struct DisabledButton;
+
+#[derive(AssetCollection, Default)]
 pub struct ImageAssets {
     #[asset(path = "images/main_menu.png")]
     main_menu: Handle<Image>,
@@ -73,6 +134,8 @@ pub struct ImageAssets {
 
     #[asset(path = "images/player_battle.png")]
     player_battle: Handle<Image>,
+    #[asset(path = "images/merchant.png")]
+    merchant: Handle<Image>,
     #[asset(path = "images/enemy1.png")]
     enemy1: Handle<Image>,
     #[asset(path = "images/enemy2.png")]
@@ -103,7 +166,45 @@ pub struct ImageAssets {
     enemy14: Handle<Image>,
 }
 
-#[derive(AssetCollection)]
+impl ImageAssets {
+    // Looks up an image by its field name, which is how content files refer to images.
+    pub(crate) fn get(&self, name: &str) -> Option<Handle<Image>> {
+        let image = match name {
+            "main_menu" => &self.main_menu,
+            "area0" => &self.area0,
+            "area1" => &self.area1,
+            "area2" => &self.area2,
+            "area3" => &self.area3,
+            "area4" => &self.area4,
+            "area5" => &self.area5,
+            "game_over" => &self.game_over,
+            "player_up" => &self.player_up,
+            "player_down" => &self.player_down,
+            "player_left" => &self.player_left,
+            "player_right" => &self.player_right,
+            "player_battle" => &self.player_battle,
+            "merchant" => &self.merchant,
+            "enemy1" => &self.enemy1,
+            "enemy2" => &self.enemy2,
+            "enemy3" => &self.enemy3,
+            "enemy4" => &self.enemy4,
+            "enemy5" => &self.enemy5,
+            "enemy6" => &self.enemy6,
+            "enemy7" => &self.enemy7,
+            "enemy8" => &self.enemy8,
+            "enemy9" => &self.enemy9,
+            "enemy10" => &self.enemy10,
+            "enemy11" => &self.enemy11,
+            "enemy12" => &self.enemy12,
+            "enemy13" => &self.enemy13,
+            "enemy14" => &self.enemy14,
+            _ => return None,
+        };
+        Some(image.clone())
+    }
+}
+
+#[derive(AssetCollection, Default)]
 pub struct FontAssets {
     #[asset(path = "fonts/FiraMono-Medium.ttf")]
     font: Handle<Font>,
@@ -113,25 +214,51 @@ pub struct FontAssets {
 
 fn setup_main(mut commands: Commands) {
     // Cameras
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(global::WorldCamera);
     commands.spawn_bundle(UiCameraBundle::default());
 }
 
+// Anything that decides how long frames take instead of the clock runs after this.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+struct FrameTimeSystem;
+
+fn measure_frame_time(time: Res<Time>, mut frame_time: ResMut<global::FrameTime>) {
+    frame_time.0 = time.delta();
+}
+
+// Play time only counts while a game is in progress
+fn tick_play_time(
+    time: Res<global::FrameTime>,
+    game_state: Res<State<global::GameState>>,
+    mut run_stats: ResMut<global::RunStats>,
+) {
+    use global::GameState::*;
+    if let Overworld | Menu | Battle | Shop = game_state.current() {
+        run_stats.play_time += time.delta_seconds();
+    }
+}
+
 // This system handles changing all buttons color based on mouse interaction
 fn button_system(
     mut interaction_query: Query<
         (&Interaction, &mut UiColor, Option<&SelectedOption>),
-        (Changed<Interaction>, With<Button>),
+        (Changed<Interaction>, With<Button>, Without<DisabledButton>),
     >,
 ) {
     for (interaction, mut color, selected) in interaction_query.iter_mut() {
-        *color = match (*interaction, selected) {
-            (Interaction::Clicked, _) => global::PRESSED_BUTTON.into(),
-            (Interaction::Hovered, Some(_)) => global::HOVERED_PRESSED_BUTTON.into(),
-            (Interaction::Hovered, None) => global::HOVERED_BUTTON.into(),
-            (Interaction::None, Some(_)) => global::PRESSED_BUTTON.into(),
-            (Interaction::None, None) => global::NORMAL_BUTTON.into(),
-        }
+        *color = button_color(*interaction, selected.is_some());
+    }
+}
+
+fn button_color(interaction: Interaction, selected: bool) -> UiColor {
+    match (interaction, selected) {
+        (Interaction::Clicked, _) => global::PRESSED_BUTTON.into(),
+        (Interaction::Hovered, true) => global::HOVERED_PRESSED_BUTTON.into(),
+        (Interaction::Hovered, false) => global::HOVERED_BUTTON.into(),
+        (Interaction::None, true) => global::PRESSED_BUTTON.into(),
+        (Interaction::None, false) => global::NORMAL_BUTTON.into(),
     }
 }
 
@@ -1,12 +1,18 @@
-use crate::{button_system, despawn_screen, global, FontAssets, ImageAssets};
+use crate::{
+    button_system, despawn_screen, global,
+    replay::{self, RecordClicks},
+    save, settings, DisabledButton, FontAssets, ImageAssets,
+};
 
-use bevy::{prelude::*, window::WindowMode};
+use bevy::{app::AppExit, prelude::*};
+use std::io;
 
 pub struct MainMenuPlugin;
 
 impl Plugin for MainMenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_state(MenuState::Main)
+        app.record_clicks::<MenuButtonAction>()
+            .add_state(MenuState::Disabled)
             .add_system_set(
                 SystemSet::on_enter(global::GameState::MainMenu).with_system(main_menu_setup),
             )
@@ -15,29 +21,65 @@ impl Plugin for MainMenuPlugin {
                     .with_system(menu_action)
                     .with_system(button_system),
             )
+            .add_system_set(SystemSet::on_enter(MenuState::Main).with_system(spawn_main_menu))
             .add_system_set(
-                SystemSet::on_exit(global::GameState::MainMenu)
-                    .with_system(despawn_screen::<MainMenuScreen>),
+                SystemSet::on_exit(MenuState::Main).with_system(despawn_screen::<MainMenuScreen>),
+            )
+            .add_system_set(
+                SystemSet::on_enter(MenuState::LoadGame).with_system(spawn_load_game_menu),
+            )
+            .add_system_set(
+                SystemSet::on_exit(MenuState::LoadGame)
+                    .with_system(despawn_screen::<LoadGameScreen>),
+            )
+            .add_system_set(
+                SystemSet::on_enter(MenuState::Settings).with_system(spawn_settings_menu),
+            )
+            .add_system_set(
+                SystemSet::on_exit(MenuState::Settings)
+                    .with_system(despawn_screen::<SettingsScreen>),
             );
+        for page_state in [
+            MenuState::SettingsDisplay,
+            MenuState::SettingsSound,
+            MenuState::SettingsControls,
+        ] {
+            app.add_system_set(
+                SystemSet::on_enter(page_state.clone()).with_system(spawn_settings_page_menu),
+            )
+            .add_system_set(
+                SystemSet::on_exit(page_state).with_system(despawn_screen::<SettingsScreen>),
+            );
+        }
+        app.add_system_set(
+            SystemSet::on_exit(MenuState::SettingsControls).with_system(settings::stop_rebinding),
+        );
     }
 }
 
 // State used for the current menu screen
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 enum MenuState {
-    // TODO: to use...
     Main,
-    Settings, // TODO
+    LoadGame,
+    Settings,
+    SettingsDisplay,
+    SettingsSound,
+    SettingsControls,
     Disabled,
 }
 
 // All actions that can be triggered from a button click
-#[derive(Component)]
-enum MenuButtonAction {
+#[derive(Component, Debug)]
+pub(crate) enum MenuButtonAction {
     Play,
+    Continue,
+    LoadGame,
+    LoadSlot(usize),
     Settings,
     SettingsDisplay,
     SettingsSound,
+    SettingsControls,
     BackToMainMenu,
     BackToSettings,
     Quit,
@@ -47,101 +89,442 @@ enum MenuButtonAction {
 #[derive(Component)]
 struct MainMenuScreen;
 
-fn main_menu_setup(
-    mut commands: Commands,
-    image_assets: Res<ImageAssets>,
-    font_assets: Res<FontAssets>,
-    mut menu_state: ResMut<State<MenuState>>,
-) {
-    // HACK: This is here because we need ImageAssets to be initialized...
-    commands.init_resource::<global::EnemyTable>();
-    commands.init_resource::<global::Areas>();
+// Tag component used to tag entities added on the load game screen
+#[derive(Component)]
+struct LoadGameScreen;
+
+// Tag component used to tag entities added on the settings screens
+#[derive(Component)]
+struct SettingsScreen;
 
+// Text under the Continue and Load Game buttons saying why a save couldn't be loaded, e.g. because
+// the file changed after the menu was shown.
+#[derive(Component)]
+struct LoadError;
+
+fn main_menu_setup(mut menu_state: ResMut<State<MenuState>>) {
     // Reset state for recurring visit to this page.
     if *menu_state.current() == MenuState::Disabled {
         menu_state.set(MenuState::Main).unwrap();
     }
+}
 
-    let button_style = Style {
-        size: Size::new(Val::Px(250.0), Val::Px(65.0)),
-        margin: Rect::all(Val::Px(20.0)),
-        justify_content: JustifyContent::Center,
-        align_items: AlignItems::Center,
+fn menu_screen(image_assets: &ImageAssets) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            margin: Rect::all(Val::Auto),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            align_content: AlignContent::Center,
+            justify_content: JustifyContent::Center,
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+            ..default()
+        },
+        image: image_assets.main_menu.clone().into(),
         ..default()
-    };
-    let button_text_style = TextStyle {
-        font: font_assets.font.clone(),
-        font_size: 40.0,
-        color: global::TEXT_COLOR,
-    };
+    }
+}
+
+fn spawn_button(
+    p: &mut ChildBuilder,
+    font_assets: &FontAssets,
+    action: MenuButtonAction,
+    text: &str,
+    width: f32,
+    enabled: bool,
+) {
+    let mut button = p.spawn_bundle(ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(width), Val::Px(65.0)),
+            margin: Rect::all(Val::Px(20.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: if enabled {
+            global::NORMAL_BUTTON.into()
+        } else {
+            global::DISABLED_BUTTON.into()
+        },
+        ..default()
+    });
+    button.insert(action).with_children(|p| {
+        p.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                text,
+                TextStyle {
+                    font: font_assets.font.clone(),
+                    font_size: if width > 250. { 30.0 } else { 40.0 },
+                    color: global::TEXT_COLOR,
+                },
+                Default::default(),
+            ),
+            ..default()
+        });
+    });
+    if !enabled {
+        button.insert(DisabledButton);
+    }
+}
+
+fn spawn_load_error(p: &mut ChildBuilder, font_assets: &FontAssets) {
+    p.spawn_bundle(TextBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: font_assets.font.clone(),
+                font_size: 30.0,
+                color: global::TEXT_COLOR,
+            },
+            Default::default(),
+        ),
+        ..default()
+    })
+    .insert(LoadError);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_main_menu(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    font_assets: Res<FontAssets>,
+    save_dir: Res<save::SaveDir>,
+    attack_table: Res<global::PlayerAttackTable>,
+    item_table: Res<global::ItemTable>,
+    areas: Res<global::Areas>,
+    new_games_only: Option<Res<replay::NewGamesOnly>>,
+) {
+    let has_saves = new_games_only.is_none()
+        && save_dir
+            .latest_slot(&attack_table, &item_table, &areas)
+            .is_some();
 
     // Button panel
     commands
-        .spawn_bundle(NodeBundle {
-            style: Style {
-                margin: Rect::all(Val::Auto),
-                flex_direction: FlexDirection::ColumnReverse,
-                align_items: AlignItems::Center,
-                align_content: AlignContent::Center,
-                justify_content: JustifyContent::Center,
-                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
-                ..default()
-            },
-            image: image_assets.main_menu.clone().into(),
-            ..default()
-        })
+        .spawn_bundle(menu_screen(&image_assets))
         .insert(MainMenuScreen)
         .with_children(|p| {
-            // Display start game button
-            p.spawn_bundle(ButtonBundle {
-                style: button_style.clone(),
-                color: global::NORMAL_BUTTON.into(),
-                ..default()
-            })
-            .insert(MenuButtonAction::Play)
-            .with_children(|p| {
-                p.spawn_bundle(TextBundle {
-                    text: Text::with_section(
-                        "New Game",
-                        button_text_style.clone(),
-                        Default::default(),
-                    ),
-                    ..default()
-                });
-            });
-
-            // TODO: settings button
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::Continue,
+                "Continue",
+                250.,
+                has_saves,
+            );
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::Play,
+                "New Game",
+                250.,
+                true,
+            );
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::LoadGame,
+                "Load Game",
+                250.,
+                has_saves,
+            );
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::Settings,
+                "Settings",
+                250.,
+                true,
+            );
+            spawn_button(p, &font_assets, MenuButtonAction::Quit, "Quit", 250., true);
+            spawn_load_error(p, &font_assets);
+        });
+}
+
+fn spawn_load_game_menu(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    font_assets: Res<FontAssets>,
+    save_dir: Res<save::SaveDir>,
+    attack_table: Res<global::PlayerAttackTable>,
+    item_table: Res<global::ItemTable>,
+    areas: Res<global::Areas>,
+) {
+    commands
+        .spawn_bundle(menu_screen(&image_assets))
+        .insert(LoadGameScreen)
+        .with_children(|p| {
+            for slot in 0..save::SAVE_SLOTS {
+                let (text, enabled) =
+                    match save_dir.read_loadable_slot(slot, &attack_table, &item_table, &areas) {
+                        Ok(save_game) => {
+                            (format!("Slot {}: {}", slot + 1, save_game.summary()), true)
+                        }
+                        Err(save::SaveError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                            (format!("Slot {}: Empty", slot + 1), false)
+                        }
+                        Err(e) => {
+                            warn!("Slot {} can't be loaded: {}", slot + 1, e);
+                            (format!("Slot {}: Can't be loaded", slot + 1), false)
+                        }
+                    };
+                spawn_button(
+                    p,
+                    &font_assets,
+                    MenuButtonAction::LoadSlot(slot),
+                    &text,
+                    800.,
+                    enabled,
+                );
+            }
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::BackToMainMenu,
+                "Back",
+                250.,
+                true,
+            );
+            spawn_load_error(p, &font_assets);
         });
 }
 
+fn spawn_settings_menu(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    font_assets: Res<FontAssets>,
+) {
+    commands
+        .spawn_bundle(menu_screen(&image_assets))
+        .insert(SettingsScreen)
+        .with_children(|p| {
+            for (action, text) in [
+                (MenuButtonAction::SettingsDisplay, "Display"),
+                (MenuButtonAction::SettingsSound, "Sound"),
+                (MenuButtonAction::SettingsControls, "Controls"),
+                (MenuButtonAction::BackToMainMenu, "Back"),
+            ] {
+                spawn_button(p, &font_assets, action, text, 250., true);
+            }
+        });
+}
+
+fn spawn_settings_page_menu(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    font_assets: Res<FontAssets>,
+    menu_state: Res<State<MenuState>>,
+    settings: Res<settings::Settings>,
+) {
+    let page = match menu_state.current() {
+        MenuState::SettingsDisplay => settings::SettingsPage::Display,
+        MenuState::SettingsSound => settings::SettingsPage::Sound,
+        _ => settings::SettingsPage::Controls,
+    };
+
+    commands
+        .spawn_bundle(menu_screen(&image_assets))
+        .insert(SettingsScreen)
+        .with_children(|p| {
+            settings::spawn_settings_page(p, &font_assets, &settings, page);
+            spawn_button(
+                p,
+                &font_assets,
+                MenuButtonAction::BackToSettings,
+                "Back",
+                250.,
+                true,
+            );
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
 fn menu_action(
     mut commands: Commands,
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
-        (Changed<Interaction>, With<Button>),
+        (Changed<Interaction>, With<Button>, Without<DisabledButton>),
     >,
     mut menu_state: ResMut<State<MenuState>>,
     mut game_state: ResMut<State<global::GameState>>,
+    mut app_exit_events: EventWriter<AppExit>,
     image_assets: Res<ImageAssets>,
     attack_table: Res<global::PlayerAttackTable>,
+    item_table: Res<global::ItemTable>,
+    new_game_seed: Res<global::NewGameSeed>,
+    areas: Res<global::Areas>,
+    save_dir: Res<save::SaveDir>,
+    mut load_error: Query<&mut Text, With<LoadError>>,
 ) {
+    let mut slot_to_load = None;
+    let mut show_load_error = |message: String| {
+        error!("{}", message);
+        for mut text in load_error.iter_mut() {
+            text.sections[0].value = message.clone();
+        }
+    };
+
     for (interaction, menu_button_action) in interaction_query.iter() {
         if *interaction == Interaction::Clicked {
             match menu_button_action {
                 MenuButtonAction::Play => {
-                    commands.insert_resource(global::Player::new(&image_assets));
-                    commands.insert_resource(global::PlayerEquipmentEquipped::default());
-                    commands.insert_resource(global::PlayerMagicEquipped::new(&attack_table));
-                    commands.insert_resource(global::PlayerLimitEquipped::new(&attack_table));
-                    commands.insert_resource(global::PlayerItemInventory::new());
-                    commands.insert_resource(global::PlayerAttackInventory::new(&attack_table));
+                    global::PlayerProgress::new(&image_assets, &attack_table, new_game_seed.pick())
+                        .insert(&mut commands);
                     game_state.set(global::GameState::Overworld).unwrap();
                     menu_state.set(MenuState::Disabled).unwrap();
-                    // player.stats = global::Stats::new(image_assets.player_battle.clone());
-                    // TODO: set player inventories to new game
                 }
-                _ => todo!("Unhandled menu button action!!"), // TODO
+                MenuButtonAction::Continue => {
+                    slot_to_load = save_dir.latest_slot(&attack_table, &item_table, &areas);
+                    if slot_to_load.is_none() {
+                        show_load_error("None of the saves can be loaded any more".to_string());
+                    }
+                }
+                MenuButtonAction::LoadGame => menu_state.set(MenuState::LoadGame).unwrap(),
+                MenuButtonAction::LoadSlot(slot) => slot_to_load = Some(*slot),
+                MenuButtonAction::Settings => menu_state.set(MenuState::Settings).unwrap(),
+                MenuButtonAction::SettingsDisplay => {
+                    menu_state.set(MenuState::SettingsDisplay).unwrap()
+                }
+                MenuButtonAction::SettingsSound => {
+                    menu_state.set(MenuState::SettingsSound).unwrap()
+                }
+                MenuButtonAction::SettingsControls => {
+                    menu_state.set(MenuState::SettingsControls).unwrap()
+                }
+                MenuButtonAction::BackToSettings => menu_state.set(MenuState::Settings).unwrap(),
+                MenuButtonAction::BackToMainMenu => menu_state.set(MenuState::Main).unwrap(),
+                MenuButtonAction::Quit => app_exit_events.send(AppExit),
             }
         }
     }
+
+    if let Some(slot) = slot_to_load {
+        let progress = save_dir.read_slot(slot).and_then(|save_game| {
+            save_game.restore(
+                image_assets.player_battle.clone(),
+                &attack_table,
+                &item_table,
+                &areas,
+            )
+        });
+        match progress {
+            Ok(progress) => {
+                progress.insert(&mut commands);
+                game_state.set(global::GameState::Overworld).unwrap();
+                menu_state.set(MenuState::Disabled).unwrap();
+            }
+            Err(e) => show_load_error(format!("Failed to load slot {}: {}", slot + 1, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content, harness::Harness};
+    use std::fs;
+
+    fn disabled(harness: &mut Harness, action: MenuButtonAction) -> bool {
+        let action = format!("{:?}", action);
+        harness.run_until(&format!("button {}", action), |world| {
+            world
+                .query::<&MenuButtonAction>()
+                .iter(world)
+                .any(|a| format!("{:?}", a) == action)
+        });
+        let world = &mut harness.app.world;
+        world
+            .query::<(&MenuButtonAction, Option<&DisabledButton>)>()
+            .iter(world)
+            .find(|(a, _)| format!("{:?}", a) == action)
+            .unwrap()
+            .1
+            .is_some()
+    }
+
+    #[test]
+    fn saves_are_needed_to_continue_or_load() {
+        let mut harness = Harness::new(0);
+        assert!(disabled(&mut harness, MenuButtonAction::Continue));
+        assert!(disabled(&mut harness, MenuButtonAction::LoadGame));
+        assert!(!disabled(&mut harness, MenuButtonAction::Play));
+    }
+
+    fn save_game(gold: i32) -> save::SaveGame {
+        let tables = content::Tables::for_tests();
+        let mut progress = global::PlayerProgress::new(&ImageAssets::default(), &tables.attacks, 0);
+        progress.player.stats.gold = gold;
+        save::SaveGame::new(
+            &progress.player,
+            &progress.run_stats,
+            &progress.equipment_equipped,
+            &progress.magic_equipped,
+            &progress.limit_equipped,
+            &progress.item_inventory,
+            &progress.attack_inventory,
+            &progress.rng,
+        )
+    }
+
+    fn load_error(harness: &mut Harness) -> String {
+        let world = &mut harness.app.world;
+        let mut texts = world.query_filtered::<&Text, With<LoadError>>();
+        texts.iter(world).next().unwrap().sections[0].value.clone()
+    }
+
+    #[test]
+    fn only_saves_that_can_be_loaded_are_offered() {
+        let mut harness = Harness::new(0);
+        let save_dir = harness.resource::<save::SaveDir>();
+        save_dir.write_slot(1, &save_game(77)).unwrap();
+        // Both written after the good save, so either would be the latest.
+        fs::write(save_dir.0.join("slot0.ron"), "not a save").unwrap();
+        let mut unknown_item = save_game(0);
+        unknown_item.item_inventory.push((999, 1));
+        save_dir.write_slot(2, &unknown_item).unwrap();
+
+        harness.click(MenuButtonAction::LoadGame);
+        assert!(disabled(&mut harness, MenuButtonAction::LoadSlot(0)));
+        assert!(!disabled(&mut harness, MenuButtonAction::LoadSlot(1)));
+        assert!(disabled(&mut harness, MenuButtonAction::LoadSlot(2)));
+
+        harness.click(MenuButtonAction::BackToMainMenu);
+        assert!(!disabled(&mut harness, MenuButtonAction::Continue));
+        harness.click(MenuButtonAction::Continue);
+        harness.run_until_state(global::GameState::Overworld);
+        assert_eq!(77, harness.resource::<global::Player>().stats.gold);
+    }
+
+    #[test]
+    fn saves_that_stop_loading_say_why() {
+        let mut harness = Harness::new(0);
+        let slot = harness.resource::<save::SaveDir>().0.join("slot0.ron");
+        harness
+            .resource::<save::SaveDir>()
+            .write_slot(0, &save_game(0))
+            .unwrap();
+
+        // Broken after the menu offered it.
+        assert!(!disabled(&mut harness, MenuButtonAction::Continue));
+        fs::write(&slot, "not a save").unwrap();
+        harness.click(MenuButtonAction::Continue);
+        assert_eq!(global::GameState::MainMenu, harness.game_state());
+        assert_eq!(
+            "None of the saves can be loaded any more",
+            load_error(&mut harness)
+        );
+
+        harness
+            .resource::<save::SaveDir>()
+            .write_slot(0, &save_game(0))
+            .unwrap();
+        harness.click(MenuButtonAction::LoadGame);
+        assert!(!disabled(&mut harness, MenuButtonAction::LoadSlot(0)));
+        fs::write(&slot, "not a save").unwrap();
+        harness.click(MenuButtonAction::LoadSlot(0));
+        assert_eq!(global::GameState::MainMenu, harness.game_state());
+        assert!(
+            load_error(&mut harness).starts_with("Failed to load slot 1: save file is corrupted")
+        );
+    }
 }
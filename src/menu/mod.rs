@@ -1,4 +1,7 @@
-use crate::{button_system, despawn_children, despawn_screen, global, FontAssets};
+use crate::{
+    actions::Action, button_system, despawn_children, despawn_screen, global, replay::RecordClicks,
+    save, settings, FontAssets,
+};
 
 mod styles;
 pub use styles::*;
@@ -12,14 +15,24 @@ pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_state(MenuState::Active)
+        app.record_clicks::<SidePanelButtonAction>()
+            .record_clicks::<ItemButton>()
+            .record_clicks::<MagicSlotButton>()
+            .record_clicks::<MagicButton>()
+            .record_clicks::<EquipSlotButton>()
+            .record_clicks::<EquipButton>()
+            .record_clicks::<LimitSlotButton>()
+            .record_clicks::<LimitButton>()
+            .record_clicks::<SaveSlotButton>()
+            .add_state(MenuState::Active)
             .add_state(SubPanelState::Inactive)
             .add_system_set(SystemSet::on_enter(global::GameState::Menu).with_system(menu_setup))
             .add_system_set(
                 SystemSet::on_update(global::GameState::Menu)
-                    .with_system(close_menu)
+                    .with_system(close_menu.before(settings::SettingsSystem::RebindKey))
                     .with_system(side_panel_action)
                     .with_system(scroll_list_scroll)
+                    .with_system(update_chance_texts)
                     .with_system(button_system),
             )
             .add_system_set(
@@ -65,6 +78,25 @@ impl Plugin for MenuPlugin {
             .add_system_set(
                 SystemSet::on_exit(SubPanelState::Limit).with_system(despawn_children::<SubPanel>),
             )
+            .add_system_set(
+                SystemSet::on_enter(SubPanelState::Save).with_system(save_menu::spawn_save_menu),
+            )
+            .add_system_set(
+                SystemSet::on_update(SubPanelState::Save)
+                    .with_system(save_menu::save_slot_button_action),
+            )
+            .add_system_set(
+                SystemSet::on_exit(SubPanelState::Save).with_system(despawn_children::<SubPanel>),
+            )
+            .add_system_set(
+                SystemSet::on_enter(SubPanelState::Settings)
+                    .with_system(settings_menu::spawn_settings_menu),
+            )
+            .add_system_set(
+                SystemSet::on_exit(SubPanelState::Settings)
+                    .with_system(despawn_children::<SubPanel>)
+                    .with_system(settings::stop_rebinding),
+            )
             .add_system_set(
                 SystemSet::on_exit(global::GameState::Menu)
                     .with_system(despawn_screen::<MenuScreen>),
@@ -84,8 +116,9 @@ enum SidePanelButtonAction {
     Equip,
     Magic,
     Limit,
+    Save,
+    Settings,
     Exit,
-    // TODO: maybe settings?
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
@@ -95,6 +128,8 @@ enum SubPanelState {
     Equip,
     Magic,
     Limit,
+    Save,
+    Settings,
 }
 
 #[derive(Component)]
@@ -121,6 +156,15 @@ struct WisdomText;
 #[derive(Component)]
 struct DefenseText;
 
+#[derive(Component)]
+struct SpeedText;
+
+#[derive(Component)]
+struct CritText;
+
+#[derive(Component)]
+struct EvasionText;
+
 #[derive(Component)]
 struct SubPanel;
 
@@ -129,7 +173,7 @@ struct ScrollList {
     position: f32,
 }
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct ItemButton(usize); // holds item id
 
 #[derive(Component)]
@@ -147,10 +191,10 @@ struct MagicSlotText(usize);
 #[derive(Default, Deref)]
 struct MagicSlotSelected(usize);
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct MagicSlotButton(usize);
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct MagicButton(global::PlayerAttack);
 
 #[derive(Component)]
@@ -162,30 +206,34 @@ struct EquipSlotText(global::ItemType);
 #[derive(Component, Deref, Debug)]
 struct EquipSlotSelected(global::ItemType);
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct EquipSlotButton(global::ItemType);
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct EquipButton(usize); // item id
 
 #[derive(Component)]
 struct LimitSlotText;
 
-#[derive(Component)]
+#[derive(Component, Debug)]
 struct LimitSlotButton;
 
 #[derive(Component)]
 struct LimitListContainer;
 
-#[derive(Component, Deref)]
+#[derive(Component, Debug, Deref)]
 struct LimitButton(global::PlayerAttack);
 
+#[derive(Component, Debug, Deref)]
+struct SaveSlotButton(usize); // slot number
+
 fn menu_setup(
     mut commands: Commands,
     font_assets: Res<FontAssets>,
     mut menu_state: ResMut<State<MenuState>>,
     mut subpanel_state: ResMut<State<SubPanelState>>,
     player: Res<global::Player>,
+    rng: Res<global::GameRng>,
 ) {
     // Ensures close_menu() doesn't conflict with open_menu() from overworld.rs.
     commands.insert_resource(Timer::from_seconds(global::MENU_TOGGLE_DURATION, false));
@@ -217,6 +265,8 @@ fn menu_setup(
                     SidePanelButtonAction::Equip,
                     SidePanelButtonAction::Magic,
                     SidePanelButtonAction::Limit,
+                    SidePanelButtonAction::Save,
+                    SidePanelButtonAction::Settings,
                     SidePanelButtonAction::Exit,
                 ] {
                     p.spawn_bundle(styled_button())
@@ -317,6 +367,30 @@ fn menu_setup(
                                 &font_assets,
                             ))
                             .insert(DefenseText);
+                            p.spawn_bundle(styled_text_bundle(
+                                format!("Speed: {}", player.stats.speed),
+                                &font_assets,
+                            ))
+                            .insert(SpeedText);
+                            p.spawn_bundle(styled_text_bundle(
+                                crit_text(&player.stats),
+                                &font_assets,
+                            ))
+                            .insert(CritText);
+                            p.spawn_bundle(styled_text_bundle(
+                                format!("Evasion: {}%", player.stats.evasion),
+                                &font_assets,
+                            ))
+                            .insert(EvasionText);
+                            p.spawn_bundle(styled_text_bundle(
+                                format!("Gold: {}", player.stats.gold),
+                                &font_assets,
+                            ));
+                            // Shown so that it can be included in bug reports.
+                            p.spawn_bundle(styled_text_bundle(
+                                format!("Seed: {}", rng.seed),
+                                &font_assets,
+                            ));
                         });
                 });
 
@@ -325,6 +399,30 @@ fn menu_setup(
         });
 }
 
+fn crit_text(stats: &global::Stats) -> String {
+    format!("Crit: {}% (+{}%)", stats.crit_chance, stats.crit_damage)
+}
+
+// Accessories change the crit and evasion chances. These are kept up to date here rather than in
+// equip_button_action, whose stat queries are already as many as a ParamSet can hold.
+fn update_chance_texts(
+    player: Res<global::Player>,
+    mut texts: ParamSet<(
+        Query<&mut Text, With<CritText>>,
+        Query<&mut Text, With<EvasionText>>,
+    )>,
+) {
+    if !player.is_changed() {
+        return;
+    }
+    for mut text in texts.p0().iter_mut() {
+        text.sections[0].value = crit_text(&player.stats);
+    }
+    for mut text in texts.p1().iter_mut() {
+        text.sections[0].value = format!("Evasion: {}%", player.stats.evasion);
+    }
+}
+
 fn side_panel_action(
     interaction_query: Query<
         (&Interaction, &SidePanelButtonAction),
@@ -371,6 +469,22 @@ fn side_panel_action(
                         _ => subpanel_state.set(SubPanelState::Limit).unwrap(),
                     }
                 }
+                SidePanelButtonAction::Save => {
+                    // Switch subpanel state.
+                    match *subpanel_state.current() {
+                        SubPanelState::Save => subpanel_state.set(SubPanelState::Inactive).unwrap(),
+                        _ => subpanel_state.set(SubPanelState::Save).unwrap(),
+                    }
+                }
+                SidePanelButtonAction::Settings => {
+                    // Switch subpanel state.
+                    match *subpanel_state.current() {
+                        SubPanelState::Settings => {
+                            subpanel_state.set(SubPanelState::Inactive).unwrap()
+                        }
+                        _ => subpanel_state.set(SubPanelState::Settings).unwrap(),
+                    }
+                }
                 SidePanelButtonAction::Exit => {
                     game_state.set(global::GameState::Overworld).unwrap();
                     menu_state.set(MenuState::Inactive).unwrap();
@@ -408,14 +522,19 @@ fn scroll_list_scroll(
 }
 
 fn close_menu(
-    time: Res<Time>,
+    time: Res<global::FrameTime>,
     mut timer: ResMut<Timer>,
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
+    rebinding: Res<settings::Rebinding>,
     mut game_state: ResMut<State<global::GameState>>,
     mut menu_state: ResMut<State<MenuState>>,
     mut subpanel_state: ResMut<State<SubPanelState>>,
 ) {
-    if timer.tick(time.delta()).finished() && keyboard_input.just_pressed(KeyCode::P) {
+    // Don't close the menu while its keys are being rebound.
+    if timer.tick(time.delta()).finished()
+        && rebinding.is_none()
+        && (actions.just_pressed(Action::OpenMenu) || actions.just_pressed(Action::Cancel))
+    {
         game_state.set(global::GameState::Overworld).unwrap();
         if *menu_state.current() == MenuState::Active {
             menu_state.set(MenuState::Inactive).unwrap();
@@ -875,6 +994,7 @@ mod equip_menu {
             Query<&mut Text, With<StrengthText>>,
             Query<&mut Text, With<WisdomText>>,
             Query<&mut Text, With<DefenseText>>,
+            Query<&mut Text, With<SpeedText>>,
         )>,
         mut desc_entity: Query<Entity, With<SubPanelDesc>>,
         equip_list_container: Query<Entity, With<EquipListContainer>>,
@@ -887,6 +1007,7 @@ mod equip_menu {
                 Without<StrengthText>,
                 Without<WisdomText>,
                 Without<DefenseText>,
+                Without<SpeedText>,
             ),
         >,
         mut player: ResMut<global::Player>,
@@ -974,6 +1095,9 @@ mod equip_menu {
                 for mut def_text in stats_query.p6().iter_mut() {
                     def_text.sections[0].value = format!("Defense: {}", player.stats.defense);
                 }
+                for mut speed_text in stats_query.p7().iter_mut() {
+                    speed_text.sections[0].value = format!("Speed: {}", player.stats.speed);
+                }
             } else if *interaction == Interaction::Hovered {
                 // Show equip description.
                 commands
@@ -1145,3 +1269,128 @@ mod limit_menu {
         }
     }
 }
+
+//==============================================================================
+// Settings menu
+//==============================================================================
+mod settings_menu {
+    use super::*;
+
+    pub(super) fn spawn_settings_menu(
+        mut commands: Commands,
+        font_assets: Res<FontAssets>,
+        subpanel: Query<Entity, With<SubPanel>>,
+        settings: Res<settings::Settings>,
+    ) {
+        commands.entity(subpanel.single()).with_children(|p| {
+            p.spawn_bundle(styled_sub_sub_panel()).with_children(|p| {
+                settings::spawn_settings_page(
+                    p,
+                    &font_assets,
+                    &settings,
+                    settings::SettingsPage::Display,
+                );
+                settings::spawn_settings_page(
+                    p,
+                    &font_assets,
+                    &settings,
+                    settings::SettingsPage::Sound,
+                );
+            });
+            p.spawn_bundle(styled_sub_sub_panel()).with_children(|p| {
+                settings::spawn_settings_page(
+                    p,
+                    &font_assets,
+                    &settings,
+                    settings::SettingsPage::Controls,
+                );
+            });
+        });
+    }
+}
+
+//==============================================================================
+// Save menu
+//==============================================================================
+mod save_menu {
+    use super::*;
+
+    fn slot_text(save_dir: &save::SaveDir, slot: usize) -> String {
+        match save_dir.read_slot(slot) {
+            Ok(save_game) => format!("Slot {}: {}", slot + 1, save_game.summary()),
+            Err(_) => format!("Slot {}: Empty", slot + 1),
+        }
+    }
+
+    pub(super) fn spawn_save_menu(
+        mut commands: Commands,
+        font_assets: Res<FontAssets>,
+        save_dir: Res<save::SaveDir>,
+        subpanel: Query<Entity, With<SubPanel>>,
+    ) {
+        commands.entity(subpanel.single()).with_children(|p| {
+            p.spawn_bundle(styled_sub_sub_panel()).with_children(|p| {
+                for slot in 0..save::SAVE_SLOTS {
+                    p.spawn_bundle(styled_subpanel_button())
+                        .insert(SaveSlotButton(slot))
+                        .with_children(|p| {
+                            p.spawn_bundle(styled_text_bundle(
+                                slot_text(&save_dir, slot),
+                                &font_assets,
+                            ));
+                        });
+                }
+            });
+            p.spawn_bundle(styled_sub_sub_panel())
+                .insert(SubPanelDescContainer)
+                .with_children(|p| {
+                    p.spawn_bundle(styled_text_bundle("", &font_assets))
+                        .insert(SubPanelDesc);
+                });
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn save_slot_button_action(
+        interaction_query: Query<
+            (&Interaction, &SaveSlotButton, &Children),
+            (Changed<Interaction>, With<Button>),
+        >,
+        mut text_query: Query<&mut Text>,
+        desc_entity: Query<Entity, With<SubPanelDesc>>,
+        font_assets: Res<FontAssets>,
+        player: Res<global::Player>,
+        run_stats: Res<global::RunStats>,
+        equipment_equipped: Res<global::PlayerEquipmentEquipped>,
+        magic_equipped: Res<global::PlayerMagicEquipped>,
+        limit_equipped: Res<global::PlayerLimitEquipped>,
+        item_inventory: Res<global::PlayerItemInventory>,
+        attack_inventory: Res<global::PlayerAttackInventory>,
+        rng: Res<global::GameRng>,
+        save_dir: Res<save::SaveDir>,
+    ) {
+        for (interaction, slot, children) in interaction_query.iter() {
+            if *interaction == Interaction::Clicked {
+                let save_game = save::SaveGame::new(
+                    &player,
+                    &run_stats,
+                    &equipment_equipped,
+                    &magic_equipped,
+                    &limit_equipped,
+                    &item_inventory,
+                    &attack_inventory,
+                    &rng,
+                );
+                let status = match save_dir.write_slot(**slot, &save_game) {
+                    Ok(()) => format!("Saved to slot {}.", **slot + 1),
+                    Err(e) => format!("Failed to save: {}", e),
+                };
+
+                *text_query.get_mut(children[0]).unwrap() =
+                    styled_text(slot_text(&save_dir, **slot), &font_assets);
+                *text_query.get_mut(desc_entity.single()).unwrap() =
+                    styled_text(status, &font_assets);
+            }
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use crate::{despawn_screen, global, ImageAssets};
+use crate::{
+    actions::{Action, Direction},
+    despawn_screen, global, shop, ImageAssets,
+};
 
 use bevy::{math::const_vec2, prelude::*};
-use rand::{prelude::SliceRandom, thread_rng, Rng};
-
-const TIME_STEP: f32 = 1.0 / 60.0;
+use rand::{prelude::SliceRandom, Rng};
 
 const PLAYER_SPEED: f32 = 640.0;
 const PLAYER_SPRINT: f32 = 1.5;
@@ -13,6 +14,11 @@ const MIN_ENEMY_SPAWN_STEPS: f32 = 64.;
 const ENEMY_TRY_SPAWN_STEPS: f32 = 64.;
 const ENEMY_SPAWN_CHANCE: usize = 10; // higher is lesser chance
 
+const INTERACTABLE_SIZE: Vec2 = const_vec2!([128.0, 128.0]);
+const INTERACT_DISTANCE: f32 = 96.;
+
+const CAMERA_SMOOTHING: f32 = 8.; // higher catches up with the player faster
+
 pub struct OverworldPlugin;
 
 impl Plugin for OverworldPlugin {
@@ -23,11 +29,13 @@ impl Plugin for OverworldPlugin {
             )
             .add_system_set(
                 SystemSet::on_update(global::GameState::Overworld)
-                    .with_system(move_player)
+                    .with_system(move_player.label(PlayerMovement))
                     .with_system(change_player_image)
-                    .with_system(spawn_monster)
+                    .with_system(spawn_monster.after(PlayerMovement))
+                    .with_system(interact.after(PlayerMovement))
                     .with_system(open_menu)
-                    .with_system(go_to_area),
+                    .with_system(go_to_area.after(PlayerMovement))
+                    .with_system(follow_player.after(PlayerMovement)),
             )
             // When exiting the state, despawn everything that was spawned for this screen
             .add_system_set(
@@ -37,9 +45,20 @@ impl Plugin for OverworldPlugin {
     }
 }
 
+// Everything that looks at where the player is runs after they've moved for the frame, so the
+// same seed and inputs always play out the same way.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+struct PlayerMovement;
+
 #[derive(Component)]
 struct OverworldScreen;
 
+#[derive(Component)]
+struct OverworldBackground;
+
+#[derive(Component)]
+struct InteractableSprite;
+
 #[derive(Default, Deref)]
 struct PlayerSteps(f32);
 
@@ -48,134 +67,168 @@ fn overworld_setup(
     image_assets: Res<ImageAssets>,
     areas: Res<global::Areas>,
     mut player: ResMut<global::Player>,
+    mut camera: Query<&mut Transform, With<global::WorldCamera>>,
 ) {
     // Ensure open_menu() doesn't conflict with close_menu() from menu/mod.rs.
     commands.insert_resource(Timer::from_seconds(global::MENU_TOGGLE_DURATION, false));
 
     // Overworld
+    let area = areas.get(&player.area).unwrap();
+    let size = area.map.size();
     commands
         .spawn_bundle(SpriteBundle {
             transform: Transform {
-                translation: Vec3::new(0., 0., 0.),
+                translation: (size / 2.).extend(0.),
                 ..default()
             },
-            texture: areas.get(&player.area).unwrap().background.clone(),
+            texture: area.background.clone(),
             sprite: Sprite {
-                custom_size: Some(global::BACKGROUND_SIZE),
+                custom_size: Some(size),
                 ..default()
             },
             ..default()
         })
         .insert(OverworldScreen)
-        .with_children(|p| {
-            player.entity = Some(
-                p.spawn_bundle(SpriteBundle {
-                    transform: Transform {
-                        translation: Vec3::new(player.x, player.y, 100.), // TODO: use player's last known coords
-                        ..default()
-                    },
-                    texture: image_assets.player_down.clone(),
-                    sprite: Sprite {
-                        custom_size: Some(PLAYER_SIZE),
-                        ..default()
-                    },
+        .insert(OverworldBackground);
+
+    spawn_interactables(&mut commands, area);
+
+    player.entity = Some(
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: Vec3::new(player.x, player.y, 100.),
                     ..default()
-                })
-                .id(),
-            );
-        });
+                },
+                texture: image_assets.player_down.clone(),
+                sprite: Sprite {
+                    custom_size: Some(PLAYER_SIZE),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(OverworldScreen)
+            .id(),
+    );
+
+    snap_camera(&mut camera, area, &player);
+}
+
+fn spawn_interactables(commands: &mut Commands, area: &global::Area) {
+    for interactable in &area.interactables {
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: interactable.position.extend(50.),
+                    ..default()
+                },
+                texture: interactable.sprite.clone(),
+                sprite: Sprite {
+                    custom_size: Some(INTERACTABLE_SIZE),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(OverworldScreen)
+            .insert(InteractableSprite);
+    }
 }
 
+// Where the camera should be to show the player, without looking past the edges of the area.
+fn camera_target(area: &global::Area, player: &global::Player) -> Vec2 {
+    area.map
+        .clamp_view(Vec2::new(player.x, player.y), global::BACKGROUND_SIZE)
+}
+
+fn snap_camera(
+    camera: &mut Query<&mut Transform, With<global::WorldCamera>>,
+    area: &global::Area,
+    player: &global::Player,
+) {
+    let target = camera_target(area, player);
+    for mut transform in camera.iter_mut() {
+        transform.translation.x = target.x;
+        transform.translation.y = target.y;
+    }
+}
+
+// Eases the camera after the player. Closing the same share of the gap every second keeps it
+// smooth at any frame rate.
+fn follow_player(
+    time: Res<global::FrameTime>,
+    areas: Res<global::Areas>,
+    player: Res<global::Player>,
+    mut camera: Query<&mut Transform, With<global::WorldCamera>>,
+) {
+    let target = camera_target(areas.get(&player.area).unwrap(), &player);
+    let catch_up = 1. - (-CAMERA_SMOOTHING * time.delta_seconds()).exp();
+    for mut transform in camera.iter_mut() {
+        let position = transform.translation.truncate().lerp(target, catch_up);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+// Takes the player through an exit once they walk onto it.
+#[allow(clippy::too_many_arguments)]
 fn go_to_area(
-    mut background: Query<&mut Handle<Image>, With<OverworldScreen>>,
-    mut transforms: Query<&mut Transform, Changed<Transform>>,
+    mut commands: Commands,
+    mut background: Query<
+        (&mut Handle<Image>, &mut Sprite, &mut Transform),
+        (With<OverworldBackground>, Without<global::WorldCamera>),
+    >,
+    interactable_sprites: Query<Entity, With<InteractableSprite>>,
+    mut transforms: Query<
+        &mut Transform,
+        (Without<OverworldBackground>, Without<global::WorldCamera>),
+    >,
+    mut camera: Query<&mut Transform, With<global::WorldCamera>>,
     mut player: ResMut<global::Player>,
     mut player_steps: ResMut<PlayerSteps>,
     areas: Res<global::Areas>,
 ) {
-    // TODO: rename these. these are horribly named...
-    fn is_below(player: &global::Player) -> bool {
-        player.y < -(global::BACKGROUND_SIZE[1] / 2. + PLAYER_SIZE[1] / 4.)
-    }
-    fn is_above(player: &global::Player) -> bool {
-        player.y > global::BACKGROUND_SIZE[1] / 2. + PLAYER_SIZE[1] / 4.
-    }
-    fn is_left(player: &global::Player) -> bool {
-        player.x < -(global::BACKGROUND_SIZE[0] / 2. + PLAYER_SIZE[0] / 4.)
-    }
-    fn is_right(player: &global::Player) -> bool {
-        player.x > global::BACKGROUND_SIZE[0] / 2. + PLAYER_SIZE[0] / 4.
-    }
-    fn get_new_below_y(player: &mut global::Player) {
-        player.y = -player.y + PLAYER_SIZE[1] / 2.;
-    }
-    fn get_new_above_y(player: &mut global::Player) {
-        player.y = -player.y - PLAYER_SIZE[1] / 2.;
-    }
-    fn get_new_left_x(player: &mut global::Player) {
-        player.x = -player.x + PLAYER_SIZE[0] / 2.;
-    }
-    fn get_new_right_x(player: &mut global::Player) {
-        player.x = -player.x - PLAYER_SIZE[0] / 2.;
-    }
+    let area = areas.get(&player.area).unwrap();
+    let exit = match area
+        .map
+        .tile_at(Vec2::new(player.x, player.y))
+        .and_then(|tile| {
+            area.exits
+                .iter()
+                .find(|exit| exit.tiles(&area.map).contains(tile))
+        }) {
+        Some(exit) => exit,
+        None => return,
+    };
 
-    let mut changed_area = true;
-    match &player.area {
-        0 if is_above(&player) => {
-            get_new_below_y(&mut player);
-            player.area = 1;
-        }
-        1 if is_below(&player) => {
-            get_new_above_y(&mut player);
-            player.area = 0;
-        }
-        1 if is_above(&player) => {
-            get_new_below_y(&mut player);
-            player.area = 2;
-        }
-        2 if is_below(&player) => {
-            get_new_above_y(&mut player);
-            player.area = 1;
-        }
-        2 if is_right(&player) => {
-            get_new_left_x(&mut player);
-            player.area = 3;
-        }
-        3 if is_left(&player) => {
-            get_new_right_x(&mut player);
-            player.area = 2;
-        }
-        3 if is_right(&player) => {
-            get_new_left_x(&mut player);
-            player.area = 4;
-        }
-        4 if is_left(&player) => {
-            get_new_right_x(&mut player);
-            player.area = 3;
-        }
-        4 if is_right(&player) => {
-            get_new_left_x(&mut player);
-            player.area = 5;
-        }
-        5 if is_left(&player) => {
-            get_new_right_x(&mut player);
-            player.area = 4;
-        }
-        _ => changed_area = false,
-    }
+    let area = areas.get(&exit.to).unwrap();
+    let arrival = area.map.tile_center(exit.arrive);
+    player.area = exit.to;
+    player.x = arrival.x;
+    player.y = arrival.y;
 
-    if changed_area {
-        *background.single_mut() = areas.get(&player.area).unwrap().background.clone();
-        let player_transform = &mut transforms.get_mut(player.entity.unwrap()).unwrap();
-        player_transform.translation.x = player.x;
-        player_transform.translation.y = player.y;
+    let (mut background_image, mut background_sprite, mut background_transform) =
+        background.single_mut();
+    *background_image = area.background.clone();
+    background_sprite.custom_size = Some(area.map.size());
+    background_transform.translation = (area.map.size() / 2.).extend(0.);
 
-        player_steps.0 = 0.;
+    for entity in interactable_sprites.iter() {
+        commands.entity(entity).despawn_recursive();
     }
+    spawn_interactables(&mut commands, area);
+
+    let player_transform = &mut transforms.get_mut(player.entity.unwrap()).unwrap();
+    player_transform.translation.x = player.x;
+    player_transform.translation.y = player.y;
+    snap_camera(&mut camera, area, &player);
+
+    player_steps.0 = 0.;
 }
 
 fn move_player(
-    keyboard_input: Res<Input<KeyCode>>,
+    time: Res<global::FrameTime>,
+    actions: Res<Input<Action>>,
+    areas: Res<global::Areas>,
     mut player: ResMut<global::Player>,
     mut transforms: Query<&mut Transform>,
     mut player_steps: ResMut<PlayerSteps>,
@@ -185,51 +238,57 @@ fn move_player(
     let mut direction_vertical = 0.0;
 
     // Only mono-directional movement allowed.
-    if keyboard_input.pressed(KeyCode::Left) {
+    if actions.pressed(Action::Move(Direction::Left)) {
         direction_horizontal -= 1.0;
-    } else if keyboard_input.pressed(KeyCode::Right) {
+    } else if actions.pressed(Action::Move(Direction::Right)) {
         direction_horizontal += 1.0;
-    } else if keyboard_input.pressed(KeyCode::Up) {
+    } else if actions.pressed(Action::Move(Direction::Up)) {
         direction_vertical += 1.0;
-    } else if keyboard_input.pressed(KeyCode::Down) {
+    } else if actions.pressed(Action::Move(Direction::Down)) {
         direction_vertical -= 1.0;
     }
 
     // Sprinting.
-    if keyboard_input.pressed(KeyCode::LShift) {
+    if actions.pressed(Action::Sprint) {
         direction_horizontal *= PLAYER_SPRINT;
         direction_vertical *= PLAYER_SPRINT;
     }
 
-    let steps_horizontal = direction_horizontal * PLAYER_SPEED * TIME_STEP;
-    let steps_vertical = direction_vertical * PLAYER_SPEED * TIME_STEP;
+    let steps_horizontal = direction_horizontal * PLAYER_SPEED * time.delta_seconds();
+    let steps_vertical = direction_vertical * PLAYER_SPEED * time.delta_seconds();
 
-    let new_player_position_x = player_transform.translation.x + steps_horizontal;
-    let new_player_position_y = player_transform.translation.y + steps_vertical;
+    // Walls and the edges of the area stop the player.
+    let old_position = player_transform.translation.truncate();
+    let new_position = areas.get(&player.area).unwrap().map.move_box(
+        old_position,
+        PLAYER_SIZE,
+        Vec2::new(steps_horizontal, steps_vertical),
+    );
 
-    player.x = new_player_position_x;
-    player.y = new_player_position_y;
+    player.x = new_position.x;
+    player.y = new_position.y;
 
-    // TODO: clamp within map area
-    player_transform.translation.x = new_player_position_x;
-    player_transform.translation.y = new_player_position_y;
+    player_transform.translation.x = new_position.x;
+    player_transform.translation.y = new_position.y;
 
-    player_steps.0 += steps_horizontal.abs() + steps_vertical.abs();
+    // Walking into a wall doesn't count towards encounters.
+    let moved = new_position - old_position;
+    player_steps.0 += moved.x.abs() + moved.y.abs();
 }
 
 fn change_player_image(
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
     image_assets: Res<ImageAssets>,
     player: ResMut<global::Player>,
     mut image: Query<&mut Handle<Image>>,
 ) {
-    let new_player_image = if keyboard_input.pressed(KeyCode::Left) {
+    let new_player_image = if actions.pressed(Action::Move(Direction::Left)) {
         Some(image_assets.player_left.clone())
-    } else if keyboard_input.pressed(KeyCode::Right) {
+    } else if actions.pressed(Action::Move(Direction::Right)) {
         Some(image_assets.player_right.clone())
-    } else if keyboard_input.pressed(KeyCode::Up) {
+    } else if actions.pressed(Action::Move(Direction::Up)) {
         Some(image_assets.player_up.clone())
-    } else if keyboard_input.pressed(KeyCode::Down) {
+    } else if actions.pressed(Action::Move(Direction::Down)) {
         Some(image_assets.player_down.clone())
     } else {
         // Don't change sprite if no input.
@@ -241,56 +300,126 @@ fn change_player_image(
     }
 }
 
-// TODO: spawn final boss monster during interaction
 fn spawn_monster(
     mut player_steps: ResMut<PlayerSteps>,
     mut game_state: ResMut<State<global::GameState>>,
-    mut commands: Commands,
-    enemy_table: Res<global::EnemyTable>,
-    mut enemy: ResMut<global::Enemy>,
+    mut encounter: ResMut<global::Encounter>,
     areas: Res<global::Areas>,
     player: Res<global::Player>,
+    mut rng: ResMut<global::GameRng>,
 ) {
-    let area_enemies = &areas.get(&player.area).unwrap().enemies;
-    if area_enemies.is_empty() {
+    let formations = &areas.get(&player.area).unwrap().formations;
+    if formations.is_empty() {
         // Length 0 means the area is a safe area.
         player_steps.0 = 0.;
         return;
     }
 
-    if player_steps.0 > MIN_ENEMY_SPAWN_STEPS + ENEMY_TRY_SPAWN_STEPS {
-        let should_spawn_enemy_roll = thread_rng().gen_range(0..ENEMY_SPAWN_CHANCE) == 0;
-        if should_spawn_enemy_roll {
-            let enemy_id_roll = area_enemies.choose(&mut rand::thread_rng()).unwrap();
-            let spawned_enemy = enemy_table.table.get(enemy_id_roll).unwrap();
-            let (enemy_stats, stats, attacks, loot_table) = (
-                spawned_enemy.0.clone(),
-                spawned_enemy.1.clone(),
-                spawned_enemy.2.clone(),
-                spawned_enemy.3.clone(),
-            );
+    if roll_encounter(&mut player_steps.0, &mut rng.encounters) {
+        let formation = formations
+            .choose_weighted(&mut rng.encounters, |formation| formation.weight)
+            .unwrap();
 
-            enemy.entity = Some(commands.spawn().id());
-            enemy.stats = stats;
-            enemy.enemy_stats = enemy_stats;
-            enemy.attacks = attacks;
-            enemy.loot_table = loot_table;
-            game_state.set(global::GameState::Battle).unwrap();
+        encounter.enemies = formation.enemies.clone();
+        game_state.set(global::GameState::Battle).unwrap();
+    }
+}
 
-            player_steps.0 = 0.;
-        } else {
-            player_steps.0 = MIN_ENEMY_SPAWN_STEPS;
+// Rolls for an encounter every ENEMY_TRY_SPAWN_STEPS walked once the player is past
+// MIN_ENEMY_SPAWN_STEPS, starting over after one. Steps left over from a failed roll count towards
+// the next, so a long frame rolls as often as several short ones covering the same distance.
+fn roll_encounter(steps: &mut f32, rng: &mut impl Rng) -> bool {
+    while *steps > MIN_ENEMY_SPAWN_STEPS + ENEMY_TRY_SPAWN_STEPS {
+        *steps -= ENEMY_TRY_SPAWN_STEPS;
+        if rng.gen_range(0..ENEMY_SPAWN_CHANCE) == 0 {
+            *steps = 0.;
+            return true;
+        }
+    }
+    false
+}
+
+fn interact(
+    actions: Res<Input<Action>>,
+    mut game_state: ResMut<State<global::GameState>>,
+    mut commands: Commands,
+    mut encounter: ResMut<global::Encounter>,
+    mut player_steps: ResMut<PlayerSteps>,
+    areas: Res<global::Areas>,
+    player: Res<global::Player>,
+) {
+    if !actions.just_pressed(Action::Confirm) {
+        return;
+    }
+
+    let player_position = Vec2::new(player.x, player.y);
+    let nearby = areas
+        .get(&player.area)
+        .unwrap()
+        .interactables
+        .iter()
+        .find(|i| i.position.distance(player_position) < INTERACT_DISTANCE);
+
+    if let Some(interactable) = nearby {
+        match interactable.kind {
+            global::InteractableKind::FinalBoss => {
+                encounter.enemies = vec![global::FINAL_BOSS_ID];
+                game_state.set(global::GameState::Battle).unwrap();
+
+                player_steps.0 = 0.;
+            }
+            global::InteractableKind::Shop(shop_id) => {
+                commands.insert_resource(shop::CurrentShop(shop_id));
+                game_state.set(global::GameState::Shop).unwrap();
+            }
         }
     }
 }
 
 fn open_menu(
-    time: Res<Time>,
+    time: Res<global::FrameTime>,
     mut timer: ResMut<Timer>,
-    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
     mut game_state: ResMut<State<global::GameState>>,
 ) {
-    if timer.tick(time.delta()).finished() && keyboard_input.just_pressed(KeyCode::P) {
+    if timer.tick(time.delta()).finished() && actions.just_pressed(Action::OpenMenu) {
         game_state.set(global::GameState::Menu).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    // Walks at full speed with the same seed at several frame rates. Counting the rolls between
+    // encounters leaves out how far the last step overshoots, the only thing allowed to differ.
+    #[test]
+    fn encounters_per_distance_dont_depend_on_frame_rate() {
+        let rolls_between_encounters = |fps: f32| {
+            let mut rng = ChaCha12Rng::seed_from_u64(3);
+            let mut steps = 0.;
+            let mut since_last = 0.;
+            let mut rolls = vec![];
+            while rolls.len() < 50 {
+                let step = PLAYER_SPEED / fps;
+                steps += step;
+                since_last += step;
+                if roll_encounter(&mut steps, &mut rng) {
+                    // The encounter's roll was somewhere in the last step.
+                    let past_min = since_last - step / 2. - MIN_ENEMY_SPAWN_STEPS;
+                    rolls.push((past_min / ENEMY_TRY_SPAWN_STEPS).round() as usize);
+                    since_last = 0.;
+                }
+            }
+            rolls
+        };
+
+        let at_60 = rolls_between_encounters(60.);
+        assert_eq!(at_60, rolls_between_encounters(30.));
+        assert_eq!(at_60, rolls_between_encounters(144.));
+        // Not always the first roll, or this wouldn't show much.
+        assert!(at_60.iter().any(|rolls| *rolls > 1));
+    }
+}
@@ -0,0 +1,576 @@
+use std::{
+    any::type_name,
+    fmt::Debug,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    app::AppExit,
+    input::{
+        gamepad::GamepadEventRaw,
+        keyboard::KeyboardInput,
+        mouse::{MouseScrollUnit, MouseWheel},
+        ElementState, InputSystem,
+    },
+    prelude::*,
+    ui::UiSystem,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{actions::ActionSystem, global, save, settings, FrameTimeSystem};
+
+// Bump this whenever the layout of Recording changes.
+const RECORDING_VERSION: u32 = 2;
+
+// Records a play session to a file, or plays one back. Together with the seed, the inputs and frame
+// times are all it takes to reproduce a session, so recordings can be attached to bug reports.
+// Frames are counted from the main menu on, so how long the assets take to load doesn't matter.
+pub(crate) enum ReplayPlugin {
+    Record(PathBuf),
+    Replay(Recording),
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = app
+            .world
+            .get_resource_or_insert_with(settings::Settings::default)
+            .clone();
+
+        match self {
+            ReplayPlugin::Record(path) => {
+                // Settle on a seed now so that it can be written into the recording.
+                let seed = app
+                    .world
+                    .get_resource_or_insert_with(global::NewGameSeed::default)
+                    .pick();
+                app.insert_resource(global::NewGameSeed(Some(seed)))
+                    .insert_resource(NewGamesOnly)
+                    .insert_resource(Recorder {
+                        path: path.clone(),
+                        recording: Recording::new(seed, settings),
+                    })
+                    .add_system_to_stage(CoreStage::PreUpdate, record_input.after(InputSystem))
+                    .add_system_to_stage(CoreStage::Last, record_frame);
+            }
+            ReplayPlugin::Replay(recording) => {
+                // Key bindings and text speed change what the inputs do, so use the recorded ones.
+                app.insert_resource(global::NewGameSeed(Some(recording.seed)))
+                    .insert_resource(recording.settings.clone())
+                    .insert_resource(NewGamesOnly)
+                    .insert_resource(Replayer::new(recording))
+                    .init_resource::<global::FrameTime>()
+                    .add_system_to_stage(CoreStage::First, replay_frame.after(FrameTimeSystem))
+                    .add_system_to_stage(CoreStage::Last, next_replay_frame);
+            }
+        }
+    }
+}
+
+impl ReplayPlugin {
+    // Picks the mode from the command line, if any.
+    pub(crate) fn from_args(args: &crate::cli::Args) -> Option<Self> {
+        if let Some(path) = &args.replay {
+            match Recording::read(path) {
+                Ok(recording) => Some(ReplayPlugin::Replay(recording)),
+                Err(e) => {
+                    eprintln!("Failed to read replay {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            args.record.clone().map(ReplayPlugin::Record)
+        }
+    }
+}
+
+// Buttons are recorded by what they do rather than where they were on screen, so that a replay
+// still works with a different window size. Every button component has to be registered here.
+pub(crate) trait RecordClicks {
+    fn record_clicks<T: Component + Debug>(&mut self) -> &mut Self;
+}
+
+impl RecordClicks for App {
+    fn record_clicks<T: Component + Debug>(&mut self) -> &mut Self {
+        self.add_system_to_stage(
+            CoreStage::PreUpdate,
+            record_button_clicks::<T>
+                .after(UiSystem::Focus)
+                .after(ActionSystem::Navigate),
+        )
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            replay_button_clicks::<T>
+                .after(UiSystem::Focus)
+                .after(ActionSystem::Navigate),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Recording {
+    pub(crate) version: u32,
+    pub(crate) seed: u64,
+    pub(crate) settings: settings::Settings,
+    pub(crate) frame_times: Vec<u64>, // nanoseconds, one per frame
+    pub(crate) inputs: Vec<(usize, RecordedInput)>, // frame number, input
+    #[serde(default)]
+    pub(crate) final_player: Option<save::PlayerSave>, // where the session left the player
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum RecordedInput {
+    KeyPressed(KeyCode),
+    KeyReleased(KeyCode),
+    Scrolled { x: f32, y: f32, pixels: bool },
+    Gamepad(GamepadEventRaw), // connections, buttons and sticks, before any dead zones
+    Clicked { button: String, action: String }, // button component, its Debug output
+}
+
+impl Recording {
+    fn new(seed: u64, settings: settings::Settings) -> Self {
+        Recording {
+            version: RECORDING_VERSION,
+            seed,
+            settings,
+            frame_times: vec![],
+            inputs: vec![],
+            final_player: None,
+        }
+    }
+
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let recording: Recording = ron::from_str(&fs::read_to_string(path)?)?;
+        if recording.version != RECORDING_VERSION {
+            anyhow::bail!("unsupported recording version {}", recording.version);
+        }
+        Ok(recording)
+    }
+
+    fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(
+            path,
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?,
+        )?;
+        Ok(())
+    }
+}
+
+// Saves on disk aren't part of a recording, so sessions that are recorded or replayed always start
+// from a new game. The main menu disables Continue and Load Game while this is around.
+pub(crate) struct NewGamesOnly;
+
+struct Recorder {
+    path: PathBuf,
+    recording: Recording,
+}
+
+impl Recorder {
+    fn push(&mut self, input: RecordedInput) {
+        let frame = self.recording.frame_times.len();
+        self.recording.inputs.push((frame, input));
+    }
+}
+
+pub(crate) struct Replayer {
+    recording: Recording,
+    frame: usize,
+    inputs: Range<usize>, // indices into recording.inputs for the current frame
+}
+
+impl Replayer {
+    fn new(recording: &Recording) -> Self {
+        let mut recording = recording.clone();
+        recording.inputs.sort_by_key(|(frame, _)| *frame);
+        let mut replayer = Replayer {
+            recording,
+            frame: 0,
+            inputs: 0..0,
+        };
+        replayer.find_inputs();
+        replayer
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.frame >= self.recording.frame_times.len()
+    }
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+        self.find_inputs();
+    }
+
+    // The inputs are sorted by frame, so the current frame's start where the last frame's ended.
+    fn find_inputs(&mut self) {
+        let start = self.inputs.end;
+        let count = self.recording.inputs[start..]
+            .iter()
+            .take_while(|(frame, _)| *frame == self.frame)
+            .count();
+        self.inputs = start..start + count;
+    }
+
+    fn inputs(&self) -> impl Iterator<Item = &RecordedInput> {
+        self.recording.inputs[self.inputs.clone()]
+            .iter()
+            .map(|(_, input)| input)
+    }
+}
+
+// The name a button component is recorded under. Several screens have their own
+// `MenuButtonAction`, so this includes the module path.
+fn button_name<T>() -> String {
+    type_name::<T>().to_string()
+}
+
+fn loading(state: &State<global::GameState>) -> bool {
+    *state.current() == global::GameState::Initialization
+}
+
+// Runs after the input systems, so gamepad events are recorded on the frame they arrived.
+fn record_input(
+    recorder: Option<ResMut<Recorder>>,
+    state: Res<State<global::GameState>>,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut gamepad_events: EventReader<GamepadEventRaw>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) if !loading(&state) => recorder,
+        _ => return,
+    };
+
+    for event in keyboard_events.iter() {
+        if let Some(key) = event.key_code {
+            recorder.push(match event.state {
+                ElementState::Pressed => RecordedInput::KeyPressed(key),
+                ElementState::Released => RecordedInput::KeyReleased(key),
+            });
+        }
+    }
+    for event in mouse_wheel_events.iter() {
+        recorder.push(RecordedInput::Scrolled {
+            x: event.x,
+            y: event.y,
+            pixels: matches!(event.unit, MouseScrollUnit::Pixel),
+        });
+    }
+    for event in gamepad_events.iter() {
+        recorder.push(RecordedInput::Gamepad(event.clone()));
+    }
+}
+
+fn record_button_clicks<T: Component + Debug>(
+    recorder: Option<ResMut<Recorder>>,
+    buttons: Query<(&Interaction, &T), Changed<Interaction>>,
+) {
+    if let Some(mut recorder) = recorder {
+        for (interaction, action) in buttons.iter() {
+            if *interaction == Interaction::Clicked {
+                recorder.push(RecordedInput::Clicked {
+                    button: button_name::<T>(),
+                    action: format!("{:?}", action),
+                });
+            }
+        }
+    }
+}
+
+// Writes the recording out when the game closes, along with where the player ended up so that a
+// replay can tell whether it played out the same way.
+fn record_frame(
+    recorder: Option<ResMut<Recorder>>,
+    state: Res<State<global::GameState>>,
+    time: Res<global::FrameTime>,
+    player: Res<global::Player>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) if !loading(&state) => recorder,
+        _ => return,
+    };
+
+    recorder
+        .recording
+        .frame_times
+        .push(time.delta().as_nanos() as u64);
+
+    if exit_events.iter().count() > 0 {
+        recorder.recording.final_player = Some(save::PlayerSave::new(&player));
+        match recorder.recording.write(&recorder.path) {
+            Ok(()) => info!("Saved recording to {}", recorder.path.display()),
+            Err(e) => error!("Failed to save recording: {}", e),
+        }
+    }
+}
+
+// Makes the frame take as long as it did when recorded and feeds in the recorded input. Runs right
+// after the frame time is measured, and before the input systems pick up the events.
+#[allow(clippy::too_many_arguments)]
+fn replay_frame(
+    replayer: Option<Res<Replayer>>,
+    state: Res<State<global::GameState>>,
+    mut frame_time: ResMut<global::FrameTime>,
+    mut keyboard_events: EventWriter<KeyboardInput>,
+    mut mouse_wheel_events: EventWriter<MouseWheel>,
+    mut gamepad_events: EventWriter<GamepadEventRaw>,
+    mut exit_events: EventWriter<AppExit>,
+    player: Res<global::Player>,
+) {
+    let replayer = match replayer {
+        Some(replayer) if !loading(&state) => replayer,
+        _ => return,
+    };
+
+    if replayer.finished() {
+        let player = save::PlayerSave::new(&player);
+        match &replayer.recording.final_player {
+            Some(expected) if *expected != player => warn!(
+                "Replay finished differently from the recording: expected {:?}, got {:?}",
+                expected, player
+            ),
+            _ => info!("Replay finished: {:?}", player),
+        }
+        exit_events.send(AppExit);
+        return;
+    }
+    frame_time.0 = Duration::from_nanos(replayer.recording.frame_times[replayer.frame]);
+
+    for input in replayer.inputs() {
+        let key_event = |key_code, state| KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key_code),
+            state,
+        };
+        match input {
+            RecordedInput::KeyPressed(key) => {
+                keyboard_events.send(key_event(*key, ElementState::Pressed))
+            }
+            RecordedInput::KeyReleased(key) => {
+                keyboard_events.send(key_event(*key, ElementState::Released))
+            }
+            RecordedInput::Scrolled { x, y, pixels } => mouse_wheel_events.send(MouseWheel {
+                unit: if *pixels {
+                    MouseScrollUnit::Pixel
+                } else {
+                    MouseScrollUnit::Line
+                },
+                x: *x,
+                y: *y,
+            }),
+            RecordedInput::Gamepad(event) => gamepad_events.send(event.clone()),
+            RecordedInput::Clicked { .. } => (),
+        }
+    }
+}
+
+// Clicks the button that was clicked in the recording. Runs after the UI has worked out which
+// button the mouse is over, so that the click isn't undone.
+fn replay_button_clicks<T: Component + Debug>(
+    replayer: Option<Res<Replayer>>,
+    state: Res<State<global::GameState>>,
+    mut buttons: Query<(&mut Interaction, &T)>,
+) {
+    let replayer = match replayer {
+        Some(replayer) if !loading(&state) => replayer,
+        _ => return,
+    };
+
+    for input in replayer.inputs() {
+        if let RecordedInput::Clicked { button, action } = input {
+            if *button != button_name::<T>() {
+                continue;
+            }
+            match buttons
+                .iter_mut()
+                .find(|(_, a)| format!("{:?}", a) == *action)
+            {
+                Some((mut interaction, _)) => *interaction = Interaction::Clicked,
+                None => warn!("Replay clicked {} {}, which isn't there", button, action),
+            }
+        }
+    }
+}
+
+fn next_replay_frame(replayer: Option<ResMut<Replayer>>, state: Res<State<global::GameState>>) {
+    if let Some(mut replayer) = replayer.filter(|_| !loading(&state)) {
+        replayer.next_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{
+        ecs::event::Events,
+        input::{
+            gamepad::{Gamepad, GamepadButton, GamepadButtonType, GamepadEventType},
+            InputPlugin,
+        },
+    };
+
+    #[derive(Component, Debug)]
+    enum TestButton {
+        Attack,
+        Run,
+    }
+
+    fn app(plugin: ReplayPlugin) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(InputPlugin)
+            .insert_resource(settings::Settings::default())
+            .insert_resource(global::NewGameSeed(Some(7)))
+            .init_resource::<global::FrameTime>()
+            .init_resource::<global::Player>()
+            .add_state(global::GameState::MainMenu)
+            .add_plugin(plugin)
+            .record_clicks::<TestButton>();
+        app
+    }
+
+    fn press(app: &mut App, key: KeyCode, state: ElementState) {
+        app.world
+            .get_resource_mut::<Events<KeyboardInput>>()
+            .unwrap()
+            .send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key),
+                state,
+            });
+    }
+
+    #[test]
+    fn inputs_are_recorded_by_frame() {
+        let path = std::env::temp_dir().join("rapid_fantasy_replay_test.ron");
+        let mut app = app(ReplayPlugin::Record(path.clone()));
+        let button = app
+            .world
+            .spawn()
+            .insert_bundle((Interaction::None, TestButton::Run))
+            .id();
+
+        app.update();
+        press(&mut app, KeyCode::Space, ElementState::Pressed);
+        app.update();
+        *app.world.get_mut::<Interaction>(button).unwrap() = Interaction::Clicked;
+        press(&mut app, KeyCode::Space, ElementState::Released);
+        app.world
+            .get_resource_mut::<Events<GamepadEventRaw>>()
+            .unwrap()
+            .send(GamepadEventRaw(Gamepad(0), GamepadEventType::Connected));
+        app.update();
+        app.world
+            .get_resource_mut::<Events<AppExit>>()
+            .unwrap()
+            .send(AppExit);
+        app.update();
+
+        let recording = Recording::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(7, recording.seed);
+        assert_eq!(4, recording.frame_times.len());
+        assert_eq!(4, recording.inputs.len());
+        for input in [
+            (1, RecordedInput::KeyPressed(KeyCode::Space)),
+            (2, RecordedInput::KeyReleased(KeyCode::Space)),
+            (
+                2,
+                RecordedInput::Gamepad(GamepadEventRaw(Gamepad(0), GamepadEventType::Connected)),
+            ),
+            (
+                2,
+                RecordedInput::Clicked {
+                    button: button_name::<TestButton>(),
+                    action: "Run".to_string(),
+                },
+            ),
+        ] {
+            assert!(recording.inputs.contains(&input), "{:?} missing", input);
+        }
+    }
+
+    #[test]
+    fn replay_feeds_inputs_back_in() {
+        let mut recording = Recording::new(42, settings::Settings::default());
+        recording.frame_times = vec![16_000_000, 33_000_000, 16_000_000];
+        recording.inputs = vec![
+            (1, RecordedInput::KeyPressed(KeyCode::Space)),
+            (
+                1,
+                RecordedInput::Clicked {
+                    button: button_name::<TestButton>(),
+                    action: "Run".to_string(),
+                },
+            ),
+            (
+                1,
+                RecordedInput::Gamepad(GamepadEventRaw(
+                    Gamepad(0),
+                    GamepadEventType::ButtonChanged(GamepadButtonType::South, 1.),
+                )),
+            ),
+            (2, RecordedInput::KeyReleased(KeyCode::Space)),
+        ];
+        let mut app = app(ReplayPlugin::Replay(recording));
+        let attack = app
+            .world
+            .spawn()
+            .insert_bundle((Interaction::None, TestButton::Attack))
+            .id();
+        let run = app
+            .world
+            .spawn()
+            .insert_bundle((Interaction::None, TestButton::Run))
+            .id();
+        assert_eq!(
+            Some(42),
+            app.world.get_resource::<global::NewGameSeed>().unwrap().0
+        );
+
+        let pressed = |app: &App| {
+            app.world
+                .get_resource::<Input<KeyCode>>()
+                .unwrap()
+                .pressed(KeyCode::Space)
+        };
+        let delta = |app: &App| {
+            app.world
+                .get_resource::<global::FrameTime>()
+                .unwrap()
+                .delta()
+        };
+
+        app.update();
+        assert!(!pressed(&app));
+        assert_eq!(Duration::from_millis(16), delta(&app));
+
+        app.update();
+        assert!(pressed(&app));
+        assert!(app
+            .world
+            .get_resource::<Input<GamepadButton>>()
+            .unwrap()
+            .pressed(GamepadButton(Gamepad(0), GamepadButtonType::South)));
+        assert_eq!(Duration::from_millis(33), delta(&app));
+        assert_eq!(
+            Interaction::Clicked,
+            *app.world.get::<Interaction>(run).unwrap()
+        );
+        assert_eq!(
+            Interaction::None,
+            *app.world.get::<Interaction>(attack).unwrap()
+        );
+
+        app.update();
+        assert!(!pressed(&app));
+
+        // Out of frames, so the game closes.
+        app.update();
+        let exits = app.world.get_resource::<Events<AppExit>>().unwrap();
+        assert_eq!(1, exits.get_reader().iter(exits).count());
+    }
+}
@@ -0,0 +1,657 @@
+use std::{fmt, fs, io, path::PathBuf};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::global;
+
+// Only bump this when older saves can't be read any more. Fields that are added get a serde default
+// instead, so that saves from before them still load.
+pub(crate) const SAVE_VERSION: u32 = 1;
+pub(crate) const SAVE_SLOTS: usize = 3;
+
+// On-disk form of global::PlayerProgress. Items and attacks are stored by id and looked up in
+// their tables again when loading.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct SaveGame {
+    pub(crate) version: u32,
+    pub(crate) player: PlayerSave,
+    pub(crate) play_time: f32,
+    pub(crate) battles_won: u32,
+    pub(crate) item_inventory: Vec<(usize, usize)>, // item id, quantity
+    pub(crate) attack_inventory: Vec<usize>,        // attack ids
+    pub(crate) magic_equipped: [Option<usize>; 4],
+    pub(crate) limit_equipped: usize,
+    pub(crate) weapon: Option<usize>,
+    pub(crate) armor: Option<usize>,
+    pub(crate) accessory: Option<usize>,
+    // Saves from before the game was seeded start their random streams over from seed 0.
+    #[serde(default)]
+    pub(crate) seed: u64,
+    #[serde(default)]
+    pub(crate) rng_positions: [u64; 3], // see global::GameRng::positions
+    #[serde(default = "old_origin")]
+    pub(crate) origin: Origin, // what the player's x and y are measured from
+}
+
+// Before areas could be any size, positions were measured from the middle of the screen, which every
+// area filled. Saves from then are moved over to the bottom left corner when they're loaded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Origin {
+    Center,
+    BottomLeft,
+}
+
+fn old_origin() -> Origin {
+    Origin::Center
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct PlayerSave {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) area: usize,
+    pub(crate) limit: u8,
+    pub(crate) hp_max: i32,
+    pub(crate) mp_max: i32,
+    pub(crate) hp: i32,
+    pub(crate) mp: i32,
+    pub(crate) strength: i32,
+    pub(crate) wisdom: i32,
+    pub(crate) defense: i32,
+    #[serde(default = "default_speed")]
+    pub(crate) speed: i32,
+    #[serde(default = "default_crit_chance")]
+    pub(crate) crit_chance: i32,
+    #[serde(default = "default_crit_damage")]
+    pub(crate) crit_damage: i32,
+    #[serde(default = "default_evasion")]
+    pub(crate) evasion: i32,
+    pub(crate) level: i32,
+    pub(crate) experience: i32,
+    pub(crate) gold: i32,
+}
+
+// Saves from before speed, critical hits and misses start out with the player's base values.
+fn default_speed() -> i32 {
+    global::BASE_SPEED
+}
+
+fn default_crit_chance() -> i32 {
+    global::BASE_CRIT_CHANCE
+}
+
+fn default_crit_damage() -> i32 {
+    global::BASE_CRIT_DAMAGE
+}
+
+fn default_evasion() -> i32 {
+    global::BASE_EVASION
+}
+
+#[derive(Debug)]
+pub(crate) enum SaveError {
+    Io(io::Error),
+    Format(ron::Error),
+    UnsupportedVersion(u32),
+    UnknownItem(usize),
+    UnknownAttack(usize),
+    UnknownArea(usize),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "could not access save file: {}", e),
+            SaveError::Format(e) => write!(f, "save file is corrupted: {}", e),
+            SaveError::UnsupportedVersion(v) => write!(f, "unsupported save version {}", v),
+            SaveError::UnknownItem(id) => write!(f, "save refers to unknown item {}", id),
+            SaveError::UnknownAttack(id) => write!(f, "save refers to unknown attack {}", id),
+            SaveError::UnknownArea(id) => write!(f, "save refers to unknown area {}", id),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(e: ron::Error) -> Self {
+        SaveError::Format(e)
+    }
+}
+
+impl PlayerSave {
+    pub(crate) fn new(player: &global::Player) -> Self {
+        let stats = &player.stats;
+        PlayerSave {
+            x: player.x,
+            y: player.y,
+            area: player.area,
+            limit: player.limit,
+            hp_max: stats.hp_max,
+            mp_max: stats.mp_max,
+            hp: stats.hp,
+            mp: stats.mp,
+            strength: stats.strength,
+            wisdom: stats.wisdom,
+            defense: stats.defense,
+            speed: stats.speed,
+            crit_chance: stats.crit_chance,
+            crit_damage: stats.crit_damage,
+            evasion: stats.evasion,
+            level: stats.level,
+            experience: stats.experience,
+            gold: stats.gold,
+        }
+    }
+}
+
+impl SaveGame {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        player: &global::Player,
+        run_stats: &global::RunStats,
+        equipment_equipped: &global::PlayerEquipmentEquipped,
+        magic_equipped: &global::PlayerMagicEquipped,
+        limit_equipped: &global::PlayerLimitEquipped,
+        item_inventory: &global::PlayerItemInventory,
+        attack_inventory: &global::PlayerAttackInventory,
+        rng: &global::GameRng,
+    ) -> Self {
+        let mut items = item_inventory
+            .iter()
+            .map(|(id, quantity)| (*id, *quantity))
+            .collect::<Vec<(usize, usize)>>();
+        items.sort_unstable();
+        let mut attacks = attack_inventory
+            .iter()
+            .map(|attack| attack.id)
+            .collect::<Vec<usize>>();
+        attacks.sort_unstable();
+
+        SaveGame {
+            version: SAVE_VERSION,
+            player: PlayerSave::new(player),
+            play_time: run_stats.play_time,
+            battles_won: run_stats.battles_won,
+            item_inventory: items,
+            attack_inventory: attacks,
+            magic_equipped: magic_equipped.0.clone().map(|magic| magic.map(|m| m.id)),
+            limit_equipped: limit_equipped.id,
+            weapon: equipment_equipped.weapon.as_ref().map(|item| item.id),
+            armor: equipment_equipped.armor.as_ref().map(|item| item.id),
+            accessory: equipment_equipped.accessory.as_ref().map(|item| item.id),
+            seed: rng.seed,
+            rng_positions: rng.positions(),
+            origin: Origin::BottomLeft,
+        }
+    }
+
+    // Rebuilds the player's progress. Equipment bonuses are already part of the saved stats.
+    pub(crate) fn restore(
+        &self,
+        battle_sprite: Handle<Image>,
+        attack_table: &global::PlayerAttackTable,
+        item_table: &global::ItemTable,
+        areas: &global::Areas,
+    ) -> Result<global::PlayerProgress, SaveError> {
+        let get_attack = |id: usize| {
+            attack_table
+                .table
+                .get(&(id as u32))
+                .cloned()
+                .ok_or(SaveError::UnknownAttack(id))
+        };
+        let get_item = |id: usize| {
+            item_table
+                .get(&id)
+                .cloned()
+                .ok_or(SaveError::UnknownItem(id))
+        };
+        let get_equip = |id: Option<usize>| id.map(get_item).transpose();
+
+        let saved = &self.player;
+        if !areas.contains_key(&saved.area) {
+            return Err(SaveError::UnknownArea(saved.area));
+        }
+        // Not the middle of the area's map, which may have been resized since.
+        let offset = match self.origin {
+            Origin::Center => global::BACKGROUND_SIZE / 2.,
+            Origin::BottomLeft => Vec2::ZERO,
+        };
+        let player = global::Player {
+            entity: None,
+            x: saved.x + offset.x,
+            y: saved.y + offset.y,
+            stats: global::Stats {
+                hp_max: saved.hp_max,
+                mp_max: saved.mp_max,
+                hp: saved.hp,
+                mp: saved.mp,
+                strength: saved.strength,
+                wisdom: saved.wisdom,
+                defense: saved.defense,
+                speed: saved.speed,
+                crit_chance: saved.crit_chance,
+                crit_damage: saved.crit_damage,
+                evasion: saved.evasion,
+                level: saved.level,
+                experience: saved.experience,
+                gold: saved.gold,
+                battle_sprite,
+            },
+            limit: saved.limit,
+            area: saved.area,
+            status_effects: global::StatusEffects::default(),
+        };
+
+        let mut items = HashMap::new();
+        for (id, quantity) in &self.item_inventory {
+            get_item(*id)?;
+            items.insert(*id, *quantity);
+        }
+
+        let mut attacks = HashSet::new();
+        for id in &self.attack_inventory {
+            attacks.insert(get_attack(*id)?);
+        }
+
+        let mut magic_equipped = global::PlayerMagicEquipped::default();
+        for (slot, id) in self.magic_equipped.iter().enumerate() {
+            magic_equipped[slot] = id.map(get_attack).transpose()?;
+        }
+
+        Ok(global::PlayerProgress {
+            player,
+            run_stats: global::RunStats {
+                play_time: self.play_time,
+                battles_won: self.battles_won,
+            },
+            equipment_equipped: global::PlayerEquipmentEquipped {
+                weapon: get_equip(self.weapon)?,
+                armor: get_equip(self.armor)?,
+                accessory: get_equip(self.accessory)?,
+            },
+            magic_equipped,
+            limit_equipped: global::PlayerLimitEquipped(get_attack(self.limit_equipped)?),
+            item_inventory: global::PlayerItemInventory(items),
+            attack_inventory: global::PlayerAttackInventory(attacks),
+            rng: global::GameRng::restore(self.seed, self.rng_positions),
+        })
+    }
+
+    // Short description of the save, e.g. for listing save slots.
+    pub(crate) fn summary(&self) -> String {
+        format!(
+            "Lv {} - Area {} - {} - {} G",
+            self.player.level,
+            self.player.area,
+            global::format_play_time(self.play_time),
+            self.player.gold
+        )
+    }
+
+    fn to_ron(&self) -> Result<String, SaveError> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::new(),
+        )?)
+    }
+
+    fn from_ron(s: &str) -> Result<Self, SaveError> {
+        let save_game: SaveGame = ron::from_str(s)?;
+        if save_game.version != SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(save_game.version));
+        }
+        Ok(save_game)
+    }
+}
+
+// Where the save slots are kept: the user's data directory in the game, a directory of their own
+// in tests.
+pub(crate) struct SaveDir(pub(crate) PathBuf);
+
+impl Default for SaveDir {
+    fn default() -> Self {
+        SaveDir(
+            dirs::data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("rapid_fantasy_remake")
+                .join("saves"),
+        )
+    }
+}
+
+impl SaveDir {
+    fn slot_path(&self, slot: usize) -> PathBuf {
+        self.0.join(format!("slot{}.ron", slot))
+    }
+
+    pub(crate) fn write_slot(&self, slot: usize, save_game: &SaveGame) -> Result<(), SaveError> {
+        fs::create_dir_all(&self.0)?;
+        fs::write(self.slot_path(slot), save_game.to_ron()?)?;
+        Ok(())
+    }
+
+    pub(crate) fn read_slot(&self, slot: usize) -> Result<SaveGame, SaveError> {
+        SaveGame::from_ron(&fs::read_to_string(self.slot_path(slot))?)
+    }
+
+    // Reads the slot and checks that it can be restored with the current content, which a save
+    // naming an item, attack or area that has since been taken out can't.
+    pub(crate) fn read_loadable_slot(
+        &self,
+        slot: usize,
+        attack_table: &global::PlayerAttackTable,
+        item_table: &global::ItemTable,
+        areas: &global::Areas,
+    ) -> Result<SaveGame, SaveError> {
+        let save_game = self.read_slot(slot)?;
+        save_game.restore(Handle::default(), attack_table, item_table, areas)?;
+        Ok(save_game)
+    }
+
+    // The most recently written slot that can be loaded, so that Continue doesn't pick a corrupted
+    // save or one from another version over an older one that works.
+    pub(crate) fn latest_slot(
+        &self,
+        attack_table: &global::PlayerAttackTable,
+        item_table: &global::ItemTable,
+        areas: &global::Areas,
+    ) -> Option<usize> {
+        (0..SAVE_SLOTS)
+            .filter(|slot| {
+                self.read_loadable_slot(*slot, attack_table, item_table, areas)
+                    .is_ok()
+            })
+            .filter_map(|slot| {
+                let modified = fs::metadata(self.slot_path(slot)).ok()?.modified().ok()?;
+                Some((modified, slot))
+            })
+            .max()
+            .map(|(_, slot)| slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content, tile_map};
+    use rand::Rng;
+
+    fn tables() -> (global::PlayerAttackTable, global::ItemTable) {
+        let tables = content::Tables::for_tests();
+        (tables.attacks, tables.items)
+    }
+
+    fn areas() -> global::Areas {
+        content::Tables::for_tests().areas
+    }
+
+    // A game some way in, so that no resource is at its new game value.
+    fn progress(
+        attack_table: &global::PlayerAttackTable,
+        item_table: &global::ItemTable,
+    ) -> global::PlayerProgress {
+        let mut progress =
+            global::PlayerProgress::new(&crate::ImageAssets::default(), attack_table, 1234);
+
+        progress.player.x = 120.5;
+        progress.player.y = -64.;
+        progress.player.area = 3;
+        progress.player.limit = 42;
+        progress.player.stats.level = 3;
+        progress.player.stats.experience = 1234;
+        progress.player.stats.gold = 999;
+        progress.player.stats.hp = 57;
+
+        progress.run_stats.play_time = 3723.25;
+        progress.run_stats.battles_won = 17;
+
+        let sword = item_table.get(&10).unwrap().clone();
+        progress.player.stats.add_item_stats(&sword.stats);
+        progress.equipment_equipped.weapon = Some(sword);
+        progress.equipment_equipped.accessory = Some(item_table.get(&28).unwrap().clone());
+        progress.item_inventory.insert(10, 1);
+        progress.item_inventory.insert(28, 2);
+        progress.item_inventory.insert(0, 3);
+
+        for id in 10..16 {
+            progress
+                .attack_inventory
+                .insert(attack_table.table.get(&id).unwrap().clone());
+        }
+        progress.magic_equipped[1] = Some(attack_table.table.get(&11).unwrap().clone());
+        progress.magic_equipped[3] = None;
+        progress.limit_equipped =
+            global::PlayerLimitEquipped(attack_table.table.get(&2).unwrap().clone());
+        for _ in 0..3 {
+            progress.rng.encounters.gen::<u32>();
+            progress.rng.ai.gen::<u64>();
+        }
+
+        progress
+    }
+
+    fn save_game(progress: &global::PlayerProgress) -> SaveGame {
+        SaveGame::new(
+            &progress.player,
+            &progress.run_stats,
+            &progress.equipment_equipped,
+            &progress.magic_equipped,
+            &progress.limit_equipped,
+            &progress.item_inventory,
+            &progress.attack_inventory,
+            &progress.rng,
+        )
+    }
+
+    fn round_trip(progress: &global::PlayerProgress) -> global::PlayerProgress {
+        let (attack_table, item_table) = tables();
+        let ron = save_game(progress).to_ron().unwrap();
+        SaveGame::from_ron(&ron)
+            .unwrap()
+            .restore(Handle::default(), &attack_table, &item_table, &areas())
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip_player() {
+        let (attack_table, item_table) = tables();
+        let saved = progress(&attack_table, &item_table);
+        let loaded = round_trip(&saved);
+
+        assert_eq!(None, loaded.player.entity);
+        assert_eq!(saved.player.x, loaded.player.x);
+        assert_eq!(saved.player.y, loaded.player.y);
+        assert_eq!(saved.player.area, loaded.player.area);
+        assert_eq!(saved.player.limit, loaded.player.limit);
+        assert_eq!(saved.player.stats, loaded.player.stats);
+    }
+
+    #[test]
+    fn round_trip_run_stats() {
+        let (attack_table, item_table) = tables();
+        let saved = progress(&attack_table, &item_table);
+        let loaded = round_trip(&saved);
+
+        assert_eq!(saved.run_stats.play_time, loaded.run_stats.play_time);
+        assert_eq!(saved.run_stats.battles_won, loaded.run_stats.battles_won);
+    }
+
+    #[test]
+    fn round_trip_inventories() {
+        let (attack_table, item_table) = tables();
+        let saved = progress(&attack_table, &item_table);
+        let loaded = round_trip(&saved);
+
+        assert_eq!(saved.item_inventory.0, loaded.item_inventory.0);
+        assert_eq!(saved.attack_inventory.0, loaded.attack_inventory.0);
+    }
+
+    #[test]
+    fn round_trip_equipped() {
+        let (attack_table, item_table) = tables();
+        let saved = progress(&attack_table, &item_table);
+        let loaded = round_trip(&saved);
+
+        assert_eq!(saved.magic_equipped.0, loaded.magic_equipped.0);
+        assert_eq!(saved.limit_equipped.0, loaded.limit_equipped.0);
+
+        let ids = |equipped: &global::PlayerEquipmentEquipped| {
+            [&equipped.weapon, &equipped.armor, &equipped.accessory]
+                .map(|equip| equip.as_ref().map(|item| item.id))
+        };
+        assert_eq!(
+            ids(&saved.equipment_equipped),
+            ids(&loaded.equipment_equipped)
+        );
+    }
+
+    #[test]
+    fn round_trip_rng() {
+        let (attack_table, item_table) = tables();
+        let mut saved = progress(&attack_table, &item_table);
+        let mut loaded = round_trip(&saved);
+
+        assert_eq!(saved.rng.seed, loaded.rng.seed);
+        assert_eq!(
+            saved.rng.encounters.gen::<u32>(),
+            loaded.rng.encounters.gen::<u32>()
+        );
+        assert_eq!(saved.rng.loot.gen::<u32>(), loaded.rng.loot.gen::<u32>());
+        assert_eq!(saved.rng.ai.gen::<u32>(), loaded.rng.ai.gen::<u32>());
+    }
+
+    #[test]
+    fn new_game_round_trip() {
+        let (attack_table, _) = tables();
+        let saved = global::PlayerProgress::new(&crate::ImageAssets::default(), &attack_table, 0);
+        let loaded = round_trip(&saved);
+
+        assert_eq!(saved.player.stats, loaded.player.stats);
+        assert_eq!(saved.item_inventory.0, loaded.item_inventory.0);
+        assert_eq!(saved.attack_inventory.0, loaded.attack_inventory.0);
+        assert_eq!(saved.magic_equipped.0, loaded.magic_equipped.0);
+    }
+
+    #[test]
+    fn unknown_ids_are_rejected() {
+        let (attack_table, item_table) = tables();
+        let mut save = save_game(&progress(&attack_table, &item_table));
+        save.item_inventory.push((1000, 1));
+        assert!(matches!(
+            save.restore(Handle::default(), &attack_table, &item_table, &areas()),
+            Err(SaveError::UnknownItem(1000))
+        ));
+    }
+
+    // Drops the given fields from a save, as if it had been written before they were added.
+    fn without_fields(save: &SaveGame, fields: &[&str]) -> SaveGame {
+        let ron = save.to_ron().unwrap();
+        let lines: Vec<_> = ron
+            .lines()
+            .filter(|line| !fields.iter().any(|field| line.trim().starts_with(field)))
+            .collect();
+        SaveGame::from_ron(&lines.join("\n")).unwrap()
+    }
+
+    #[test]
+    fn saves_from_before_the_seed_load() {
+        let (attack_table, item_table) = tables();
+        let save = save_game(&progress(&attack_table, &item_table));
+        let save = without_fields(&save, &["seed:", "rng_positions:"]);
+        let loaded = save
+            .restore(Handle::default(), &attack_table, &item_table, &areas())
+            .unwrap();
+
+        assert_eq!(0, loaded.rng.seed);
+        assert_eq!([0; 3], loaded.rng.positions());
+    }
+
+    #[test]
+    fn saves_from_before_speed_load() {
+        let (attack_table, item_table) = tables();
+        let save = save_game(&progress(&attack_table, &item_table));
+        let save = without_fields(&save, &["speed:"]);
+
+        assert_eq!(global::BASE_SPEED, save.player.speed);
+    }
+
+    #[test]
+    fn saves_from_before_crits_load() {
+        let (attack_table, item_table) = tables();
+        let save = save_game(&progress(&attack_table, &item_table));
+        let save = without_fields(&save, &["crit_chance:", "crit_damage:", "evasion:"]);
+
+        assert_eq!(global::BASE_CRIT_CHANCE, save.player.crit_chance);
+        assert_eq!(global::BASE_CRIT_DAMAGE, save.player.crit_damage);
+        assert_eq!(global::BASE_EVASION, save.player.evasion);
+    }
+
+    #[test]
+    fn saves_from_before_areas_had_any_size_are_moved_to_the_corner() {
+        let (attack_table, item_table) = tables();
+        let mut save = save_game(&progress(&attack_table, &item_table));
+        save.player.x = -50.;
+        save.player.y = 10.;
+        let save = without_fields(&save, &["origin:"]);
+        // The area has grown since, which shouldn't move where the player was.
+        let mut areas = areas();
+        areas.0.get_mut(&save.player.area).unwrap().map =
+            tile_map::TileMap::parse(&vec![".".repeat(30); 20]).unwrap();
+        let loaded = save
+            .restore(Handle::default(), &attack_table, &item_table, &areas)
+            .unwrap();
+
+        assert_eq!(640. - 50., loaded.player.x);
+        assert_eq!(360. + 10., loaded.player.y);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let (attack_table, item_table) = tables();
+        let mut save = save_game(&progress(&attack_table, &item_table));
+        save.version = SAVE_VERSION + 1;
+        assert!(matches!(
+            SaveGame::from_ron(&save.to_ron().unwrap()),
+            Err(SaveError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn latest_slot_skips_saves_that_cant_be_loaded() {
+        let (attack_table, item_table) = tables();
+        let areas = areas();
+        let latest = |dir: &SaveDir| dir.latest_slot(&attack_table, &item_table, &areas);
+        let dir = SaveDir(std::env::temp_dir().join("rapid_fantasy_latest_slot_test"));
+        let _ = fs::remove_dir_all(&dir.0);
+        assert_eq!(None, latest(&dir));
+
+        let save = save_game(&progress(&attack_table, &item_table));
+        dir.write_slot(1, &save).unwrap();
+        assert_eq!(Some(1), latest(&dir));
+
+        // Written later, but one is from another version and the other has an item that's gone.
+        let mut other_version = save.clone();
+        other_version.version = SAVE_VERSION + 1;
+        dir.write_slot(0, &other_version).unwrap();
+        let mut unknown_item = save;
+        unknown_item.item_inventory.push((999, 1));
+        dir.write_slot(2, &unknown_item).unwrap();
+        assert!(matches!(
+            dir.read_loadable_slot(2, &attack_table, &item_table, &areas),
+            Err(SaveError::UnknownItem(999))
+        ));
+        assert_eq!(Some(1), latest(&dir));
+
+        fs::remove_dir_all(&dir.0).unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::{fmt, fs, path::PathBuf};
+
+use bevy::{prelude::*, window::WindowMode};
+use serde::{Deserialize, Serialize};
+
+use crate::{actions::Action, global, replay::RecordClicks, FontAssets};
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.record_clicks::<SettingButton>()
+            .init_resource::<Rebinding>()
+            .add_system(setting_button_action.label(SettingsSystem::ButtonAction))
+            .add_system(
+                rebind_key
+                    .label(SettingsSystem::RebindKey)
+                    .after(SettingsSystem::ButtonAction),
+            )
+            .add_system(apply_settings);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum TextSpeed {
+    Slow,
+    Normal,
+    Fast,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DisplayMode {
+    Windowed,
+    Borderless,
+    Fullscreen,
+}
+
+impl From<DisplayMode> for WindowMode {
+    fn from(mode: DisplayMode) -> Self {
+        match mode {
+            DisplayMode::Windowed => WindowMode::Windowed,
+            DisplayMode::Borderless => WindowMode::BorderlessFullscreen,
+            DisplayMode::Fullscreen => WindowMode::Fullscreen,
+        }
+    }
+}
+
+const WINDOW_SCALES: [f64; 4] = [0.5, 0.75, 1., 1.5];
+const VOLUME_STEP: u8 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum KeyAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Sprint,
+    Interact,
+    Cancel,
+    Menu,
+}
+
+// Keys missing from older settings files keep their defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct KeyBindings {
+    pub(crate) up: KeyCode,
+    pub(crate) down: KeyCode,
+    pub(crate) left: KeyCode,
+    pub(crate) right: KeyCode,
+    pub(crate) sprint: KeyCode,
+    pub(crate) interact: KeyCode,
+    pub(crate) cancel: KeyCode,
+    pub(crate) menu: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            up: KeyCode::Up,
+            down: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            sprint: KeyCode::LShift,
+            interact: KeyCode::Return,
+            cancel: KeyCode::Escape,
+            menu: KeyCode::P,
+        }
+    }
+}
+
+impl KeyBindings {
+    fn get_mut(&mut self, action: KeyAction) -> &mut KeyCode {
+        match action {
+            KeyAction::Up => &mut self.up,
+            KeyAction::Down => &mut self.down,
+            KeyAction::Left => &mut self.left,
+            KeyAction::Right => &mut self.right,
+            KeyAction::Sprint => &mut self.sprint,
+            KeyAction::Interact => &mut self.interact,
+            KeyAction::Cancel => &mut self.cancel,
+            KeyAction::Menu => &mut self.menu,
+        }
+    }
+
+    pub(crate) fn get(&self, action: KeyAction) -> KeyCode {
+        match action {
+            KeyAction::Up => self.up,
+            KeyAction::Down => self.down,
+            KeyAction::Left => self.left,
+            KeyAction::Right => self.right,
+            KeyAction::Sprint => self.sprint,
+            KeyAction::Interact => self.interact,
+            KeyAction::Cancel => self.cancel,
+            KeyAction::Menu => self.menu,
+        }
+    }
+
+    // Binds the key to the action. An action already using the key gets this action's old key, so
+    // that no two actions share a key.
+    pub(crate) fn set(&mut self, action: KeyAction, key: KeyCode) {
+        let old_key = self.get(action);
+        for other in [
+            KeyAction::Up,
+            KeyAction::Down,
+            KeyAction::Left,
+            KeyAction::Right,
+            KeyAction::Sprint,
+            KeyAction::Interact,
+            KeyAction::Cancel,
+            KeyAction::Menu,
+        ] {
+            if self.get(other) == key {
+                *self.get_mut(other) = old_key;
+            }
+        }
+        *self.get_mut(action) = key;
+    }
+}
+
+// Player preferences, persisted to disk whenever they change.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) text_speed: TextSpeed,
+    pub(crate) display_mode: DisplayMode,
+    pub(crate) window_scale: f64,
+    pub(crate) master_volume: u8, // percent
+    pub(crate) music_volume: u8,  // percent
+    pub(crate) sfx_volume: u8,    // percent
+    pub(crate) key_bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            text_speed: TextSpeed::Normal,
+            display_mode: DisplayMode::Windowed,
+            window_scale: 1.,
+            master_volume: 100,
+            music_volume: 100,
+            sfx_volume: 100,
+            key_bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    // How long each battle announcement stays on screen, in seconds.
+    pub(crate) fn text_duration(&self) -> f32 {
+        match self.text_speed {
+            TextSpeed::Slow => 3.,
+            TextSpeed::Normal => 2.,
+            TextSpeed::Fast => 1.,
+        }
+    }
+
+    pub(crate) fn window_descriptor(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            title: "Rapid Fantasy - Remake".to_string(),
+            width: global::BACKGROUND_SIZE[0],
+            height: global::BACKGROUND_SIZE[1],
+            resizable: false,
+            mode: self.display_mode.into(),
+            scale_factor_override: Some(self.window_scale),
+            ..default()
+        }
+    }
+
+    fn path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("rapid_fantasy_remake")
+            .join("settings.ron")
+    }
+
+    // Falls back to the defaults if there are no settings yet or they can't be read.
+    pub(crate) fn load() -> Self {
+        fs::read_to_string(Settings::path())
+            .ok()
+            .and_then(|s| ron::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let path = Settings::path();
+        let result = fs::create_dir_all(path.parent().unwrap()).and_then(|_| {
+            let s = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            fs::write(&path, s)
+        });
+        if let Err(e) = result {
+            error!("Failed to save settings: {}", e);
+        }
+    }
+
+    fn change(&mut self, setting: Setting) {
+        fn next<T: Copy + PartialEq>(options: &[T], current: T) -> T {
+            let i = options.iter().position(|o| *o == current).unwrap_or(0);
+            options[(i + 1) % options.len()]
+        }
+        fn next_volume(volume: u8) -> u8 {
+            if volume >= 100 {
+                0
+            } else {
+                volume + VOLUME_STEP
+            }
+        }
+
+        match setting {
+            Setting::TextSpeed => {
+                use TextSpeed::*;
+                self.text_speed = next(&[Slow, Normal, Fast], self.text_speed);
+            }
+            Setting::WindowMode => {
+                use DisplayMode::*;
+                self.display_mode = next(&[Windowed, Borderless, Fullscreen], self.display_mode);
+            }
+            Setting::WindowScale => self.window_scale = next(&WINDOW_SCALES, self.window_scale),
+            Setting::MasterVolume => self.master_volume = next_volume(self.master_volume),
+            Setting::MusicVolume => self.music_volume = next_volume(self.music_volume),
+            Setting::SfxVolume => self.sfx_volume = next_volume(self.sfx_volume),
+            Setting::Key(_) => (), // handled by rebind_key()
+        }
+    }
+
+    fn value_text(&self, setting: Setting) -> String {
+        match setting {
+            Setting::TextSpeed => format!("{:?}", self.text_speed),
+            Setting::WindowMode => format!("{:?}", self.display_mode),
+            Setting::WindowScale => format!("{}%", self.window_scale * 100.),
+            Setting::MasterVolume => format!("{}%", self.master_volume),
+            Setting::MusicVolume => format!("{}%", self.music_volume),
+            Setting::SfxVolume => format!("{}%", self.sfx_volume),
+            Setting::Key(action) => format!("{:?}", self.key_bindings.get(action)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Setting {
+    TextSpeed,
+    WindowMode,
+    WindowScale,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    Key(KeyAction),
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Setting::TextSpeed => write!(f, "Text speed"),
+            Setting::WindowMode => write!(f, "Window mode"),
+            Setting::WindowScale => write!(f, "Window scale"),
+            Setting::MasterVolume => write!(f, "Master volume"),
+            Setting::MusicVolume => write!(f, "Music volume"),
+            Setting::SfxVolume => write!(f, "SFX volume"),
+            Setting::Key(action) => write!(f, "{:?}", action),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SettingsPage {
+    Display,
+    Sound,
+    Controls,
+}
+
+impl SettingsPage {
+    fn settings(&self) -> Vec<Setting> {
+        match self {
+            SettingsPage::Display => vec![
+                Setting::TextSpeed,
+                Setting::WindowMode,
+                Setting::WindowScale,
+            ],
+            SettingsPage::Sound => vec![
+                Setting::MasterVolume,
+                Setting::MusicVolume,
+                Setting::SfxVolume,
+            ],
+            SettingsPage::Controls => [
+                KeyAction::Up,
+                KeyAction::Down,
+                KeyAction::Left,
+                KeyAction::Right,
+                KeyAction::Sprint,
+                KeyAction::Interact,
+                KeyAction::Cancel,
+                KeyAction::Menu,
+            ]
+            .into_iter()
+            .map(Setting::Key)
+            .collect(),
+        }
+    }
+}
+
+// The key binding waiting for a key press, if any.
+#[derive(Default, Deref)]
+pub(crate) struct Rebinding(Option<KeyAction>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub(crate) enum SettingsSystem {
+    ButtonAction,
+    RebindKey, // menus that close on Cancel run before it, so cancelling a rebind doesn't close them
+}
+
+#[derive(Component, Debug, Deref)]
+struct SettingButton(Setting);
+
+fn text_style(font_assets: &FontAssets) -> TextStyle {
+    TextStyle {
+        font: font_assets.font.clone(),
+        font_size: 30.0,
+        color: global::TEXT_COLOR,
+    }
+}
+
+// Spawns a row for each setting on the page: its name and a button that cycles through its values.
+pub(crate) fn spawn_settings_page(
+    p: &mut ChildBuilder,
+    font_assets: &FontAssets,
+    settings: &Settings,
+    page: SettingsPage,
+) {
+    for setting in page.settings() {
+        p.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(500.), Val::Px(50.)),
+                margin: Rect::all(Val::Px(5.)),
+                justify_content: JustifyContent::SpaceBetween,
+                align_items: AlignItems::Center,
+                flex_shrink: 0.,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|p| {
+            p.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    setting.to_string(),
+                    text_style(font_assets),
+                    Default::default(),
+                ),
+                ..default()
+            });
+            p.spawn_bundle(ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(240.), Val::Percent(100.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                color: global::NORMAL_BUTTON.into(),
+                ..default()
+            })
+            .insert(SettingButton(setting))
+            .with_children(|p| {
+                p.spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        settings.value_text(setting),
+                        text_style(font_assets),
+                        Default::default(),
+                    ),
+                    ..default()
+                });
+            });
+        });
+    }
+}
+
+fn setting_button_action(
+    interaction_query: Query<
+        (&Interaction, &SettingButton, &Children),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut text_query: Query<&mut Text>,
+    mut settings: ResMut<Settings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, setting, children) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            let mut text = text_query.get_mut(children[0]).unwrap();
+            if let Setting::Key(action) = **setting {
+                rebinding.0 = Some(action);
+                text.sections[0].value = "Press a key...".to_string();
+            } else {
+                settings.change(**setting);
+                text.sections[0].value = settings.value_text(**setting);
+            }
+        }
+    }
+}
+
+// Binds the next key pressed. Cancel gives up instead, so it can't be bound by accident.
+fn rebind_key(
+    keyboard_input: Res<Input<KeyCode>>,
+    actions: Res<Input<Action>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut settings: ResMut<Settings>,
+    buttons: Query<(&SettingButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    // Skip the frame the rebinding started, so the key that clicked the button isn't bound.
+    if rebinding.is_changed() {
+        return;
+    }
+
+    if let Some(action) = rebinding.0 {
+        // Cancel comes from the gamepad too, without a key being pressed.
+        if !actions.just_pressed(Action::Cancel) {
+            match keyboard_input.get_just_pressed().next() {
+                Some(key) => settings.key_bindings.set(action, *key),
+                None => return,
+            }
+        }
+        rebinding.0 = None;
+
+        // Swapping keys can change other bindings too, so refresh every key button.
+        for (setting, children) in buttons.iter() {
+            if let Setting::Key(_) = **setting {
+                text_query.get_mut(children[0]).unwrap().sections[0].value =
+                    settings.value_text(**setting);
+            }
+        }
+    }
+}
+
+// Leaving the page gives up on a rebind that's still waiting for a key.
+pub(crate) fn stop_rebinding(mut rebinding: ResMut<Rebinding>) {
+    if rebinding.is_some() {
+        rebinding.0 = None;
+    }
+}
+
+fn apply_settings(settings: Res<Settings>, mut windows: ResMut<Windows>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_mode(settings.display_mode.into());
+        window.set_scale_factor_override(Some(settings.window_scale));
+    }
+    settings.save();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{harness::Harness, mainmenu::MenuButtonAction};
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = Settings::default();
+        settings.change(Setting::TextSpeed);
+        settings.change(Setting::WindowScale);
+        settings.change(Setting::SfxVolume);
+        settings
+            .key_bindings
+            .set(KeyAction::Interact, KeyCode::Space);
+
+        let s = ron::ser::to_string_pretty(&settings, ron::ser::PrettyConfig::new()).unwrap();
+        assert_eq!(settings, ron::from_str::<Settings>(&s).unwrap());
+    }
+
+    #[test]
+    fn settings_cycle_through_values() {
+        let mut settings = Settings::default();
+        settings.change(Setting::TextSpeed);
+        assert_eq!(TextSpeed::Fast, settings.text_speed);
+        settings.change(Setting::TextSpeed);
+        assert_eq!(TextSpeed::Slow, settings.text_speed);
+        assert_eq!(3., settings.text_duration());
+
+        settings.change(Setting::MasterVolume);
+        assert_eq!(0, settings.master_volume);
+        settings.change(Setting::MasterVolume);
+        assert_eq!(VOLUME_STEP, settings.master_volume);
+    }
+
+    #[test]
+    fn rebinding_a_used_key_swaps_bindings() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.set(KeyAction::Up, KeyCode::Down);
+        assert_eq!(KeyCode::Down, key_bindings.up);
+        assert_eq!(KeyCode::Up, key_bindings.down);
+    }
+
+    #[test]
+    fn cancelling_or_leaving_the_page_stops_a_rebind() {
+        let mut harness = Harness::new(0);
+        harness.click(MenuButtonAction::Settings);
+        harness.click(MenuButtonAction::SettingsControls);
+
+        harness.click(SettingButton(Setting::Key(KeyAction::Up)));
+        assert_eq!(Some(KeyAction::Up), **harness.resource::<Rebinding>());
+        harness.press(KeyCode::Escape);
+        harness.update();
+        assert_eq!(None, **harness.resource::<Rebinding>());
+        assert_eq!(KeyCode::Up, harness.resource::<Settings>().key_bindings.up);
+        harness.release(KeyCode::Escape);
+
+        harness.click(SettingButton(Setting::Key(KeyAction::Up)));
+        harness.click(MenuButtonAction::BackToSettings);
+        assert_eq!(None, **harness.resource::<Rebinding>());
+    }
+}
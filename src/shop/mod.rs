@@ -0,0 +1,470 @@
+use crate::{
+    actions::Action, button_system, despawn_screen, global, replay::RecordClicks, FontAssets,
+};
+
+use bevy::prelude::*;
+
+mod styles;
+use styles::*;
+pub struct ShopPlugin;
+
+impl Plugin for ShopPlugin {
+    fn build(&self, app: &mut App) {
+        app.record_clicks::<ShopButtonAction>()
+            .record_clicks::<TradeButton>()
+            .add_system_set(SystemSet::on_enter(global::GameState::Shop).with_system(shop_setup))
+            .add_system_set(
+                SystemSet::on_update(global::GameState::Shop)
+                    .with_system(shop_action)
+                    .with_system(trade_action)
+                    .with_system(leave_on_cancel)
+                    .with_system(button_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(global::GameState::Shop)
+                    .with_system(despawn_screen::<ShopScreen>),
+            );
+    }
+}
+
+const MAX_QUANTITY: usize = 99;
+
+// The shop the player is visiting, set before entering GameState::Shop.
+pub(crate) struct CurrentShop(pub(crate) usize);
+
+#[derive(Default, Deref)]
+struct Quantity(usize);
+
+#[derive(Component, Debug)]
+enum ShopButtonAction {
+    DecreaseQuantity,
+    IncreaseQuantity,
+    Leave,
+}
+
+#[derive(Component, Debug)]
+enum TradeButton {
+    Buy(usize),  // item id
+    Sell(usize), // item id
+}
+
+#[derive(Component)]
+struct ShopScreen;
+
+#[derive(Component)]
+struct GoldText;
+
+#[derive(Component)]
+struct QuantityText;
+
+#[derive(Component)]
+struct StatusText;
+
+#[derive(Component)]
+struct SellList;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum TradeError {
+    InsufficientFunds(i32), // cost
+    NotEnoughItems,
+}
+
+// Buys quantity of an item, returning the total cost.
+pub(crate) fn buy(
+    stats: &mut global::Stats,
+    item_inventory: &mut global::PlayerItemInventory,
+    item_id: usize,
+    price: i32,
+    quantity: usize,
+) -> Result<i32, TradeError> {
+    let cost = price * quantity as i32;
+    if cost > stats.gold {
+        return Err(TradeError::InsufficientFunds(cost));
+    }
+
+    stats.gold -= cost;
+    *item_inventory.entry(item_id).or_insert(0) += quantity;
+    Ok(cost)
+}
+
+// Sells quantity of an item, returning the total earned. Equipped items can't be sold.
+pub(crate) fn sell(
+    stats: &mut global::Stats,
+    item_inventory: &mut global::PlayerItemInventory,
+    equipment_equipped: &global::PlayerEquipmentEquipped,
+    item_id: usize,
+    price: i32,
+    quantity: usize,
+) -> Result<i32, TradeError> {
+    if sellable_quantity(item_inventory, equipment_equipped, item_id) < quantity {
+        return Err(TradeError::NotEnoughItems);
+    }
+
+    let earned = price * quantity as i32;
+    stats.gold += earned;
+    let owned = item_inventory.get_mut(&item_id).unwrap();
+    *owned -= quantity;
+    if *owned == 0 {
+        item_inventory.remove(&item_id);
+    }
+    Ok(earned)
+}
+
+fn sellable_quantity(
+    item_inventory: &global::PlayerItemInventory,
+    equipment_equipped: &global::PlayerEquipmentEquipped,
+    item_id: usize,
+) -> usize {
+    let owned = *item_inventory.get(&item_id).unwrap_or(&0);
+    let equipped = [
+        &equipment_equipped.weapon,
+        &equipment_equipped.armor,
+        &equipment_equipped.accessory,
+    ]
+    .iter()
+    .filter(|equip| matches!(equip, Some(item) if item.id == item_id))
+    .count();
+    owned.saturating_sub(equipped)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn shop_setup(
+    mut commands: Commands,
+    font_assets: Res<FontAssets>,
+    current_shop: Res<CurrentShop>,
+    shop_table: Res<global::ShopTable>,
+    item_table: Res<global::ItemTable>,
+    player: Res<global::Player>,
+    item_inventory: Res<global::PlayerItemInventory>,
+    equipment_equipped: Res<global::PlayerEquipmentEquipped>,
+) {
+    commands.insert_resource(Quantity(1));
+
+    let shop = shop_table.shops.get(&current_shop.0).unwrap();
+
+    commands
+        .spawn_bundle(styled_shop_screen())
+        .insert(ShopScreen)
+        .with_children(|p| {
+            p.spawn_bundle(styled_shop_header(&font_assets, &shop.name));
+
+            p.spawn_bundle(styled_row()).with_children(|p| {
+                p.spawn_bundle(styled_shop_text_bundle(
+                    format!("Gold: {}", player.stats.gold),
+                    &font_assets,
+                ))
+                .insert(GoldText);
+                p.spawn_bundle(styled_small_button())
+                    .insert(ShopButtonAction::DecreaseQuantity)
+                    .with_children(|p| {
+                        p.spawn_bundle(styled_shop_text_bundle("-", &font_assets));
+                    });
+                p.spawn_bundle(styled_shop_text_bundle("Quantity: 1", &font_assets))
+                    .insert(QuantityText);
+                p.spawn_bundle(styled_small_button())
+                    .insert(ShopButtonAction::IncreaseQuantity)
+                    .with_children(|p| {
+                        p.spawn_bundle(styled_shop_text_bundle("+", &font_assets));
+                    });
+            });
+
+            p.spawn_bundle(styled_shop_text_bundle("", &font_assets))
+                .insert(StatusText);
+
+            p.spawn_bundle(styled_lists_container()).with_children(|p| {
+                p.spawn_bundle(styled_list()).with_children(|p| {
+                    p.spawn_bundle(styled_shop_text_bundle("Buy", &font_assets));
+                    for item_id in &shop.stock {
+                        let item = item_table.get(item_id).unwrap();
+                        let price = shop_table.buy_price(*item_id).unwrap();
+                        p.spawn_bundle(styled_list_button())
+                            .insert(TradeButton::Buy(*item_id))
+                            .with_children(|p| {
+                                p.spawn_bundle(styled_shop_text_bundle(
+                                    format!("{} - {} G", item.name, price),
+                                    &font_assets,
+                                ));
+                            });
+                    }
+                });
+                p.spawn_bundle(styled_list())
+                    .insert(SellList)
+                    .with_children(|p| {
+                        spawn_sell_list(
+                            p,
+                            &font_assets,
+                            current_shop.0,
+                            &shop_table,
+                            &item_table,
+                            &item_inventory,
+                            &equipment_equipped,
+                        );
+                    });
+            });
+
+            p.spawn_bundle(styled_button())
+                .insert(ShopButtonAction::Leave)
+                .with_children(|p| {
+                    p.spawn_bundle(styled_shop_text_bundle("Leave", &font_assets));
+                });
+        });
+}
+
+fn spawn_sell_list(
+    p: &mut ChildBuilder,
+    font_assets: &Res<FontAssets>,
+    shop_id: usize,
+    shop_table: &global::ShopTable,
+    item_table: &global::ItemTable,
+    item_inventory: &global::PlayerItemInventory,
+    equipment_equipped: &global::PlayerEquipmentEquipped,
+) {
+    p.spawn_bundle(styled_shop_text_bundle("Sell", font_assets));
+
+    let mut item_ids = item_inventory.keys().copied().collect::<Vec<usize>>();
+    item_ids.sort_unstable();
+    for item_id in item_ids {
+        let item = item_table.get(&item_id).unwrap();
+        let quantity = sellable_quantity(item_inventory, equipment_equipped, item_id);
+        if let (Some(price), true) = (shop_table.sell_price(shop_id, item), quantity > 0) {
+            p.spawn_bundle(styled_list_button())
+                .insert(TradeButton::Sell(item_id))
+                .with_children(|p| {
+                    p.spawn_bundle(styled_shop_text_bundle(
+                        format!("{} x{} - {} G", item.name, quantity, price),
+                        font_assets,
+                    ));
+                });
+        }
+    }
+}
+
+fn shop_action(
+    interaction_query: Query<
+        (&Interaction, &ShopButtonAction),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut quantity_text: Query<&mut Text, With<QuantityText>>,
+    mut quantity: ResMut<Quantity>,
+    mut game_state: ResMut<State<global::GameState>>,
+    font_assets: Res<FontAssets>,
+) {
+    for (interaction, button_action) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            match button_action {
+                ShopButtonAction::DecreaseQuantity => quantity.0 = quantity.0.max(2) - 1,
+                ShopButtonAction::IncreaseQuantity => {
+                    quantity.0 = (quantity.0 + 1).min(MAX_QUANTITY)
+                }
+                ShopButtonAction::Leave => {
+                    game_state.set(global::GameState::Overworld).unwrap();
+                    return;
+                }
+            }
+            *quantity_text.single_mut() =
+                styled_shop_text(format!("Quantity: {}", quantity.0), &font_assets);
+        }
+    }
+}
+
+fn leave_on_cancel(actions: Res<Input<Action>>, mut game_state: ResMut<State<global::GameState>>) {
+    if actions.just_pressed(Action::Cancel) {
+        game_state.set(global::GameState::Overworld).unwrap();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn trade_action(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &TradeButton), (Changed<Interaction>, With<Button>)>,
+    mut text_query: ParamSet<(
+        Query<&mut Text, With<GoldText>>,
+        Query<&mut Text, With<StatusText>>,
+    )>,
+    sell_list: Query<Entity, With<SellList>>,
+    children_query: Query<&Children>,
+    font_assets: Res<FontAssets>,
+    current_shop: Res<CurrentShop>,
+    shop_table: Res<global::ShopTable>,
+    item_table: Res<global::ItemTable>,
+    quantity: Res<Quantity>,
+    mut player: ResMut<global::Player>,
+    mut item_inventory: ResMut<global::PlayerItemInventory>,
+    equipment_equipped: Res<global::PlayerEquipmentEquipped>,
+) {
+    for (interaction, trade_button) in interaction_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        let status = match trade_button {
+            TradeButton::Buy(item_id) => {
+                let item = item_table.get(item_id).unwrap();
+                let price = shop_table.buy_price(*item_id).unwrap();
+                match buy(
+                    &mut player.stats,
+                    &mut item_inventory,
+                    *item_id,
+                    price,
+                    **quantity,
+                ) {
+                    Ok(cost) => format!("Bought {} x{} for {} G.", item.name, **quantity, cost),
+                    Err(TradeError::InsufficientFunds(cost)) => {
+                        format!(
+                            "Not enough gold! {} x{} costs {} G.",
+                            item.name, **quantity, cost
+                        )
+                    }
+                    Err(_) => unreachable!(),
+                }
+            }
+            TradeButton::Sell(item_id) => {
+                let item = item_table.get(item_id).unwrap();
+                let price = shop_table.sell_price(current_shop.0, item).unwrap();
+                match sell(
+                    &mut player.stats,
+                    &mut item_inventory,
+                    &equipment_equipped,
+                    *item_id,
+                    price,
+                    **quantity,
+                ) {
+                    Ok(earned) => format!("Sold {} x{} for {} G.", item.name, **quantity, earned),
+                    Err(_) => format!("You don't have {} {} to sell.", **quantity, item.name),
+                }
+            }
+        };
+
+        *text_query.p0().single_mut() =
+            styled_shop_text(format!("Gold: {}", player.stats.gold), &font_assets);
+        *text_query.p1().single_mut() = styled_shop_text(status, &font_assets);
+
+        // Rebuild the sell list, as quantities changed.
+        let sell_list = sell_list.single();
+        if let Ok(children) = children_query.get(sell_list) {
+            for child in children.iter() {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        commands.entity(sell_list).with_children(|p| {
+            spawn_sell_list(
+                p,
+                &font_assets,
+                current_shop.0,
+                &shop_table,
+                &item_table,
+                &item_inventory,
+                &equipment_equipped,
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content;
+
+    fn item_table() -> global::ItemTable {
+        content::Tables::for_tests().items
+    }
+
+    #[test]
+    fn buying_spends_gold() {
+        let mut stats = global::Stats::new(Handle::default());
+        stats.gold = 100;
+        let mut item_inventory = global::PlayerItemInventory::new();
+
+        assert_eq!(Ok(75), buy(&mut stats, &mut item_inventory, 0, 25, 3));
+        assert_eq!(25, stats.gold);
+        assert_eq!(Some(&8), item_inventory.get(&0));
+    }
+
+    #[test]
+    fn buying_without_enough_gold_fails() {
+        let mut stats = global::Stats::new(Handle::default());
+        stats.gold = 100;
+        let mut item_inventory = global::PlayerItemInventory::new();
+
+        assert_eq!(
+            Err(TradeError::InsufficientFunds(125)),
+            buy(&mut stats, &mut item_inventory, 0, 25, 5)
+        );
+        assert_eq!(100, stats.gold);
+        assert_eq!(Some(&5), item_inventory.get(&0));
+    }
+
+    #[test]
+    fn selling_earns_gold_and_removes_items() {
+        let mut stats = global::Stats::new(Handle::default());
+        let mut item_inventory = global::PlayerItemInventory::new();
+        let equipment_equipped = global::PlayerEquipmentEquipped::default();
+
+        assert_eq!(
+            Ok(60),
+            sell(
+                &mut stats,
+                &mut item_inventory,
+                &equipment_equipped,
+                5,
+                12,
+                5
+            )
+        );
+        assert_eq!(60, stats.gold);
+        assert_eq!(None, item_inventory.get(&5));
+        assert_eq!(
+            Err(TradeError::NotEnoughItems),
+            sell(
+                &mut stats,
+                &mut item_inventory,
+                &equipment_equipped,
+                5,
+                12,
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn equipped_items_are_not_sold() {
+        let mut stats = global::Stats::new(Handle::default());
+        let mut item_inventory = global::PlayerItemInventory::new();
+        item_inventory.insert(10, 1);
+        let equipment_equipped = global::PlayerEquipmentEquipped {
+            weapon: Some(item_table().get(&10).unwrap().clone()),
+            ..default()
+        };
+
+        assert_eq!(
+            Err(TradeError::NotEnoughItems),
+            sell(
+                &mut stats,
+                &mut item_inventory,
+                &equipment_equipped,
+                10,
+                125,
+                1
+            )
+        );
+        assert_eq!(0, stats.gold);
+    }
+
+    #[test]
+    fn stock_has_prices_and_sells_back_for_less() {
+        let shop_table = content::Tables::for_tests().shops;
+        let item_table = item_table();
+        for (shop_id, shop) in shop_table.shops.iter() {
+            for item_id in &shop.stock {
+                let item = item_table.get(item_id).unwrap();
+                let buy_price = shop_table.buy_price(*item_id).unwrap();
+                let sell_price = shop_table.sell_price(*shop_id, item).unwrap();
+                assert!(
+                    sell_price < buy_price,
+                    "{} sells back for too much",
+                    item.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{global, FontAssets};
+use bevy::prelude::*;
+
+pub fn styled_shop_screen() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+            ..default()
+        },
+        color: Color::WHITE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_shop_header(font_assets: &Res<FontAssets>, name: &str) -> TextBundle {
+    TextBundle {
+        text: Text::with_section(
+            name,
+            TextStyle {
+                font: font_assets.font_bold.clone(),
+                font_size: 60.,
+                color: global::TEXT_COLOR,
+            },
+            Default::default(),
+        ),
+        style: Style {
+            margin: Rect::all(Val::Px(10.)),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+pub fn styled_shop_text<T: Into<String>>(text: T, font_assets: &Res<FontAssets>) -> Text {
+    Text::with_section(
+        text,
+        TextStyle {
+            font: font_assets.font.clone(),
+            font_size: 24.,
+            color: global::TEXT_COLOR,
+        },
+        Default::default(),
+    )
+}
+
+pub fn styled_shop_text_bundle<T: Into<String>>(
+    text: T,
+    font_assets: &Res<FontAssets>,
+) -> TextBundle {
+    TextBundle {
+        text: styled_shop_text(text, font_assets),
+        style: Style {
+            margin: Rect::all(Val::Px(5.)),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+pub fn styled_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            flex_shrink: 0.,
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_lists_container() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.), Val::Auto),
+            justify_content: JustifyContent::SpaceAround,
+            flex_grow: 1.,
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_list() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::Center,
+            size: Size::new(Val::Px(560.), Val::Percent(100.)),
+            overflow: Overflow::Hidden,
+            ..default()
+        },
+        color: Color::NONE.into(),
+        ..default()
+    }
+}
+
+pub fn styled_list_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(540.), Val::Px(30.)),
+            margin: Rect::all(Val::Px(2.)),
+            padding: Rect {
+                left: Val::Px(10.),
+                ..default()
+            },
+            align_items: AlignItems::Center,
+            flex_shrink: 0.,
+            ..default()
+        },
+        color: global::NORMAL_BUTTON.into(),
+        ..default()
+    }
+}
+
+pub fn styled_small_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(40.), Val::Px(40.)),
+            margin: Rect::all(Val::Px(5.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: global::NORMAL_BUTTON.into(),
+        ..default()
+    }
+}
+
+pub fn styled_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(250.), Val::Px(50.)),
+            margin: Rect::all(Val::Px(10.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        color: global::NORMAL_BUTTON.into(),
+        ..default()
+    }
+}
@@ -0,0 +1,75 @@
+use bevy::{reflect::TypeUuid, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{
+    content::ContentError,
+    global::{Shop, ShopTable},
+};
+
+// Contents of a `.shops.ron` file.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "5f3b8c1e-6d2a-4f0b-9a7e-3c41d8e29b56"]
+pub(crate) struct ShopTableAsset {
+    prices: Vec<(usize, i32)>, // item id, price
+    shops: Vec<ShopEntry>,
+}
+
+#[derive(Deserialize)]
+struct ShopEntry {
+    id: usize,
+    name: String,
+    stock: Vec<usize>, // item ids
+    consumable_sell_ratio: f32,
+    equipment_sell_ratio: f32,
+}
+
+impl ShopTableAsset {
+    pub(crate) fn build(&self) -> Result<ShopTable, Vec<ContentError>> {
+        let mut prices = HashMap::new();
+        let mut shops = HashMap::new();
+        let mut errors = vec![];
+
+        for (item_id, price) in &self.prices {
+            let entry = format!("price of item {}", item_id);
+            if prices.contains_key(item_id) {
+                errors.push(ContentError::new("shops", entry.clone(), "duplicate item"));
+            }
+            if *price <= 0 {
+                errors.push(ContentError::new("shops", entry, "price must be above 0"));
+            }
+            prices.insert(*item_id, *price);
+        }
+
+        for shop in &self.shops {
+            let entry = format!("shop {} \"{}\"", shop.id, shop.name);
+            let mut error =
+                |message: &str| errors.push(ContentError::new("shops", entry.clone(), message));
+
+            if shops.contains_key(&shop.id) {
+                error("duplicate id");
+            }
+            // Selling back for the full price or more would let the player buy and sell for gold.
+            if !(0. ..1.).contains(&shop.consumable_sell_ratio)
+                || !(0. ..1.).contains(&shop.equipment_sell_ratio)
+            {
+                error("sell ratios must be at least 0 and below 1");
+            }
+
+            shops.insert(
+                shop.id,
+                Shop::new(
+                    &shop.name,
+                    shop.stock.clone(),
+                    shop.consumable_sell_ratio,
+                    shop.equipment_sell_ratio,
+                ),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(ShopTable { shops, prices })
+        } else {
+            Err(errors)
+        }
+    }
+}
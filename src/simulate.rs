@@ -0,0 +1,538 @@
+// Plays out battles with the real combat rules and content files, without starting the game, to
+// see how the enemies are balanced against a player of a given level and loadout. Run with
+// `cargo run -- simulate`, see `cli::USAGE` for the options.
+//
+// It's a subcommand of the game rather than a binary of its own because everything it uses, from
+// the combat rules to the content tables, is a module of this binary crate. A second binary could
+// only share them once the game is moved into a library crate.
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail};
+use bevy::utils::HashMap;
+
+use crate::{
+    combat::{self, BattleEvent, PlayerAction, Target},
+    content, global,
+};
+
+// Battles that go on longer than this are counted as losses, e.g. when neither side can hurt the
+// other.
+const MAX_TURNS: u32 = 200;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Options {
+    pub(crate) level: i32,
+    pub(crate) equipment: Vec<usize>, // item ids, at most one of each type
+    pub(crate) policy: Policy,
+    pub(crate) area: Option<usize>, // only fight the formations of this area
+    pub(crate) battles: u32,        // per formation
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            level: 1,
+            equipment: vec![],
+            policy: Policy::default(),
+            area: None,
+            battles: 1000,
+        }
+    }
+}
+
+// How the simulated player picks their action each turn. With nothing set they always attack.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Policy {
+    pub(crate) magic: bool, // use the magic that does the most damage, if it beats attacking
+    pub(crate) limit: bool, // use the limit break as soon as it's full
+    pub(crate) potion_below: Option<u8>, // drink the strongest red potion below this % of HP
+}
+
+// What the player brings into every battle.
+struct Loadout {
+    stats: global::Stats,
+    items: HashMap<usize, usize>, // id, quantity
+    attack: global::PlayerAttack,
+    limit: global::PlayerAttack,
+    magic: Vec<global::PlayerAttack>,
+}
+
+impl Loadout {
+    // A fresh player at the given level, with everything they unlocked on the way and the given
+    // equipment on.
+    fn new(options: &Options, tables: &content::Tables) -> anyhow::Result<Self> {
+        let mut stats = global::Stats::new(Default::default());
+        let mut attacks = global::PlayerAttackInventory::new(&tables.attacks);
+        while stats.level < options.level {
+            combat::level_up(&mut stats);
+            if let Some((_, attack_ids)) = combat::unlocks_at(stats.level) {
+                for id in attack_ids {
+                    attacks.insert(tables.attacks.table.get(&id).unwrap().clone());
+                }
+            }
+        }
+
+        let mut equipped = vec![];
+        for id in &options.equipment {
+            let item = tables
+                .items
+                .get(id)
+                .ok_or_else(|| anyhow!("there is no item {}", id))?;
+            if item.item_type == global::ItemType::Consumable {
+                bail!("{} can't be equipped", item.name);
+            }
+            if equipped.contains(&item.item_type) {
+                bail!("only one {:?} can be equipped", item.item_type);
+            }
+            equipped.push(item.item_type.clone());
+            stats.add_item_stats(&item.stats);
+        }
+
+        let limit = attacks
+            .iter()
+            .filter(|attack| attack.attack_type == Some(global::PlayerAttackType::Limit))
+            .max_by_key(|attack| attack.tier)
+            .unwrap()
+            .clone();
+        let magic = attacks
+            .iter()
+            .filter(|attack| attack.attack_type == Some(global::PlayerAttackType::Magic))
+            .cloned()
+            .collect();
+        Ok(Loadout {
+            stats,
+            items: global::PlayerItemInventory::new().0,
+            attack: tables.attacks.table.get(&0).unwrap().clone(),
+            limit,
+            magic,
+        })
+    }
+}
+
+// How a single battle went.
+#[derive(Default)]
+struct Outcome {
+    won: bool,
+    turns: u32,
+    damage_taken: i32,
+    potions: u32,
+    experience: i32,
+    gold: i32,
+    drops: u32,
+}
+
+// Outcomes added up over many battles.
+#[derive(Default)]
+struct Totals {
+    battles: u32,
+    wins: u32,
+    turns: u64,
+    damage_taken: i64,
+    potions: u64,
+    experience: i64,
+    gold: i64,
+    drops: u64,
+}
+
+impl Totals {
+    fn add(&mut self, outcome: &Outcome) {
+        self.battles += 1;
+        self.wins += outcome.won as u32;
+        self.turns += outcome.turns as u64;
+        self.damage_taken += outcome.damage_taken as i64;
+        self.potions += outcome.potions as u64;
+        self.experience += outcome.experience as i64;
+        self.gold += outcome.gold as i64;
+        self.drops += outcome.drops as u64;
+    }
+
+    fn add_totals(&mut self, other: &Totals) {
+        self.battles += other.battles;
+        self.wins += other.wins;
+        self.turns += other.turns;
+        self.damage_taken += other.damage_taken;
+        self.potions += other.potions;
+        self.experience += other.experience;
+        self.gold += other.gold;
+        self.drops += other.drops;
+    }
+
+    // One line of the report, with everything averaged per battle.
+    fn row(&self, area: usize, name: &str) -> String {
+        let average = |total: f64| total / self.battles.max(1) as f64;
+        format!(
+            "{:>4}  {:<28}{:>6.1}{:>7.1}{:>8.1}{:>9.2}{:>8.1}{:>7.1}{:>7.2}",
+            area,
+            name,
+            average(self.wins as f64) * 100.,
+            average(self.turns as f64),
+            average(self.damage_taken as f64),
+            average(self.potions as f64),
+            average(self.experience as f64),
+            average(self.gold as f64),
+            average(self.drops as f64),
+        )
+    }
+}
+
+pub(crate) fn run(options: &Options, seed: u64) -> anyhow::Result<()> {
+    let tables = content::Tables::from_content_dir().map_err(|errors| {
+        let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        anyhow!("content files have errors:\n{}", errors.join("\n"))
+    })?;
+    print!("{}", report(options, &tables, seed)?);
+    Ok(())
+}
+
+fn report(options: &Options, tables: &content::Tables, seed: u64) -> anyhow::Result<String> {
+    let loadout = Loadout::new(options, tables)?;
+    let mut rng = global::GameRng::new(seed);
+
+    let mut area_ids: Vec<_> = tables.areas.keys().copied().collect();
+    area_ids.sort_unstable();
+    if let Some(area) = options.area {
+        if !area_ids.contains(&area) {
+            bail!("there is no area {}", area);
+        }
+        area_ids.retain(|id| *id == area);
+    }
+
+    let mut report = String::new();
+    writeln!(
+        report,
+        "Level {} with {} HP, {} battles per formation, seed {}",
+        options.level, loadout.stats.hp_max, options.battles, seed
+    )?;
+    writeln!(
+        report,
+        "Area  {:<30}Win%  Turns  Damage  Potions      XP   Gold  Drops",
+        "Formation"
+    )?;
+    for area_id in area_ids {
+        let area = tables.areas.get(&area_id).unwrap();
+        let mut formations: Vec<_> = area
+            .formations
+            .iter()
+            .map(|formation| formation.enemies.clone())
+            .collect();
+        if area
+            .interactables
+            .iter()
+            .any(|i| i.kind == global::InteractableKind::FinalBoss)
+        {
+            formations.push(vec![global::FINAL_BOSS_ID]);
+        }
+        if formations.is_empty() {
+            continue;
+        }
+
+        // Every formation is fought the same number of times, however likely it is to be met.
+        let mut area_totals = Totals::default();
+        for formation in formations {
+            let mut totals = Totals::default();
+            for _ in 0..options.battles {
+                totals.add(&battle(
+                    &loadout,
+                    &options.policy,
+                    &formation,
+                    tables,
+                    &mut rng,
+                ));
+            }
+            let name = formation_name(&formation, &tables.enemies);
+            writeln!(report, "{}", totals.row(area_id, &name))?;
+            area_totals.add_totals(&totals);
+        }
+        writeln!(report, "{}", area_totals.row(area_id, "(all)"))?;
+    }
+
+    Ok(report)
+}
+
+// Names the enemies of a formation, e.g. "Slime x2, Rocky".
+fn formation_name(formation: &[usize], enemy_table: &global::EnemyTable) -> String {
+    let mut names: Vec<(&str, usize)> = vec![];
+    for id in formation {
+        let name = enemy_table.table.get(id).unwrap().0.name.as_str();
+        match names.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => names.push((name, 1)),
+        }
+    }
+    names
+        .iter()
+        .map(|(name, count)| match count {
+            1 => name.to_string(),
+            _ => format!("{} x{}", name, count),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Fights a formation of enemies and all of their phases, following the same turn order as the
+// battle screen. The player always goes for the enemy with the least HP left.
+fn battle(
+    loadout: &Loadout,
+    policy: &Policy,
+    formation: &[usize],
+    tables: &content::Tables,
+    rng: &mut global::GameRng,
+) -> Outcome {
+    let mut player = global::Player {
+        stats: loadout.stats.clone(),
+        ..Default::default()
+    };
+    let mut enemies: Vec<_> = formation
+        .iter()
+        .map(|id| global::Enemy::from_table(&tables.enemies, *id))
+        .collect();
+    let mut items = loadout.items.clone();
+    let mut outcome = Outcome::default();
+
+    let enemy_speeds: Vec<_> = enemies.iter().map(|enemy| enemy.stats.speed).collect();
+    let mut timeline = combat::Timeline::new(player.stats.speed, &enemy_speeds);
+    let mut blocking = false;
+
+    while outcome.turns < MAX_TURNS {
+        let side = timeline.advance();
+        let slot = match side {
+            combat::Side::Player => weakest(&enemies),
+            combat::Side::Enemy(slot) => slot,
+        };
+        let enemy = &mut enemies[slot];
+        let events = match side {
+            combat::Side::Player => {
+                outcome.turns += 1;
+                let action = choose_action(policy, loadout, &player, enemy, &items, &tables.items);
+                let mut state = combat::BattleState::new(&player, enemy, false);
+                let events = combat::apply_player_action(&mut state, &action, &mut rng.ai);
+                state.write_back(&mut player, enemy);
+                blocking = state.blocking;
+                if let PlayerAction::UseItem(item) = &action {
+                    if events
+                        .iter()
+                        .any(|e| matches!(e, BattleEvent::ItemUsed { .. }))
+                    {
+                        *items.get_mut(&item.id).unwrap() -= 1;
+                        items.retain(|_, quantity| *quantity > 0);
+                        outcome.potions += 1;
+                    }
+                }
+                events
+            }
+            combat::Side::Enemy(_) => {
+                let mut state = combat::BattleState::new(&player, enemy, blocking);
+                let events = combat::apply_enemy_turn(
+                    &mut state,
+                    &enemy.enemy_stats.ai,
+                    &enemy.attacks,
+                    &mut rng.ai,
+                );
+                state.write_back(&mut player, enemy);
+                blocking = state.blocking;
+                events
+            }
+        };
+        outcome.damage_taken += damage_taken(&events);
+
+        if player.stats.hp <= 0 {
+            return outcome;
+        }
+        if enemy.stats.hp <= 0 {
+            if next_phase(enemy, &tables.enemies) {
+                // The next phase acts at its own speed, as on the battle screen.
+                timeline.set_speed(combat::Side::Enemy(slot), enemy.stats.speed);
+            } else {
+                timeline.remove(combat::Side::Enemy(slot));
+                if enemies.iter().all(|enemy| enemy.stats.hp <= 0) {
+                    win(&mut outcome, &player, &enemies, tables, rng);
+                    return outcome;
+                }
+            }
+        }
+    }
+
+    outcome
+}
+
+// Slot of the living enemy with the least HP left.
+fn weakest(enemies: &[global::Enemy]) -> usize {
+    enemies
+        .iter()
+        .enumerate()
+        .filter(|(_, enemy)| enemy.stats.hp > 0)
+        .min_by_key(|(_, enemy)| enemy.stats.hp)
+        .map_or(0, |(slot, _)| slot)
+}
+
+fn choose_action(
+    policy: &Policy,
+    loadout: &Loadout,
+    player: &global::Player,
+    enemy: &global::Enemy,
+    items: &HashMap<usize, usize>,
+    item_table: &global::ItemTable,
+) -> PlayerAction {
+    if let Some(percent) = policy.potion_below {
+        if player.stats.hp * 100 < player.stats.hp_max * percent as i32 {
+            let potion = items
+                .keys()
+                .filter_map(|id| item_table.get(id))
+                .filter(|item| item.item_type == global::ItemType::Consumable)
+                .filter(|item| item.stats.hp > 0)
+                .max_by_key(|item| item.stats.hp);
+            if let Some(potion) = potion {
+                return PlayerAction::UseItem(potion.clone());
+            }
+        }
+    }
+
+    if policy.limit && player.limit == 100 {
+        return PlayerAction::Attack(loadout.limit.clone());
+    }
+
+    let state = combat::BattleState::new(player, enemy, false);
+    let damage = |attack: &global::PlayerAttack| {
+        combat::player_attack_damage(attack, &state.player, &state.enemy)
+    };
+    if policy.magic && !player.status_effects.has(global::StatusEffectKind::Silence) {
+        let best = loadout
+            .magic
+            .iter()
+            .filter(|attack| attack.mp_use <= player.stats.mp)
+            .max_by_key(|attack| damage(attack));
+        if let Some(magic) = best {
+            if damage(magic) > damage(&loadout.attack) {
+                return PlayerAction::Attack(magic.clone());
+            }
+        }
+    }
+
+    PlayerAction::Attack(loadout.attack.clone())
+}
+
+// Damage the player took from attacks and status effects.
+fn damage_taken(events: &[BattleEvent]) -> i32 {
+    events
+        .iter()
+        .map(|event| match event {
+            BattleEvent::EnemyAttacked { damage, .. } => *damage,
+            BattleEvent::StatusDamage(Target::Player, _, amount) => *amount,
+            _ => 0,
+        })
+        .sum()
+}
+
+// Swaps a defeated enemy for its next phase, if it has one.
+fn next_phase(enemy: &mut global::Enemy, enemy_table: &global::EnemyTable) -> bool {
+    match enemy.enemy_stats.next_phase {
+        Some(next) => {
+            enemy.set_from_table(enemy_table, next);
+            true
+        }
+        None => false,
+    }
+}
+
+fn win(
+    outcome: &mut Outcome,
+    player: &global::Player,
+    enemies: &[global::Enemy],
+    tables: &content::Tables,
+    rng: &mut global::GameRng,
+) {
+    let mut stats = player.stats.clone();
+    let victory = combat::resolve_victory(&mut stats, enemies, &tables.items, &mut rng.loot);
+    outcome.won = true;
+    outcome.experience = enemies.iter().map(|enemy| enemy.stats.experience).sum();
+    outcome.gold = enemies.iter().map(|enemy| enemy.stats.gold).sum();
+    outcome.drops = victory.loot.len() as u32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loadout_has_unlocks_and_equipment() {
+        let tables = content::Tables::for_tests();
+        let options = Options {
+            level: 3,
+            equipment: vec![10], // Steel Sword
+            ..Default::default()
+        };
+        let loadout = Loadout::new(&options, &tables).unwrap();
+        let mut level_3 = global::Stats::new(Default::default());
+        combat::level_up(&mut level_3);
+        combat::level_up(&mut level_3);
+
+        assert_eq!(level_3.strength + 20, loadout.stats.strength);
+        assert_eq!(2, loadout.limit.tier);
+        assert_eq!(12, loadout.magic.len());
+
+        let two_weapons = Options {
+            equipment: vec![10, 13],
+            ..Default::default()
+        };
+        assert!(Loadout::new(&two_weapons, &tables).is_err());
+    }
+
+    #[test]
+    fn reports_are_reproducible() {
+        let tables = content::Tables::for_tests();
+        let options = Options {
+            area: Some(1),
+            battles: 20,
+            policy: Policy {
+                potion_below: Some(30),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let first = report(&options, &tables, 3).unwrap();
+        assert_eq!(first, report(&options, &tables, 3).unwrap());
+        assert!(first.contains("Slime x2"));
+        assert!(!first.contains("Duck"));
+    }
+
+    #[test]
+    fn an_overpowered_player_always_wins() {
+        let mut tables = content::Tables::for_tests();
+        // A paralysed player loses a turn, which would make the turn counts depend on the rolls.
+        for (_, _, attacks, _) in tables.enemies.table.values_mut() {
+            for attack in attacks {
+                attack.effect = None;
+            }
+        }
+        let options = Options {
+            level: 5,
+            ..Default::default()
+        };
+        let mut loadout = Loadout::new(&options, &tables).unwrap();
+        loadout.stats.strength = 10000;
+        loadout.stats.defense = 10000;
+
+        let mut rng = global::GameRng::new(0);
+        let outcome = battle(&loadout, &options.policy, &[0], &tables, &mut rng);
+        assert!(outcome.won);
+        assert_eq!(1, outcome.turns);
+        assert_eq!(0, outcome.damage_taken);
+
+        // One enemy falls per turn, however many there are.
+        let outcome = battle(&loadout, &options.policy, &[0, 0, 1], &tables, &mut rng);
+        assert!(outcome.won);
+        assert_eq!(3, outcome.turns);
+        assert_eq!(0, outcome.damage_taken);
+
+        // Every phase of the final boss has to be beaten.
+        let outcome = battle(
+            &loadout,
+            &options.policy,
+            &[global::FINAL_BOSS_ID],
+            &tables,
+            &mut rng,
+        );
+        assert!(outcome.won);
+        assert_eq!(3, outcome.turns);
+    }
+}
@@ -0,0 +1,296 @@
+// The ground of an area, as a grid of tiles laid over its background. Row 0 is at the top, while
+// the world is measured right and up from the map's bottom left corner. Anything outside the grid
+// counts as solid, so the only ways out of an area are its exits.
+use bevy::prelude::*;
+use serde::Deserialize;
+
+pub(crate) const TILE_SIZE: f32 = 80.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Tile {
+    Walkable, // '.' in a map
+    Solid,    // '#' in a map
+}
+
+// A side of the map.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub(crate) enum Edge {
+    North,
+    South,
+    East,
+    West,
+}
+
+// A rectangle of tiles, counted from the top left of the map.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub(crate) struct TileRect {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl TileRect {
+    pub(crate) fn contains(&self, (x, y): (usize, usize)) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct TileMap {
+    width: usize,
+    height: usize,
+    tiles: Vec<Tile>,
+}
+
+impl TileMap {
+    // Reads a map from rows of tile characters, top row first.
+    pub(crate) fn parse(rows: &[String]) -> Result<Self, String> {
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        if width == 0 {
+            return Err("map is empty".to_string());
+        }
+
+        let mut tiles = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!(
+                    "map row {} is {} tiles wide, the first is {}",
+                    y,
+                    row.chars().count(),
+                    width
+                ));
+            }
+            for c in row.chars() {
+                tiles.push(match c {
+                    '.' => Tile::Walkable,
+                    '#' => Tile::Solid,
+                    _ => return Err(format!("map row {} has unknown tile '{}'", y, c)),
+                });
+            }
+        }
+
+        Ok(TileMap {
+            width,
+            height: rows.len(),
+            tiles,
+        })
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    // The line of tiles along one side of the map.
+    pub(crate) fn edge(&self, edge: Edge) -> TileRect {
+        let (width, height) = (self.width, self.height);
+        match edge {
+            Edge::North => TileRect {
+                x: 0,
+                y: 0,
+                width,
+                height: 1,
+            },
+            Edge::South => TileRect {
+                x: 0,
+                y: height.saturating_sub(1),
+                width,
+                height: 1,
+            },
+            Edge::East => TileRect {
+                x: width.saturating_sub(1),
+                y: 0,
+                width: 1,
+                height,
+            },
+            Edge::West => TileRect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height,
+            },
+        }
+    }
+
+    // Size of the map in the world.
+    pub(crate) fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE
+    }
+
+    pub(crate) fn tile(&self, x: i32, y: i32) -> Tile {
+        if (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) {
+            self.tiles[y as usize * self.width + x as usize]
+        } else {
+            Tile::Solid
+        }
+    }
+
+    // The tile a point in the world is on, if it's on the map at all.
+    pub(crate) fn tile_at(&self, position: Vec2) -> Option<(usize, usize)> {
+        let grid = (self.to_grid(position) / TILE_SIZE).floor();
+        let on_map = (0. ..self.width as f32).contains(&grid.x)
+            && (0. ..self.height as f32).contains(&grid.y);
+        on_map.then(|| (grid.x as usize, grid.y as usize))
+    }
+
+    // Where the middle of a tile is in the world.
+    pub(crate) fn tile_center(&self, (x, y): (usize, usize)) -> Vec2 {
+        let grid = (Vec2::new(x as f32, y as f32) + 0.5) * TILE_SIZE;
+        self.to_world(grid)
+    }
+
+    // Where to centre a view of the given size so it shows as much of the map around `focus` as it
+    // can without going past the edges. A map smaller than the view is centred in it instead.
+    pub(crate) fn clamp_view(&self, focus: Vec2, view: Vec2) -> Vec2 {
+        let size = self.size();
+        let clamp = |focus: f32, view: f32, size: f32| {
+            if size <= view {
+                size / 2.
+            } else {
+                focus.clamp(view / 2., size - view / 2.)
+            }
+        };
+        Vec2::new(
+            clamp(focus.x, view.x, size.x),
+            clamp(focus.y, view.y, size.y),
+        )
+    }
+
+    // Moves a box of the given size centred on `position` by `delta`, first sideways and then up or
+    // down, stopping it flush against the first solid tile in its way. Returns where it ends up.
+    pub(crate) fn move_box(&self, position: Vec2, size: Vec2, delta: Vec2) -> Vec2 {
+        let half = size / 2.;
+        let mut grid = self.to_grid(position);
+        let grid_delta = Vec2::new(delta.x, -delta.y);
+        for axis in 0..2 {
+            grid[axis] = self.sweep(grid, half, grid_delta[axis], axis);
+        }
+        self.to_world(grid)
+    }
+
+    // Moves a box along one axis of the grid, returning its new centre on that axis.
+    fn sweep(&self, center: Vec2, half: Vec2, distance: f32, axis: usize) -> f32 {
+        let across = 1 - axis;
+        // Lines of tiles the box covers, across the way it's moving.
+        let first = ((center[across] - half[across]) / TILE_SIZE).floor() as i32;
+        let last = ((center[across] + half[across]) / TILE_SIZE).ceil() as i32 - 1;
+        let blocked = |line: i32| {
+            (first..=last).any(|i| {
+                let (x, y) = if axis == 0 { (line, i) } else { (i, line) };
+                self.tile(x, y) == Tile::Solid
+            })
+        };
+
+        if distance > 0. {
+            let edge = center[axis] + half[axis];
+            let from = (edge / TILE_SIZE).ceil() as i32;
+            let to = ((edge + distance) / TILE_SIZE).ceil() as i32 - 1;
+            if let Some(line) = (from..=to).find(|line| blocked(*line)) {
+                return line as f32 * TILE_SIZE - half[axis];
+            }
+        } else if distance < 0. {
+            let edge = center[axis] - half[axis];
+            let from = (edge / TILE_SIZE).floor() as i32 - 1;
+            let to = ((edge + distance) / TILE_SIZE).floor() as i32;
+            if let Some(line) = (to..=from).rev().find(|line| blocked(*line)) {
+                return (line + 1) as f32 * TILE_SIZE + half[axis];
+            }
+        }
+        center[axis] + distance
+    }
+
+    // The grid runs down from the map's top left corner, the world up from its bottom left.
+    fn to_grid(&self, position: Vec2) -> Vec2 {
+        Vec2::new(position.x, self.size().y - position.y)
+    }
+
+    fn to_world(&self, grid: Vec2) -> Vec2 {
+        Vec2::new(grid.x, self.size().y - grid.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: Vec2 = bevy::math::const_vec2!([64., 64.]);
+
+    // A 4x3 room with a pillar in the middle of the bottom row:
+    //   ....
+    //   ....
+    //   .#..
+    fn room() -> TileMap {
+        let rows = ["....", "....", ".#.."].map(String::from);
+        TileMap::parse(&rows).unwrap()
+    }
+
+    #[test]
+    fn solid_tiles_stop_movement_flush() {
+        let map = room();
+        // Standing above the pillar and walking down into it.
+        let start = map.tile_center((1, 1));
+        let end = map.move_box(start, PLAYER, Vec2::new(0., -100.));
+        assert_eq!(start.x, end.x);
+        assert_eq!(
+            map.tile_center((1, 2)).y + TILE_SIZE / 2. + PLAYER.y / 2.,
+            end.y
+        );
+
+        // Walking past it on the row above is fine.
+        let end = map.move_box(end, PLAYER, Vec2::new(100., 0.));
+        assert_eq!(start.x + 100., end.x);
+    }
+
+    #[test]
+    fn nothing_walks_off_the_map() {
+        let map = room();
+        let start = map.tile_center((0, 0));
+        let size = map.size();
+
+        let end = map.move_box(start, PLAYER, Vec2::new(-500., 0.));
+        assert_eq!(PLAYER.x / 2., end.x);
+        let end = map.move_box(start, PLAYER, Vec2::new(0., 500.));
+        assert_eq!(size.y - PLAYER.y / 2., end.y);
+        assert_eq!(None, map.tile_at(Vec2::new(-1., 0.)));
+        assert_eq!(None, map.tile_at(Vec2::new(0., size.y + 1.)));
+    }
+
+    #[test]
+    fn views_stay_on_the_map() {
+        let map = room(); // 320x240
+        let view = Vec2::new(160., 160.);
+        assert_eq!(Vec2::new(80., 80.), map.clamp_view(Vec2::ZERO, view));
+        assert_eq!(
+            Vec2::new(240., 160.),
+            map.clamp_view(Vec2::new(300., 230.), view)
+        );
+        assert_eq!(
+            Vec2::new(200., 100.),
+            map.clamp_view(Vec2::new(200., 100.), view)
+        );
+        // Too small to scroll, so it sits in the middle.
+        assert_eq!(
+            Vec2::new(160., 120.),
+            map.clamp_view(Vec2::ZERO, Vec2::new(400., 400.))
+        );
+    }
+
+    #[test]
+    fn maps_must_be_rectangular_and_known() {
+        let ragged = ["....", "..."].map(String::from);
+        assert!(TileMap::parse(&ragged).is_err());
+        let unknown = ["..~."].map(String::from);
+        assert_eq!(
+            Err("map row 0 has unknown tile '~'".to_string()),
+            TileMap::parse(&unknown)
+        );
+
+        let map = room();
+        assert_eq!(Tile::Solid, map.tile(1, 2));
+        assert_eq!(Some((1, 2)), map.tile_at(map.tile_center((1, 2))));
+    }
+}
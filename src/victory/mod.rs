@@ -0,0 +1,82 @@
+use crate::{button_system, despawn_screen, global, replay::RecordClicks, FontAssets, ImageAssets};
+
+use bevy::prelude::*;
+
+mod styles;
+use styles::*;
+pub struct VictoryPlugin;
+
+impl Plugin for VictoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.record_clicks::<MenuButtonAction>()
+            .add_system_set(
+                SystemSet::on_enter(global::GameState::FinalVictory).with_system(victory_setup),
+            )
+            .add_system_set(
+                SystemSet::on_update(global::GameState::FinalVictory)
+                    .with_system(menu_action)
+                    .with_system(button_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(global::GameState::FinalVictory)
+                    .with_system(despawn_screen::<VictoryScreen>),
+            );
+    }
+}
+
+#[derive(Component, Debug)]
+enum MenuButtonAction {
+    BackToMainMenu,
+}
+
+#[derive(Component)]
+struct VictoryScreen;
+
+fn victory_setup(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    font_assets: Res<FontAssets>,
+    player: Res<global::Player>,
+    run_stats: Res<global::RunStats>,
+) {
+    let summary = format!(
+        "The Emperor Penguin has fallen!\n\nLevel: {}\nGold: {}\nBattles won: {}\nPlay time: {}",
+        player.stats.level,
+        player.stats.gold,
+        run_stats.battles_won,
+        global::format_play_time(run_stats.play_time)
+    );
+
+    commands
+        .spawn_bundle(styled_victory_screen(&image_assets))
+        .insert(VictoryScreen)
+        .with_children(|p| {
+            p.spawn_bundle(styled_victory_header(&font_assets));
+
+            p.spawn_bundle(styled_summary_text(&font_assets, summary));
+
+            p.spawn_bundle(styled_button())
+                .insert(MenuButtonAction::BackToMainMenu)
+                .with_children(|p| {
+                    p.spawn_bundle(styled_button_text(&font_assets));
+                });
+        });
+}
+
+fn menu_action(
+    interaction_query: Query<
+        (&Interaction, &MenuButtonAction),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut game_state: ResMut<State<global::GameState>>,
+) {
+    for (interaction, menu_button_action) in interaction_query.iter() {
+        if *interaction == Interaction::Clicked {
+            match menu_button_action {
+                MenuButtonAction::BackToMainMenu => {
+                    game_state.set(global::GameState::MainMenu).unwrap();
+                }
+            }
+        }
+    }
+}